@@ -0,0 +1,36 @@
+//! Build script. Records the embedded database migrations so the server can check whether all of
+//! them have been applied and revert them.
+
+use std::{env, fs, path::Path};
+
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+
+    let mut migrations: Vec<(String, String)> = fs::read_dir("migrations")
+        .expect("failed to read migrations directory")
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .map(|name| {
+            // Versions are formatted like diesel does it: the directory name up to the first
+            // underscore, with dashes removed.
+            let version = name.split('_').next().unwrap_or_default().replace('-', "");
+            (version, name)
+        })
+        .collect();
+    migrations.sort();
+
+    let dir = env::current_dir().expect("failed to get current directory");
+    let mut out = String::from("pub const MIGRATIONS: &[Migration] = &[\n");
+    for (version, name) in migrations {
+        let down = dir.join("migrations").join(&name).join("down.sql");
+        out.push_str(&format!(
+            "    Migration {{ version: {:?}, name: {:?}, down: include_str!({:?}) }},\n",
+            version, name, down
+        ));
+    }
+    out.push_str("];\n");
+
+    let path = Path::new(&env::var("OUT_DIR").unwrap()).join("migrations.rs");
+    fs::write(path, out).expect("failed to write migration list");
+}
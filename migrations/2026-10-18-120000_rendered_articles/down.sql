@@ -0,0 +1 @@
+DROP TABLE rendered_articles;
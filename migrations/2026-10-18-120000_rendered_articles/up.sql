@@ -0,0 +1,5 @@
+-- Cached renderings get their own table, so storing them never counts as editing an article
+CREATE TABLE rendered_articles (
+	article INTEGER PRIMARY KEY REFERENCES articles(id) ON DELETE CASCADE NOT NULL,
+	html TEXT NOT NULL
+);
@@ -0,0 +1,2 @@
+DROP TRIGGER set_updated_at ON articles;
+ALTER TABLE articles DROP COLUMN updated_at;
@@ -0,0 +1,3 @@
+ALTER TABLE articles ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP;
+UPDATE articles SET updated_at = date;
+SELECT diesel_manage_updated_at('articles');
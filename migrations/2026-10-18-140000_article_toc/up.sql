@@ -0,0 +1 @@
+ALTER TABLE articles ADD COLUMN toc BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+ALTER TABLE articles DROP COLUMN summary;
+ALTER TABLE articles DROP COLUMN tags;
@@ -0,0 +1,24 @@
+CREATE TABLE pages (
+	id SERIAL PRIMARY KEY,
+	slug VARCHAR NOT NULL UNIQUE,
+	title VARCHAR NOT NULL,
+	content TEXT NOT NULL,
+	nav_order INTEGER,
+	visible BOOLEAN NOT NULL DEFAULT false,
+	updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+SELECT diesel_manage_updated_at('pages');
+
+-- The about page used to be built in
+INSERT INTO pages (slug, title, content, nav_order, visible) VALUES ('about', 'About', $$Hi. My name is Amanda and this is my website.
+
+## Licenses
+
+The source code for this site is released under the [GNU Affero General Public License](https://www.gnu.org/licenses/agpl-3.0.html).
+
+The font "Roboto" is licensed under the [Apache License 2.0](https://www.apache.org/licenses/LICENSE-2.0).
+
+The fonts "Merriweather", "Montserrat" and "Oxygen Mono" are licensed under the [Open Font License](https://scripts.sil.org/cms/scripts/page.php?site_id=nrsi&id=OFL).
+
+Other files are licensed under the [Creative Commons Attribution-ShareAlike 4.0 International License](http://creativecommons.org/licenses/by-sa/4.0/) unless otherwise is stated.
+$$, 0, true);
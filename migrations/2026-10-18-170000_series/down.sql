@@ -0,0 +1,3 @@
+ALTER TABLE articles DROP COLUMN series_part;
+ALTER TABLE articles DROP COLUMN series;
+DROP TABLE series;
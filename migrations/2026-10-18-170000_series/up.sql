@@ -0,0 +1,8 @@
+CREATE TABLE series (
+	id SERIAL PRIMARY KEY,
+	title VARCHAR NOT NULL UNIQUE,
+	url VARCHAR NOT NULL UNIQUE,
+	description TEXT NOT NULL DEFAULT ''
+);
+ALTER TABLE articles ADD COLUMN series INTEGER REFERENCES series(id) ON DELETE SET NULL;
+ALTER TABLE articles ADD COLUMN series_part INTEGER;
@@ -0,0 +1,7 @@
+-- Co-authors of articles in addition to articles.author, in the order they're credited
+CREATE TABLE article_authors (
+	article INTEGER REFERENCES articles(id) ON DELETE CASCADE NOT NULL,
+	author VARCHAR REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+	position INTEGER NOT NULL,
+	PRIMARY KEY (article, author)
+);
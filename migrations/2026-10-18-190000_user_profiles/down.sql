@@ -0,0 +1,4 @@
+ALTER TABLE users DROP COLUMN avatar;
+ALTER TABLE users DROP COLUMN links;
+ALTER TABLE users DROP COLUMN website;
+ALTER TABLE users DROP COLUMN bio;
@@ -0,0 +1,4 @@
+ALTER TABLE users ADD COLUMN bio TEXT NOT NULL DEFAULT '';
+ALTER TABLE users ADD COLUMN website VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE users ADD COLUMN links TEXT[] NOT NULL DEFAULT '{}';
+ALTER TABLE users ADD COLUMN avatar VARCHAR;
@@ -1,14 +1,19 @@
 use chrono::naive::NaiveDateTime;
-use comrak::markdown_to_html_with_plugins;
 use diesel::{
     pg::PgConnection as Connection, prelude::*, result::Error as DieselError, Queryable,
     RunQueryDsl,
 };
 
-use crate::schema::articles;
+use crate::schema::{article_authors, articles, rendered_articles, users};
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use std::collections::{BTreeSet, HashMap};
 
 use crate::{
-    config::{self, COMRAK_ARTICLE_OPTS},
+    front_matter,
+    render::Renderer,
+    sanitize,
     user::{Permission, Session, User},
 };
 
@@ -23,6 +28,9 @@ const ILLEGAL_URL_CHARS: &[char] = &[
     '}',
 ];
 
+/// Characters that are escaped when a tag or article url is put in a path.
+pub(crate) const PATH_ESCAPED: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.');
+
 #[derive(Debug, Deserialize, Serialize, Queryable, Identifiable)]
 pub struct Article {
     /// The article's numeric id
@@ -40,6 +48,19 @@ pub struct Article {
     pub date: NaiveDateTime,
     /// Whether the article has been published
     pub visible: bool,
+    /// The time of the last change
+    #[serde(with = "crate::date_format")]
+    pub updated_at: NaiveDateTime,
+    /// Whether to show a table of contents
+    pub toc: bool,
+    /// The article's tags
+    pub tags: Vec<String>,
+    /// A short summary of the article, used instead of its beginning in previews
+    pub summary: Option<String>,
+    /// The id of the series the article is part of
+    pub series: Option<i32>,
+    /// The article's position in its series
+    pub series_part: Option<i32>,
 }
 
 impl Article {
@@ -58,8 +79,8 @@ impl Article {
     }
 
     /// Checks if the given session has permission to edit this article. Permission is granted if
-    /// the user has the EditForeignArticle permission, or owns this article and has the
-    /// EditArticle permission.
+    /// the user has the EditForeignArticle permission, or is one of this article's authors and
+    /// has the EditArticle permission.
     pub fn editable(
         &self,
         session: Option<&Session>,
@@ -67,7 +88,8 @@ impl Article {
     ) -> Result<bool, DieselError> {
         if let Some(session) = session {
             Ok(session.allowed(Permission::EditForeignArticle, conn)?
-                || session.allowed(Permission::EditArticle, conn)? && self.author == session.user)
+                || session.allowed(Permission::EditArticle, conn)?
+                    && is_author(conn, self.id, &session.user)?)
         } else {
             Ok(false)
         }
@@ -80,25 +102,47 @@ impl Article {
             .first(connection)
     }
 
+    /// Get the users credited for this article: the author who submitted it followed by the
+    /// co-authors in order.
+    pub fn authors(&self, connection: &Connection) -> Result<Vec<User>, DieselError> {
+        Ok(authors_of(connection, std::slice::from_ref(self))?
+            .pop()
+            .unwrap_or_default())
+    }
+
     /// Return the marked up version of the article's body.
-    pub fn formatted(&self) -> String {
-        let adapter = config::comrak_syntax_adapter();
-        let plugins = config::comrak_plugins(&adapter);
-        markdown_to_html_with_plugins(&self.content, &COMRAK_ARTICLE_OPTS, &plugins)
+    pub fn formatted(&self, renderer: &Renderer, connection: &Connection) -> String {
+        renderer.article(self, connection)
+    }
+
+    /// Return the table of contents of the article's body.
+    pub fn contents(&self, renderer: &Renderer) -> String {
+        renderer.toc(self)
     }
 
-    /// Get a short slice of the article's contents.
-    pub fn description(&self) -> &str {
-        let mut end = DESCRIPTION_LEN;
+    /// Get the article's summary, or a short slice of its contents if it doesn't have one.
+    pub fn description(&self) -> String {
+        if let Some(summary) = &self.summary {
+            return summary.clone();
+        }
+        let mut end = DESCRIPTION_LEN.min(self.content.len());
         while !self.content.is_char_boundary(end) {
             end -= 1;
         }
-        &self.content[..end]
+        match end < self.content.len() {
+            true => format!("{}…", &self.content[..end]),
+            false => self.content.clone(),
+        }
+    }
+
+    /// Export the article as markdown with front matter.
+    pub fn markdown(&self) -> String {
+        front_matter::export(self)
     }
 
     /// Used when displaying a preview of the article's contents in a list of articles.
-    pub fn preview(&self) -> String {
-        let mut rendered = self.formatted();
+    pub fn preview(&self, renderer: &Renderer, connection: &Connection) -> String {
+        let mut rendered = self.formatted(renderer, connection);
 
         let len = rendered.len();
         if len < PREVIEW_LEN {
@@ -125,25 +169,140 @@ impl Article {
     }
 }
 
+/// Makes the url of an article from its title.
+pub(crate) fn slug(title: &str) -> String {
+    comrak::Anchorizer::new().anchorize(title.to_owned())
+}
+
 #[derive(Insertable, Deserialize, Serialize)]
 #[table_name = "articles"]
 pub struct NewArticle {
+    /// May be left empty if set in front matter
+    #[serde(default)]
     pub title: String,
+    /// Made from the title if left empty
+    #[serde(default)]
     pub url: String,
     pub content: String,
+    /// Replaced by the submitter unless they may post for other users
+    #[serde(default)]
     pub author: String,
     #[serde(default)]
     pub visible: bool,
+    #[serde(default)]
+    pub toc: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub summary: Option<String>,
+    /// Only set from front matter, the time of submission otherwise
+    #[serde(skip)]
+    pub date: Option<NaiveDateTime>,
+}
+
+impl NewArticle {
+    /// Credits the article to its submitter, unless `foreign` allows them to name another author.
+    /// The author decides which sanitization policy the article gets, so it can't be left to the
+    /// submitter otherwise.
+    pub fn credit(&mut self, submitter: &str, foreign: bool) {
+        if !foreign || self.author.is_empty() {
+            self.author = submitter.to_owned();
+        }
+    }
+
+    /// Moves front matter at the start of the content into the article's fields.
+    pub fn read_front_matter(&mut self) -> Result<(), failure::Error> {
+        let (front_matter, content) = front_matter::split(&self.content)?;
+        if let Some(front_matter) = front_matter {
+            let content = content.to_owned();
+            self.title = front_matter
+                .title
+                .clone()
+                .unwrap_or_else(|| self.title.clone());
+            self.url = front_matter.url.clone().unwrap_or_else(|| self.url.clone());
+            self.date = front_matter.date()?.or(self.date);
+            self.tags = front_matter.tags().unwrap_or_else(|| self.tags.clone());
+            self.visible = front_matter.visible().unwrap_or(self.visible);
+            self.toc = front_matter.toc.unwrap_or(self.toc);
+            if let Some(summary) = front_matter.summary() {
+                self.summary = summary;
+            }
+            self.content = content;
+        }
+        if self.url.is_empty() {
+            self.url = slug(&self.title);
+        }
+        Ok(())
+    }
 }
 
 #[derive(AsChangeset, Deserialize, Serialize)]
 #[table_name = "articles"]
 pub struct ArticleChanges {
+    #[serde(default)]
     pub title: String,
+    #[serde(default)]
     pub url: String,
     pub content: String,
     #[serde(default)]
     pub visible: bool,
+    #[serde(default)]
+    pub toc: bool,
+    /// Left unchanged if unset
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+    /// Left unchanged if unset
+    #[serde(default)]
+    pub summary: Option<Option<String>>,
+    /// Only set from front matter
+    #[serde(skip)]
+    pub date: Option<NaiveDateTime>,
+}
+
+impl ArticleChanges {
+    /// Moves front matter at the start of the content into the changes.
+    pub fn read_front_matter(&mut self) -> Result<(), failure::Error> {
+        let (front_matter, content) = front_matter::split(&self.content)?;
+        if let Some(front_matter) = front_matter {
+            let content = content.to_owned();
+            self.title = front_matter
+                .title
+                .clone()
+                .unwrap_or_else(|| self.title.clone());
+            self.url = front_matter.url.clone().unwrap_or_else(|| self.url.clone());
+            self.date = front_matter.date()?.or(self.date);
+            self.tags = front_matter.tags().or_else(|| self.tags.take());
+            self.visible = front_matter.visible().unwrap_or(self.visible);
+            self.toc = front_matter.toc.unwrap_or(self.toc);
+            if let Some(summary) = front_matter.summary() {
+                self.summary = Some(summary);
+            }
+            self.content = content;
+        }
+        if self.url.is_empty() {
+            self.url = slug(&self.title);
+        }
+        Ok(())
+    }
+}
+
+/// Checks that an article has a title and a usable url.
+fn check(title: &str, url: &str) -> Result<(), failure::Error> {
+    if title.trim().is_empty() {
+        return Err(failure::err_msg("Article has no title"));
+    }
+    if url.is_empty() {
+        return Err(failure::err_msg("Article has no url"));
+    }
+    if !valid_url(url) {
+        return Err(failure::err_msg("Illegal character in article url"));
+    }
+    Ok(())
+}
+
+/// Checks that a url has no characters that aren't allowed in urls.
+pub(crate) fn valid_url(url: &str) -> bool {
+    !url.contains(|c| ILLEGAL_URL_CHARS.contains(&c))
 }
 
 pub fn id_from_url(connection: &Connection, url: &str) -> Result<i32, DieselError> {
@@ -170,6 +329,67 @@ pub fn page(connection: &Connection, page: i64) -> Result<Vec<Article>, DieselEr
         .load::<Article>(connection)
 }
 
+/// Gets the published articles with a tag
+pub fn by_tag(connection: &Connection, tag: &str) -> Result<Vec<Article>, DieselError> {
+    use crate::schema::articles::dsl::*;
+
+    articles
+        .filter(visible.eq(true))
+        .filter(tags.contains(vec![tag]))
+        .order(date.desc())
+        .load::<Article>(connection)
+}
+
+/// Builds a query for the published articles a user wrote or co-wrote.
+fn authored_by(user: &str) -> articles::BoxedQuery<'_, diesel::pg::Pg> {
+    let coauthored = article_authors::table
+        .filter(article_authors::author.eq(user))
+        .select(article_authors::article);
+    articles::table
+        .filter(articles::visible.eq(true))
+        .filter(
+            articles::author
+                .eq(user)
+                .or(articles::id.eq_any(coauthored)),
+        )
+        .into_boxed()
+}
+
+/// Gets one page of the published articles a user wrote or co-wrote. Zero is not a valid index.
+pub fn by_author(
+    connection: &Connection,
+    user: &str,
+    page: i64,
+) -> Result<Vec<Article>, DieselError> {
+    authored_by(user)
+        .order(articles::date.desc())
+        .limit(PAGE_SIZE)
+        .offset((page - 1) * PAGE_SIZE)
+        .load::<Article>(connection)
+}
+
+/// Counts the published articles a user wrote or co-wrote
+pub fn count_by_author(connection: &Connection, user: &str) -> Result<i64, DieselError> {
+    authored_by(user).count().get_result(connection)
+}
+
+/// Gets the tags of published articles in alphabetical order
+pub fn tags(connection: &Connection) -> Result<Vec<String>, DieselError> {
+    use crate::schema::articles::dsl;
+
+    let lists: Vec<Vec<String>> = dsl::articles
+        .filter(dsl::visible.eq(true))
+        .select(dsl::tags)
+        .load(connection)?;
+    let tags: BTreeSet<String> = lists.into_iter().flatten().collect();
+    Ok(tags.into_iter().collect())
+}
+
+/// Gets the path of the page listing the articles with a tag
+pub fn tag_path(tag: &str) -> String {
+    format!("/tag/{}", utf8_percent_encode(tag, PATH_ESCAPED))
+}
+
 /// Gets a single article with a specific id
 pub fn view(connection: &Connection, name: &str) -> Result<Article, DieselError> {
     use crate::schema::articles::dsl::*;
@@ -180,28 +400,114 @@ pub fn view(connection: &Connection, name: &str) -> Result<Article, DieselError>
     }
 }
 
-/// Submits a new article
-pub fn submit(connection: &Connection, article: &NewArticle) -> Result<usize, failure::Error> {
-    if article.url.contains(|c| ILLEGAL_URL_CHARS.contains(&c)) {
-        return Err(failure::err_msg("Illegal character in article url"));
-    }
+/// Submits a new article, returning its id
+pub fn submit(connection: &Connection, article: &NewArticle) -> Result<i32, failure::Error> {
+    check(&article.title, &article.url)?;
     Ok(diesel::insert_into(articles::table)
         .values(article)
+        .returning(articles::id)
+        .get_result(connection)?)
+}
+
+/// Submits an article with a given id, used when restoring backups
+pub fn restore(
+    connection: &Connection,
+    id: i32,
+    article: &NewArticle,
+) -> Result<usize, failure::Error> {
+    check(&article.title, &article.url)?;
+    Ok(diesel::insert_into(articles::table)
+        .values((article, articles::id.eq(id)))
         .execute(connection)?)
 }
 
+/// Sets when an article was last edited, used when restoring backups
+pub fn set_updated_at(
+    connection: &Connection,
+    id: i32,
+    updated_at: NaiveDateTime,
+) -> Result<usize, DieselError> {
+    diesel::update(articles::table.find(id))
+        .set(articles::updated_at.eq(updated_at))
+        .execute(connection)
+}
+
+/// Counts all articles
+pub fn count(connection: &Connection) -> Result<i64, DieselError> {
+    articles::table.count().first(connection)
+}
+
+/// Edits an article, clearing its cached rendering
 pub fn edit(
     connection: &Connection,
     id: i32,
     changes: &ArticleChanges,
-) -> Result<usize, DieselError> {
+) -> Result<usize, failure::Error> {
     use crate::schema::articles::dsl;
 
-    diesel::update(dsl::articles.find(id))
+    use crate::schema::rendered_articles::dsl as r;
+
+    check(&changes.title, &changes.url)?;
+    diesel::delete(r::rendered_articles.find(id)).execute(connection)?;
+    Ok(diesel::update(dsl::articles.find(id))
         .set(changes)
+        .execute(connection)?)
+}
+
+/// Gets the cached rendering of an article
+pub fn rendered(connection: &Connection, id: i32) -> Result<Option<String>, DieselError> {
+    use crate::schema::rendered_articles::dsl;
+
+    dsl::rendered_articles
+        .select(dsl::html)
+        .find(id)
+        .first(connection)
+        .optional()
+}
+
+/// Stores the cached rendering of an article
+pub fn set_rendered(connection: &Connection, id: i32, html: &str) -> Result<usize, DieselError> {
+    use crate::schema::rendered_articles::dsl;
+
+    diesel::insert_into(rendered_articles::table)
+        .values((dsl::article.eq(id), dsl::html.eq(html)))
+        .on_conflict(dsl::article)
+        .do_update()
+        .set(dsl::html.eq(html))
         .execute(connection)
 }
 
+/// Checks whether an article has a cached rendering
+pub fn is_rendered(connection: &Connection, id: i32) -> Result<bool, DieselError> {
+    use crate::schema::rendered_articles::dsl;
+
+    diesel::select(diesel::dsl::exists(dsl::rendered_articles.find(id))).get_result(connection)
+}
+
+/// Clears the cached renderings of the articles a user wrote or co-authored, e.g. after their
+/// group and with it their sanitization policy changed.
+pub fn clear_rendered_by(connection: &Connection, user: &str) -> Result<usize, DieselError> {
+    use crate::schema::{article_authors::dsl as aa, articles::dsl as a, rendered_articles::dsl};
+
+    let written = a::articles.select(a::id).filter(a::author.eq(user));
+    let coauthored = aa::article_authors
+        .select(aa::article)
+        .filter(aa::author.eq(user));
+    diesel::delete(
+        dsl::rendered_articles.filter(
+            dsl::article
+                .eq_any(written)
+                .or(dsl::article.eq_any(coauthored)),
+        ),
+    )
+    .execute(connection)
+}
+
+/// Clears the cached rendering of every article
+pub fn clear_rendered(connection: &Connection) -> Result<usize, DieselError> {
+    diesel::delete(rendered_articles::table).execute(connection)
+}
+
 pub fn delete(connection: &Connection, name: i32) -> Result<usize, DieselError> {
     use crate::schema::articles::dsl::*;
 
@@ -220,3 +526,219 @@ pub fn author(connection: &Connection, id: i32) -> Result<String, DieselError> {
     use crate::schema::articles::dsl;
     dsl::articles.select(dsl::author).find(id).first(connection)
 }
+
+/// Gets the co-authors of an article in order
+pub fn coauthors(connection: &Connection, id: i32) -> Result<Vec<String>, DieselError> {
+    article_authors::table
+        .filter(article_authors::article.eq(id))
+        .order(article_authors::position.asc())
+        .select(article_authors::author)
+        .load(connection)
+}
+
+/// Checks if a user is the author or a co-author of an article
+pub fn is_author(connection: &Connection, id: i32, user: &str) -> Result<bool, DieselError> {
+    if author(connection, id)? == user {
+        return Ok(true);
+    }
+    let count: i64 = article_authors::table
+        .filter(article_authors::article.eq(id))
+        .filter(article_authors::author.eq(user))
+        .count()
+        .get_result(connection)?;
+    Ok(count > 0)
+}
+
+/// Replaces the co-authors of an article, clearing its cached rendering as their policies apply to
+/// it. The article's author and repeated users are left out.
+pub fn set_coauthors(
+    connection: &Connection,
+    id: i32,
+    coauthors: &[String],
+) -> Result<(), failure::Error> {
+    use crate::schema::rendered_articles::dsl as r;
+
+    let author = author(connection, id)?;
+    let mut rows = Vec::new();
+    for coauthor in coauthors {
+        if *coauthor == author || rows.iter().any(|(_, user, _)| user == coauthor) {
+            continue;
+        }
+        if crate::user::get(connection, coauthor).optional()?.is_none() {
+            return Err(failure::format_err!("There's no user '{}'", coauthor));
+        }
+        rows.push((id, coauthor.clone(), rows.len() as i32));
+    }
+    diesel::delete(r::rendered_articles.find(id)).execute(connection)?;
+    diesel::delete(article_authors::table.filter(article_authors::article.eq(id)))
+        .execute(connection)?;
+    let rows: Vec<_> = rows
+        .into_iter()
+        .map(|(article, user, position)| {
+            (
+                article_authors::article.eq(article),
+                article_authors::author.eq(user),
+                article_authors::position.eq(position),
+            )
+        })
+        .collect();
+    diesel::insert_into(article_authors::table)
+        .values(&rows)
+        .execute(connection)?;
+    Ok(())
+}
+
+/// Gets the users credited for each of `articles`, like `Article::authors` does for one.
+pub fn authors_of(
+    connection: &Connection,
+    articles: &[Article],
+) -> Result<Vec<Vec<User>>, DieselError> {
+    let ids: Vec<i32> = articles.iter().map(|a| a.id).collect();
+    let coauthors: Vec<(i32, String)> = article_authors::table
+        .filter(article_authors::article.eq_any(&ids))
+        .order(article_authors::position.asc())
+        .select((article_authors::article, article_authors::author))
+        .load(connection)?;
+    let names: Vec<&str> = articles
+        .iter()
+        .map(|a| a.author.as_str())
+        .chain(coauthors.iter().map(|(_, name)| name.as_str()))
+        .collect();
+    let users: HashMap<String, User> = users::table
+        .filter(users::id.eq_any(&names))
+        .load::<User>(connection)?
+        .into_iter()
+        .map(|user| (user.id.clone(), user))
+        .collect();
+    Ok(articles
+        .iter()
+        .map(|article| {
+            let coauthors = coauthors.iter().filter(|(id, _)| *id == article.id);
+            std::iter::once(&article.author)
+                .chain(coauthors.map(|(_, name)| name))
+                .filter_map(|name| users.get(name).cloned())
+                .collect()
+        })
+        .collect())
+}
+
+/// Splits a comma separated list of usernames, as entered in the edit form.
+pub fn parse_coauthors(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|user| !user.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Joins the names of an article's authors for bylines, like "A, B and C".
+pub fn byline<S: AsRef<str>>(names: &[S]) -> String {
+    match names {
+        [] => String::new(),
+        [name] => name.as_ref().to_owned(),
+        [rest @ .., last] => {
+            let rest: Vec<&str> = rest.iter().map(AsRef::as_ref).collect();
+            format!("{} and {}", rest.join(", "), last.as_ref())
+        }
+    }
+}
+
+/// Makes the byline shown above articles, linking to the profiles of `authors`. Empty if there
+/// are none.
+pub fn byline_html(authors: &[User]) -> String {
+    if authors.is_empty() {
+        return String::new();
+    }
+    let links: Vec<String> = authors
+        .iter()
+        .map(|author| {
+            format!(
+                "<a href=\"/user/{}\">{}</a>",
+                sanitize::escape(&author.id),
+                sanitize::escape(&author.name)
+            )
+        })
+        .collect();
+    format!("<p class=\"byline\">By {}</p>", byline(&links))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::{byline, parse_coauthors, Article, NewArticle, DESCRIPTION_LEN};
+    use crate::{
+        config::{Sanitize, UNRESTRICTED_POLICY},
+        sanitize,
+    };
+
+    #[test]
+    fn bylines() {
+        assert_eq!(byline::<&str>(&[]), "");
+        assert_eq!(byline(&["Amanda"]), "Amanda");
+        assert_eq!(byline(&["Amanda", "Bea"]), "Amanda and Bea");
+        assert_eq!(byline(&["Amanda", "Bea", "Cleo"]), "Amanda, Bea and Cleo");
+    }
+
+    #[test]
+    fn descriptions() {
+        let date = NaiveDate::from_ymd_opt(2024, 3, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let mut article = Article {
+            id: 1,
+            title: String::from("Short"),
+            author: String::from("root"),
+            url: String::from("short"),
+            content: String::from("Short enough"),
+            date,
+            visible: true,
+            updated_at: date,
+            toc: false,
+            tags: Vec::new(),
+            summary: None,
+            series: None,
+            series_part: None,
+        };
+        assert_eq!(article.description(), "Short enough");
+        article.content = "é".repeat(DESCRIPTION_LEN);
+        let description = article.description();
+        assert!(description.ends_with('…'));
+        assert!(description.len() <= DESCRIPTION_LEN + '…'.len_utf8());
+    }
+
+    #[test]
+    fn coauthor_list() {
+        assert_eq!(parse_coauthors(" bea, ,cleo "), vec!["bea", "cleo"]);
+        assert!(parse_coauthors("").is_empty());
+    }
+
+    #[test]
+    fn spoofed_author() {
+        let mut sanitize = Sanitize::default();
+        sanitize
+            .groups
+            .insert(String::from("admin"), String::from(UNRESTRICTED_POLICY));
+        let group = |user: &str| match user {
+            "root" => "admin",
+            _ => "guest",
+        };
+        let post = "title=Hi&content=%3Cscript%3Ealert(1)%3C%2Fscript%3E&author=root";
+
+        let mut article: NewArticle = serde_urlencoded::from_str(post).unwrap();
+        article.credit("guest", false);
+        assert_eq!(article.author, "guest");
+        let policy = sanitize.for_group(Some(group(&article.author)));
+        assert!(!sanitize::clean(&article.content, policy).contains("<script"));
+
+        let mut article: NewArticle = serde_urlencoded::from_str(post).unwrap();
+        article.credit("bea", true);
+        assert_eq!(article.author, "root");
+        let policy = sanitize.for_group(Some(group(&article.author)));
+        assert!(sanitize::clean(&article.content, policy).contains("<script"));
+        let mut article: NewArticle = serde_urlencoded::from_str("content=x").unwrap();
+        article.credit("bea", true);
+        assert_eq!(article.author, "bea");
+    }
+}
@@ -0,0 +1,398 @@
+//! Backups of the whole site in a portable archive, for moving between hosts and disaster
+//! recovery.
+//!
+//! A backup is a gzipped tar archive containing:
+//!
+//! * `manifest.json`: The format version and when the backup was made
+//! * `groups.json`: Groups and their permissions
+//! * `users.json`: User accounts and profiles without their passwords
+//! * `articles/<id>.md`: Articles as markdown with front matter
+//! * `comments/<id>.json`: The comments of an article, as trees
+//! * `series.json`: Series and the ids of their articles in order
+//! * `coauthors.json`: The co-authors of each article in order
+//! * `pages.json`: Static pages
+//! * `media/`: Media files referenced by articles and pages, and avatars
+
+use chrono::{NaiveDateTime, Utc};
+use diesel::{sql_query, Connection as _, RunQueryDsl};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use tar::{Archive, Builder, Header};
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{Read, Write},
+    path::{Component, Path},
+};
+
+use crate::{
+    article::{self, NewArticle},
+    comment::{self, Node},
+    config::Media,
+    db::Connection,
+    front_matter, media,
+    page::{self, Page},
+    series::{self, Series},
+    user::{self, Account, Group},
+};
+
+/// Version of the archive layout, increased when it changes incompatibly
+const FORMAT: u32 = 1;
+
+/// Characters that end a media url in an article
+const URL_END: &[char] = &[')', '"', '\'', '<', '>', '?', '#', ']'];
+
+#[derive(Deserialize, Serialize)]
+struct Manifest {
+    format: u32,
+    /// The version of mogger that made the backup
+    version: String,
+    #[serde(with = "crate::date_format")]
+    created: NaiveDateTime,
+}
+
+/// A series with its articles
+#[derive(Deserialize, Serialize)]
+struct SeriesEntry {
+    #[serde(flatten)]
+    series: Series,
+    /// Ids of the articles in the series in order
+    articles: Vec<i32>,
+}
+
+/// What was restored from a backup
+#[derive(Default)]
+pub struct Summary {
+    pub users: usize,
+    pub articles: usize,
+    pub comments: usize,
+    pub pages: usize,
+    pub media: usize,
+}
+
+/// Gets the paths within the media directory of the media files an article refers to.
+fn media_references(settings: &Media, content: &str) -> Vec<String> {
+    content
+        .match_indices(media::URL_PREFIX)
+        .filter_map(|(start, _)| {
+            let url = &content[start..];
+            let end = url
+                .find(|c: char| c.is_whitespace() || URL_END.contains(&c))
+                .unwrap_or(url.len());
+            let url = &url[..end];
+            media::resolve(settings, url)?;
+            Some(url[media::URL_PREFIX.len()..].to_owned())
+        })
+        .collect()
+}
+
+fn append(
+    builder: &mut Builder<impl Write>,
+    path: &str,
+    data: &[u8],
+) -> Result<(), failure::Error> {
+    let mut header = Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(Utc::now().timestamp().max(0) as u64);
+    builder.append_data(&mut header, path, data)?;
+    Ok(())
+}
+
+/// Writes a backup of the site.
+pub fn export(
+    connection: &Connection,
+    settings: &Media,
+    writer: impl Write,
+) -> Result<(), failure::Error> {
+    let mut builder = Builder::new(GzEncoder::new(writer, Compression::default()));
+
+    let manifest = Manifest {
+        format: FORMAT,
+        version: String::from(env!("CARGO_PKG_VERSION")),
+        created: Utc::now().naive_utc(),
+    };
+    append(
+        &mut builder,
+        "manifest.json",
+        &serde_json::to_vec_pretty(&manifest)?,
+    )?;
+    let groups = user::groups(connection)?;
+    append(
+        &mut builder,
+        "groups.json",
+        &serde_json::to_vec_pretty(&groups)?,
+    )?;
+    let accounts = user::accounts(connection)?;
+    append(
+        &mut builder,
+        "users.json",
+        &serde_json::to_vec_pretty(&accounts)?,
+    )?;
+
+    let mut entries = Vec::new();
+    for entry in series::list(connection)? {
+        let articles = series::articles(connection, entry.id)?;
+        entries.push(SeriesEntry {
+            series: entry,
+            articles: articles.iter().map(|article| article.id).collect(),
+        });
+    }
+    append(
+        &mut builder,
+        "series.json",
+        &serde_json::to_vec_pretty(&entries)?,
+    )?;
+
+    let mut files: BTreeSet<String> = accounts
+        .iter()
+        .filter_map(|account| account.avatar.as_ref())
+        .filter(|avatar| {
+            media::resolve(settings, &format!("{}{}", media::URL_PREFIX, avatar)).is_some()
+        })
+        .cloned()
+        .collect();
+    let mut coauthors = BTreeMap::new();
+    for article in article::list(connection)? {
+        let path = format!("articles/{}.md", article.id);
+        append(&mut builder, &path, article.markdown().as_bytes())?;
+        let comments = comment::list(connection, article.id)?;
+        if !comments.is_empty() {
+            let path = format!("comments/{}.json", article.id);
+            append(&mut builder, &path, &serde_json::to_vec_pretty(&comments)?)?;
+        }
+        files.extend(media_references(settings, &article.content));
+        let users = article::coauthors(connection, article.id)?;
+        if !users.is_empty() {
+            coauthors.insert(article.id, users);
+        }
+    }
+    append(
+        &mut builder,
+        "coauthors.json",
+        &serde_json::to_vec_pretty(&coauthors)?,
+    )?;
+    let pages = page::list(connection)?;
+    for page in &pages {
+        files.extend(media_references(settings, &page.content));
+    }
+    append(
+        &mut builder,
+        "pages.json",
+        &serde_json::to_vec_pretty(&pages)?,
+    )?;
+    for file in files {
+        let data = fs::read(Path::new(&settings.dir).join(&file))?;
+        append(&mut builder, &format!("media/{}", file), &data)?;
+    }
+
+    builder.into_inner()?.finish()?.flush()?;
+    Ok(())
+}
+
+/// Reads an article from a backup, along with when it was last edited.
+fn read_article(text: &str) -> Result<(NewArticle, Option<NaiveDateTime>), failure::Error> {
+    let (front_matter, content) = front_matter::split(text)?;
+    let front_matter = front_matter.ok_or_else(|| failure::err_msg("no front matter"))?;
+    let article = NewArticle {
+        title: front_matter.title.clone().unwrap_or_default(),
+        url: front_matter.url.clone().unwrap_or_default(),
+        content: content.to_owned(),
+        author: front_matter
+            .author
+            .clone()
+            .ok_or_else(|| failure::err_msg("no author"))?,
+        visible: front_matter.visible().unwrap_or(false),
+        toc: front_matter.toc.unwrap_or(false),
+        tags: front_matter.tags().unwrap_or_default(),
+        summary: front_matter.summary().flatten(),
+        date: front_matter.date()?,
+    };
+    // Backups from before edit times were included only have the time of publishing
+    let updated = front_matter.updated()?.or(article.date);
+    Ok((article, updated))
+}
+
+/// Adds a tree of comments to the database, returning how many there were.
+fn restore_comments(connection: &Connection, nodes: &[Node]) -> Result<usize, failure::Error> {
+    let mut count = 0;
+    for node in nodes {
+        comment::insert(connection, &node.comment)?;
+        count += 1 + restore_comments(connection, &node.children)?;
+    }
+    Ok(count)
+}
+
+/// Restores a backup into an empty database and writes its media files into the media directory,
+/// leaving existing files alone.
+pub fn restore(
+    connection: &Connection,
+    settings: &Media,
+    reader: impl Read,
+) -> Result<Summary, failure::Error> {
+    if user::count(connection)? > 0 || article::count(connection)? > 0 {
+        return Err(failure::err_msg(
+            "Backups can only be restored into an empty database",
+        ));
+    }
+
+    let mut files = BTreeMap::new();
+    for entry in Archive::new(GzDecoder::new(reader)).entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        files.insert(path, data);
+    }
+    let file = |path: &str| {
+        files
+            .get(path)
+            .ok_or_else(|| failure::format_err!("Backup has no {}", path))
+    };
+    let manifest: Manifest = serde_json::from_slice(file("manifest.json")?)?;
+    if manifest.format != FORMAT {
+        return Err(failure::format_err!(
+            "Backup format {} isn't supported, it was made by mogger {}",
+            manifest.format,
+            manifest.version
+        ));
+    }
+
+    let mut summary = Summary::default();
+    connection.transaction::<_, failure::Error, _>(|| {
+        let mut edited = Vec::new();
+        let groups: Vec<Group> = serde_json::from_slice(file("groups.json")?)?;
+        for group in &groups {
+            user::restore_group(connection, group)?;
+        }
+        let accounts: Vec<Account> = serde_json::from_slice(file("users.json")?)?;
+        for account in accounts {
+            user::restore(connection, account)?;
+            summary.users += 1;
+        }
+
+        for (path, data) in &files {
+            let id = match path
+                .strip_prefix("articles/")
+                .and_then(|name| name.strip_suffix(".md"))
+            {
+                Some(id) => id.parse()?,
+                None => continue,
+            };
+            let (article, updated) = read_article(std::str::from_utf8(data)?)
+                .map_err(|e| failure::format_err!("Invalid {}: {}", path, e))?;
+            article::restore(connection, id, &article)?;
+            edited.extend(updated.map(|updated| (id, updated)));
+            summary.articles += 1;
+        }
+        // Backups from before series were added have none
+        if let Some(data) = files.get("series.json") {
+            let entries: Vec<SeriesEntry> = serde_json::from_slice(data)?;
+            for entry in entries {
+                series::restore(connection, &entry.series)?;
+                for (part, article) in (1..).zip(entry.articles) {
+                    series::set(connection, article, Some(entry.series.id), Some(part))?;
+                }
+            }
+        }
+        // Backups from before co-authors were added have none
+        if let Some(data) = files.get("coauthors.json") {
+            let coauthors: BTreeMap<i32, Vec<String>> = serde_json::from_slice(data)?;
+            for (article, users) in coauthors {
+                article::set_coauthors(connection, article, &users)?;
+            }
+        }
+        // Backups from before static pages were added have none
+        if let Some(data) = files.get("pages.json") {
+            let pages: Vec<Page> = serde_json::from_slice(data)?;
+            // They replace the about page new databases start with
+            page::clear(connection)?;
+            for page in &pages {
+                page::restore(connection, page)?;
+            }
+            summary.pages = pages.len();
+        }
+        for (path, data) in &files {
+            if path.starts_with("comments/") {
+                let nodes: Vec<Node> = serde_json::from_slice(data)?;
+                summary.comments += restore_comments(connection, &nodes)?;
+            }
+        }
+
+        // Adding articles to series counts as editing them, so their edit times are set last
+        for (id, updated) in edited {
+            article::set_updated_at(connection, id, updated)?;
+        }
+
+        // New articles, comments, series and pages get ids after the restored ones
+        for table in &["articles", "comments", "series", "pages"] {
+            sql_query(format!(
+                "SELECT setval(pg_get_serial_sequence('{0}', 'id'), COALESCE(MAX(id), 0) + 1, \
+                 false) FROM {0}",
+                table
+            ))
+            .execute(connection)?;
+        }
+        Ok(())
+    })?;
+
+    let dir = Path::new(&settings.dir);
+    for (path, data) in &files {
+        let relative = match path.strip_prefix("media/") {
+            Some(relative) => Path::new(relative),
+            None => continue,
+        };
+        if !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            return Err(failure::format_err!(
+                "Invalid media path in backup: {}",
+                path
+            ));
+        }
+        let target = dir.join(relative);
+        if target.exists() {
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&target, data)?;
+        if let Err(e) = media::process(settings, &target) {
+            log::warn!("Failed to process media file '{}': {}", target.display(), e);
+        }
+        summary.media += 1;
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::media_references;
+    use crate::config::Media;
+
+    #[test]
+    fn references() {
+        let dir = std::env::temp_dir().join(format!("mogger-backup-{}", std::process::id()));
+        fs::create_dir_all(dir.join("2024")).unwrap();
+        fs::write(dir.join("2024/a.png"), b"").unwrap();
+        fs::write(dir.join("b.pdf"), b"").unwrap();
+        let settings = Media {
+            dir: dir.to_string_lossy().into_owned(),
+            ..Media::default()
+        };
+        let content = "![A](/media/2024/a.png \"Title\") [B](/media/b.pdf#page=2) \
+                       <img src=\"/media/missing.png\"> /media/../secret";
+        assert_eq!(
+            media_references(&settings, content),
+            vec!["2024/a.png", "b.pdf"]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,224 @@
+//! HTTP caching. Adds entity tags and `Cache-Control` headers to responses and answers
+//! conditional requests with `304 Not Modified`.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use futures::prelude::*;
+use gotham::{
+    handler::{HandlerFuture, HandlerResult},
+    hyper::{
+        body,
+        header::{
+            HeaderMap, HeaderValue, CACHE_CONTROL, CONTENT_DISPOSITION, ETAG, IF_MODIFIED_SINCE,
+            IF_NONE_MATCH, LAST_MODIFIED,
+        },
+        Body, Method, Response, StatusCode, Uri,
+    },
+    middleware::{Middleware, NewMiddleware},
+    state::{FromState, State},
+};
+use sha2::{Digest, Sha256};
+
+use std::pin::Pin;
+
+use crate::config::{Cache, Settings};
+
+/// Formats a date as an HTTP date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn http_date(date: NaiveDateTime) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Sets the `Last-Modified` header of a response.
+pub fn set_last_modified(response: &mut Response<Body>, date: NaiveDateTime) {
+    if let Ok(value) = HeaderValue::from_str(&http_date(date)) {
+        response.headers_mut().insert(LAST_MODIFIED, value);
+    }
+}
+
+/// Keeps a response from being stored or tagged, e.g. because it embeds the request's CSP nonce
+/// and a cached copy would no longer match the header.
+pub fn set_no_store(response: &mut Response<Body>) {
+    response
+        .headers_mut()
+        .insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+}
+
+/// Gets the `Cache-Control` header value for the route class of the given path.
+fn cache_control<'a>(settings: &'a Cache, path: &str) -> &'a str {
+    if path.starts_with("/api/") {
+        &settings.api
+    } else if path.ends_with(".rss") {
+        &settings.feeds
+    } else {
+        &settings.pages
+    }
+}
+
+/// Computes a strong entity tag from a response body.
+fn entity_tag(body: &[u8]) -> String {
+    let digest = format!("{:x}", Sha256::digest(body));
+    format!("\"{}\"", &digest[..32])
+}
+
+/// Checks if a response must be passed through as is: downloads, which can be large and are
+/// streamed, and responses that mustn't be stored at all.
+fn uncacheable(headers: &HeaderMap) -> bool {
+    let contains = |name, value| {
+        headers
+            .get(name)
+            .and_then(|header: &HeaderValue| header.to_str().ok())
+            .is_some_and(|header| header.to_ascii_lowercase().contains(value))
+    };
+    contains(CACHE_CONTROL, "no-store") || contains(CONTENT_DISPOSITION, "attachment")
+}
+
+/// Checks if the conditional headers of a request are satisfied by the response's validators,
+/// meaning the client's cached copy is still fresh.
+fn not_modified(request: &HeaderMap, response: &HeaderMap) -> bool {
+    // If-Modified-Since must be ignored when If-None-Match is present
+    if let Some(tags) = request.get(IF_NONE_MATCH) {
+        let etag = match response.get(ETAG).and_then(|etag| etag.to_str().ok()) {
+            Some(etag) => etag,
+            None => return false,
+        };
+        return tags
+            .to_str()
+            .map(|tags| {
+                tags.split(',')
+                    .map(|tag| tag.trim().trim_start_matches("W/"))
+                    .any(|tag| tag == "*" || tag == etag)
+            })
+            .unwrap_or(false);
+    }
+    let parse = |value: &HeaderValue| {
+        let date = DateTime::parse_from_rfc2822(value.to_str().ok()?).ok()?;
+        Some(date.with_timezone(&Utc))
+    };
+    match (
+        request.get(IF_MODIFIED_SINCE).and_then(parse),
+        response.get(LAST_MODIFIED).and_then(parse),
+    ) {
+        (Some(since), Some(modified)) => modified <= since,
+        _ => false,
+    }
+}
+
+async fn conditional(state: State, response: Response<Body>) -> HandlerResult {
+    // Responses that already have a tag, like static files, handle conditional requests themselves
+    if Method::borrow_from(&state) != Method::GET
+        || response.status() != StatusCode::OK
+        || response.headers().contains_key(ETAG)
+        || uncacheable(response.headers())
+    {
+        return Ok((state, response));
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let body = match body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(e) => return Err((state, e.into())),
+    };
+
+    if let Ok(etag) = HeaderValue::from_str(&entity_tag(&body)) {
+        parts.headers.insert(ETAG, etag);
+    }
+    if !parts.headers.contains_key(CACHE_CONTROL) {
+        let settings = &Settings::borrow_from(&state).cache;
+        let value = cache_control(settings, Uri::borrow_from(&state).path());
+        if let Ok(value) = HeaderValue::from_str(value) {
+            parts.headers.insert(CACHE_CONTROL, value);
+        }
+    }
+
+    if not_modified(HeaderMap::borrow_from(&state), &parts.headers) {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::NOT_MODIFIED;
+        for name in &[ETAG, CACHE_CONTROL, LAST_MODIFIED] {
+            if let Some(value) = parts.headers.remove(name) {
+                response.headers_mut().insert(name, value);
+            }
+        }
+        return Ok((state, response));
+    }
+
+    Ok((state, Response::from_parts(parts, Body::from(body))))
+}
+
+/// Middleware that adds caching headers to successful responses and handles conditional `GET`
+/// requests.
+#[derive(Clone, NewMiddleware)]
+pub struct CacheMiddleware;
+
+impl Middleware for CacheMiddleware {
+    fn call<C>(self, state: State, chain: C) -> Pin<Box<HandlerFuture>>
+    where
+        C: FnOnce(State) -> Pin<Box<HandlerFuture>>,
+    {
+        chain(state)
+            .and_then(|(state, response)| conditional(state, response))
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use futures::{executor, stream};
+    use gotham::{
+        hyper::{
+            body,
+            header::{
+                HeaderMap, HeaderValue, CACHE_CONTROL, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+                LAST_MODIFIED,
+            },
+            Body, Request, Response,
+        },
+        state::State,
+    };
+
+    use std::io;
+
+    use super::{conditional, entity_tag, http_date, not_modified};
+
+    #[test]
+    fn conditional_headers() {
+        let date = NaiveDate::from_ymd(2020, 7, 29).and_hms(11, 39, 0);
+        let mut response = HeaderMap::new();
+        response.insert(ETAG, HeaderValue::from_str(&entity_tag(b"body")).unwrap());
+        response.insert(
+            LAST_MODIFIED,
+            HeaderValue::from_str(&http_date(date)).unwrap(),
+        );
+
+        let mut request = HeaderMap::new();
+        assert!(!not_modified(&request, &response));
+
+        request.insert(IF_MODIFIED_SINCE, response[LAST_MODIFIED].clone());
+        assert!(not_modified(&request, &response));
+
+        // If-None-Match takes precedence over If-Modified-Since
+        request.insert(IF_NONE_MATCH, HeaderValue::from_static("\"other\""));
+        assert!(!not_modified(&request, &response));
+
+        let tags = format!("\"other\", W/{}", entity_tag(b"body"));
+        request.insert(IF_NONE_MATCH, HeaderValue::from_str(&tags).unwrap());
+        assert!(not_modified(&request, &response));
+    }
+
+    #[test]
+    fn streamed() {
+        // The body fails after its first chunk, so it only gets through if it isn't buffered
+        let chunks = vec![Ok(b"chunk".to_vec()), Err(io::Error::other("buffered"))];
+        let response = Response::builder()
+            .header(CACHE_CONTROL, "no-store")
+            .body(Body::wrap_stream(stream::iter(chunks)))
+            .unwrap();
+        let request = Request::get("/backup").body(Body::empty()).unwrap();
+        let state = State::from_request(request, ([127, 0, 0, 1], 8000).into());
+
+        let (_, response) = executor::block_on(conditional(state, response))
+            .map_err(|(_, e)| e)
+            .unwrap();
+        assert!(!response.headers().contains_key(ETAG));
+        assert!(executor::block_on(body::to_bytes(response.into_body())).is_err());
+    }
+}
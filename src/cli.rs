@@ -0,0 +1,379 @@
+//! Command line interface for running the server and administrative tasks.
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+use std::{
+    io::BufRead,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    backup,
+    config::Settings,
+    db::{self, Connection},
+    highlight,
+    import::{self, Authors, Report},
+    user::{self, Group, NewUser, Permission},
+};
+
+/// Simple blogging engine
+#[derive(Parser)]
+#[command(version)]
+pub struct Args {
+    /// Path to the configuration file. Defaults to /etc/mogger/mogger.toml if it exists,
+    /// otherwise mogger.toml in the current directory
+    #[arg(long, env = "MOGGER_CONFIG", global = true)]
+    pub config: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the web server (default)
+    Serve {
+        /// Don't apply pending database migrations on startup
+        #[arg(long)]
+        no_migrate: bool,
+    },
+    /// Manage database migrations
+    #[command(subcommand)]
+    Migrate(Migrate),
+    /// Manage users
+    #[command(subcommand)]
+    User(User),
+    /// Manage groups and their permissions
+    #[command(subcommand)]
+    Group(GroupCommand),
+    /// Manage login sessions
+    #[command(subcommand)]
+    Session(Session),
+    /// Inspect the configuration
+    #[command(subcommand)]
+    Config(Config),
+    /// Import articles and comments from a WordPress export or a Jekyll or Hugo site
+    Import {
+        /// The WordPress export (WXR) file, or the directory of the site
+        path: PathBuf,
+        /// Attribute the articles and comments of an author to a user. Authors that aren't mapped
+        /// aren't attributed to users, even if one has the same name
+        #[arg(long = "author", value_name = "AUTHOR=USER", value_parser = import::parse_mapping)]
+        authors: Vec<(String, String)>,
+        /// The user to attribute articles to whose author isn't mapped to a user. Such articles are
+        /// skipped if this isn't given
+        #[arg(long)]
+        default_author: Option<String>,
+    },
+    /// Write a backup of the site to a file
+    Export { path: PathBuf },
+    /// Restore a backup made with `export` into an empty database
+    Restore { path: PathBuf },
+    /// Render the site to static files
+    Build {
+        /// The directory to write the site to
+        #[arg(long)]
+        out: PathBuf,
+        /// Render every article again, not just those that changed since the last build
+        #[arg(long)]
+        full: bool,
+    },
+}
+
+impl Default for Command {
+    fn default() -> Self {
+        Command::Serve { no_migrate: false }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Migrate {
+    /// Apply all pending migrations
+    Run,
+    /// Revert the most recently applied migration
+    Revert,
+    /// List migrations and whether they have been applied
+    Status,
+}
+
+#[derive(Subcommand)]
+pub enum User {
+    /// Create a user
+    Add {
+        /// The username
+        id: String,
+        /// The display name. Defaults to the username
+        #[arg(long)]
+        name: Option<String>,
+        #[arg(long, default_value = "")]
+        email: String,
+        #[arg(long, default_value = "default")]
+        group: String,
+        /// Read the password from the first line of standard input instead of prompting for it
+        #[arg(long)]
+        password_stdin: bool,
+    },
+    /// Set a user's password
+    Passwd {
+        id: String,
+        /// Read the password from the first line of standard input instead of prompting for it
+        #[arg(long)]
+        password_stdin: bool,
+    },
+    /// Move a user to another group
+    SetGroup { id: String, group: String },
+    /// Delete a user, disowning their comments
+    Delete {
+        id: String,
+        /// Remove the contents of the user's comments as well
+        #[arg(long)]
+        purge: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum GroupCommand {
+    /// List groups and their permissions
+    List,
+    /// Create a group
+    Add {
+        id: String,
+        #[arg(value_enum)]
+        permissions: Vec<Permission>,
+    },
+    /// Delete a group. It must not have any members
+    Delete { id: String },
+    /// Give a group permissions
+    Grant {
+        id: String,
+        #[arg(value_enum, required = true)]
+        permissions: Vec<Permission>,
+    },
+    /// Take permissions away from a group
+    Revoke {
+        id: String,
+        #[arg(value_enum, required = true)]
+        permissions: Vec<Permission>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum Session {
+    /// Delete expired sessions
+    Purge {
+        /// Delete all sessions, logging out every user
+        #[arg(long)]
+        all: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum Config {
+    /// Check that the configuration file and environment overrides are valid
+    Check,
+    /// Print a stylesheet coloring code highlighted with classes like a built-in theme
+    HighlightCss {
+        /// The theme. Lists the available themes if omitted
+        theme: Option<String>,
+    },
+}
+
+/// Reads a new password, either from standard input or by prompting for it twice.
+fn read_password(stdin: bool) -> Result<String, failure::Error> {
+    if stdin {
+        let mut line = String::new();
+        std::io::stdin().lock().read_line(&mut line)?;
+        return Ok(line.trim_end_matches(&['\r', '\n'][..]).to_owned());
+    }
+    let password = rpassword::prompt_password("Password: ")?;
+    if rpassword::prompt_password("Repeat password: ")? != password {
+        return Err(failure::err_msg("Passwords don't match"));
+    }
+    Ok(password)
+}
+
+/// Gets the name of a permission as used on the command line and in the database.
+fn permission_name(permission: Permission) -> String {
+    permission
+        .to_possible_value()
+        .map_or_else(|| format!("{:?}", permission), |v| v.get_name().to_owned())
+}
+
+fn migrate(connection: &Connection, command: Migrate) -> Result<(), failure::Error> {
+    match command {
+        Migrate::Run => {
+            let pending = db::pending_migrations(connection)?;
+            db::migrate(connection)?;
+            println!("Applied {} migrations", pending.len());
+        }
+        Migrate::Revert => match db::revert_migration(connection)? {
+            Some(migration) => println!("Reverted {}", migration.name),
+            None => println!("No migrations to revert"),
+        },
+        Migrate::Status => {
+            let applied = db::applied_migrations(connection)?;
+            for migration in db::MIGRATIONS {
+                let status = if applied.contains(migration.version) {
+                    "applied"
+                } else {
+                    "pending"
+                };
+                println!("{:8} {}", status, migration.name);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn user(connection: &Connection, command: User) -> Result<(), failure::Error> {
+    match command {
+        User::Add {
+            id,
+            name,
+            email,
+            group,
+            password_stdin,
+        } => {
+            let password = read_password(password_stdin)?;
+            let new = NewUser {
+                name: name.unwrap_or_else(|| id.clone()),
+                id,
+                password,
+                email,
+                group,
+                phone: String::new(),
+            };
+            user::create(connection, new)?;
+        }
+        User::Passwd { id, password_stdin } => {
+            let password = read_password(password_stdin)?;
+            user::set_password(connection, &id, &password)?;
+        }
+        User::SetGroup { id, group } => user::set_group(connection, &id, &group)?,
+        User::Delete { id, purge } => user::remove(connection, &id, purge)?,
+    }
+    Ok(())
+}
+
+fn import(connection: &Connection, path: &Path, authors: Authors) -> Result<(), failure::Error> {
+    let files = if path.is_dir() {
+        import::read_dir(path)?
+    } else {
+        vec![(path.to_owned(), std::fs::read(path)?)]
+    };
+    let mut report = Report::default();
+    let posts = import::read_files(files, &mut report)?;
+    import::import(connection, posts, &authors, &mut report)?;
+    println!(
+        "Imported {} articles and {} comments",
+        report.articles, report.comments
+    );
+    for skipped in &report.skipped {
+        println!("Skipped {}", skipped);
+    }
+    Ok(())
+}
+
+fn group(connection: &Connection, command: GroupCommand) -> Result<(), failure::Error> {
+    let modify = |id: &str, f: &dyn Fn(&mut Vec<Permission>)| -> Result<(), failure::Error> {
+        let mut group = user::groups(connection)?
+            .into_iter()
+            .find(|g| g.id == id)
+            .ok_or_else(|| failure::err_msg(format!("No such group '{}'", id)))?;
+        f(&mut group.permissions);
+        user::set_permissions(connection, id, &group.permissions)
+    };
+
+    match command {
+        GroupCommand::List => {
+            for group in user::groups(connection)? {
+                let permissions: Vec<String> =
+                    group.permissions.into_iter().map(permission_name).collect();
+                println!("{}: {}", group.id, permissions.join(", "));
+            }
+        }
+        GroupCommand::Add { id, permissions } => {
+            user::create_group(connection, &Group { id, permissions })?;
+        }
+        GroupCommand::Delete { id } => user::delete_group(connection, &id)?,
+        GroupCommand::Grant { id, permissions } => modify(&id, &|current| {
+            for permission in &permissions {
+                if !current.contains(permission) {
+                    current.push(*permission);
+                }
+            }
+        })?,
+        GroupCommand::Revoke { id, permissions } => {
+            modify(&id, &|current| current.retain(|p| !permissions.contains(p)))?
+        }
+    }
+    Ok(())
+}
+
+/// Runs an administrative command. Serving is handled by the caller.
+pub fn run(
+    command: Command,
+    settings: &Settings,
+    config: Option<&Path>,
+) -> Result<(), failure::Error> {
+    match command {
+        // Settings are validated when loaded, so getting here means they're fine
+        Command::Config(Config::Check) => {
+            match config {
+                Some(path) => println!("{}: ok", path.display()),
+                None => println!("No configuration file, settings from environment: ok"),
+            }
+            return Ok(());
+        }
+        Command::Config(Config::HighlightCss { theme: Some(theme) }) => {
+            print!("{}", highlight::css(&theme)?);
+            return Ok(());
+        }
+        Command::Config(Config::HighlightCss { theme: None }) => {
+            for theme in highlight::themes() {
+                println!("{}", theme);
+            }
+            return Ok(());
+        }
+        _ => (),
+    }
+
+    let connection = db::connect(&settings.database_url)?;
+    match command {
+        Command::Serve { .. } | Command::Build { .. } | Command::Config(_) => unreachable!(),
+        Command::Migrate(command) => migrate(&connection, command),
+        Command::User(command) => user(&connection, command),
+        Command::Group(command) => group(&connection, command),
+        Command::Session(Session::Purge { all }) => {
+            let count = user::purge_sessions(&connection, all)?;
+            println!("Deleted {} sessions", count);
+            Ok(())
+        }
+        Command::Import {
+            path,
+            authors,
+            default_author,
+        } => {
+            let authors = Authors {
+                map: authors.into_iter().collect(),
+                default: default_author,
+            };
+            import(&connection, &path, authors)
+        }
+        Command::Export { path } => {
+            let file = std::fs::File::create(&path)?;
+            backup::export(&connection, &settings.media, std::io::BufWriter::new(file))?;
+            println!("Wrote backup to {}", path.display());
+            Ok(())
+        }
+        Command::Restore { path } => {
+            let file = std::fs::File::open(&path)?;
+            let summary = backup::restore(&connection, &settings.media, file)?;
+            println!(
+                "Restored {} users, {} articles, {} comments, {} pages and {} media files",
+                summary.users, summary.articles, summary.comments, summary.pages, summary.media
+            );
+            println!("Users have to be given new passwords with `mogger user passwd`");
+            Ok(())
+        }
+    }
+}
@@ -1,9 +1,8 @@
 use chrono::NaiveDateTime;
-use comrak::markdown_to_html;
 use diesel::{pg::PgConnection as Connection, prelude::*, result::Error as DieselError, Queryable};
 
 use crate::{
-    config::COMRAK_OPTS,
+    render::Renderer,
     schema::comments,
     user::{self, Permission, Session},
 };
@@ -87,8 +86,8 @@ impl Comment {
         }
     }
 
-    pub fn formatted(&self) -> String {
-        markdown_to_html(&self.content, &COMRAK_OPTS)
+    pub fn formatted(&self, renderer: &Renderer) -> String {
+        renderer.comment(&self.content)
     }
 
     pub fn author(&self, connection: &Connection) -> Result<String, failure::Error> {
@@ -194,6 +193,37 @@ pub fn submit(connection: &Connection, comment: NewComment) -> Result<Comment, D
     Ok(sumbitted)
 }
 
+/// Submits a comment with the given submission time instead of the current time
+pub fn submit_at(
+    connection: &Connection,
+    comment: &NewComment,
+    date: NaiveDateTime,
+) -> Result<Comment, DieselError> {
+    use crate::schema::comments::dsl;
+
+    diesel::insert_into(comments::table)
+        .values((comment, dsl::date.eq(date)))
+        .get_result(connection)
+}
+
+/// Adds a comment exactly as given, used when restoring backups
+pub fn insert(connection: &Connection, comment: &Comment) -> Result<usize, DieselError> {
+    use crate::schema::comments::dsl;
+
+    diesel::insert_into(comments::table)
+        .values((
+            dsl::id.eq(comment.id),
+            dsl::parent.eq(comment.parent),
+            dsl::article.eq(comment.article),
+            dsl::author.eq(&comment.author),
+            dsl::name.eq(&comment.name),
+            dsl::content.eq(&comment.content),
+            dsl::date.eq(comment.date),
+            dsl::visible.eq(comment.visible),
+        ))
+        .execute(connection)
+}
+
 pub fn edit(
     connection: &Connection,
     id: i32,
@@ -244,6 +274,32 @@ pub fn author(connection: &Connection, id: i32) -> Result<Option<String>, Diesel
     dsl::comments.select(dsl::author).find(id).first(connection)
 }
 
+/// Gets the submission time of the newest comment on an article
+pub fn last_submitted(
+    connection: &Connection,
+    article: i32,
+) -> Result<Option<NaiveDateTime>, DieselError> {
+    use crate::schema::comments::dsl;
+
+    dsl::comments
+        .filter(dsl::article.eq(article))
+        .select(diesel::dsl::max(dsl::date))
+        .first(connection)
+}
+
+/// Counts guest comments awaiting moderation
+pub fn pending_count(connection: &Connection) -> Result<i64, DieselError> {
+    use crate::schema::comments::dsl;
+
+    dsl::comments
+        .filter(dsl::visible.eq(false))
+        .filter(dsl::author.is_null())
+        // Comments of deleted users are hidden too, but aren't waiting for anything
+        .filter(dsl::name.ne("[deleted]"))
+        .count()
+        .first(connection)
+}
+
 pub fn by_user(connection: &Connection, user: &str) -> Result<Vec<Comment>, DieselError> {
     use crate::schema::comments::dsl;
 
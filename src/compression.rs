@@ -0,0 +1,185 @@
+//! Response compression. Text-like responses are compressed with brotli or gzip depending on the
+//! client's `Accept-Encoding` header.
+
+use flate2::write::GzEncoder;
+use futures::prelude::*;
+use gotham::{
+    handler::{HandlerFuture, HandlerResult},
+    hyper::{
+        body,
+        header::{
+            HeaderMap, HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH,
+            CONTENT_TYPE, ETAG, VARY,
+        },
+        Body, Response, StatusCode,
+    },
+    middleware::{Middleware, NewMiddleware},
+    state::{FromState, State},
+};
+
+use std::{io::Write, pin::Pin};
+
+use crate::config::Settings;
+
+/// Brotli quality level. Higher levels are too slow for compressing on the fly.
+const BROTLI_QUALITY: u32 = 5;
+/// Brotli window size, as a base 2 logarithm.
+const BROTLI_WINDOW: u32 = 22;
+
+/// A content coding supported for compression.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut writer =
+                    brotli::CompressorWriter::new(Vec::new(), 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+                writer.write_all(data)?;
+                Ok(writer.into_inner())
+            }
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Picks the encoding the client prefers from an `Accept-Encoding` header. Brotli is preferred
+/// when the client has no preference.
+fn negotiate(accept: &str) -> Option<Encoding> {
+    let mut best: Option<(Encoding, f32)> = None;
+    for item in accept.split(',') {
+        let mut params = item.split(';').map(str::trim);
+        let encoding = match params.next() {
+            Some("br") => Encoding::Brotli,
+            Some("gzip") | Some("x-gzip") => Encoding::Gzip,
+            _ => continue,
+        };
+        let quality = params
+            .find_map(|param| param.strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        let better = match best {
+            _ if quality <= 0.0 => false,
+            Some((current, q)) => quality > q || quality == q && current == Encoding::Gzip,
+            None => true,
+        };
+        if better {
+            best = Some((encoding, quality));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Checks if a response's media type is worth compressing.
+fn compressible(headers: &HeaderMap) -> bool {
+    let mime = match headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok()) {
+        Some(mime) => mime.split(';').next().unwrap_or_default().trim(),
+        None => return false,
+    };
+    mime.starts_with("text/")
+        || mime.ends_with("+xml")
+        || mime.ends_with("+json")
+        || matches!(
+            mime,
+            "application/json" | "application/javascript" | "application/xml" | "image/svg+xml"
+        )
+}
+
+async fn compress(state: State, response: Response<Body>) -> HandlerResult {
+    let settings = &Settings::borrow_from(&state).compression;
+    if !settings.enabled || !compressible(response.headers()) {
+        return Ok((state, response));
+    }
+
+    let (mut parts, body) = response.into_parts();
+    // The response depends on Accept-Encoding whether or not we end up compressing it
+    parts
+        .headers
+        .append(VARY, HeaderValue::from_static("accept-encoding"));
+
+    let encoding = HeaderMap::borrow_from(&state)
+        .get(ACCEPT_ENCODING)
+        .and_then(|accept| accept.to_str().ok())
+        .and_then(negotiate);
+    let encoding = match encoding {
+        Some(encoding)
+            if parts.status == StatusCode::OK && !parts.headers.contains_key(CONTENT_ENCODING) =>
+        {
+            encoding
+        }
+        _ => return Ok((state, Response::from_parts(parts, body))),
+    };
+
+    let body = match body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(e) => return Err((state, e.into())),
+    };
+    if body.len() < settings.min_size {
+        return Ok((state, Response::from_parts(parts, Body::from(body))));
+    }
+    let compressed = match encoding.compress(&body) {
+        Ok(compressed) => compressed,
+        Err(e) => return Err((state, e.into())),
+    };
+
+    parts
+        .headers
+        .insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.name()));
+    parts
+        .headers
+        .insert(CONTENT_LENGTH, compressed.len().into());
+    // The compressed body is no longer byte for byte identical, so the tag can only be weak
+    if let Some(etag) = parts.headers.get(ETAG).and_then(|v| v.to_str().ok()) {
+        if !etag.starts_with("W/") {
+            if let Ok(weak) = HeaderValue::from_str(&format!("W/{}", etag)) {
+                parts.headers.insert(ETAG, weak);
+            }
+        }
+    }
+    Ok((state, Response::from_parts(parts, Body::from(compressed))))
+}
+
+/// Middleware that compresses text-like responses larger than the configured threshold.
+#[derive(Clone, NewMiddleware)]
+pub struct CompressionMiddleware;
+
+impl Middleware for CompressionMiddleware {
+    fn call<C>(self, state: State, chain: C) -> Pin<Box<HandlerFuture>>
+    where
+        C: FnOnce(State) -> Pin<Box<HandlerFuture>>,
+    {
+        chain(state)
+            .and_then(|(state, response)| compress(state, response))
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{negotiate, Encoding};
+
+    #[test]
+    fn accept_encoding() {
+        assert_eq!(negotiate("gzip, deflate, br"), Some(Encoding::Brotli));
+        assert_eq!(negotiate("gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("br;q=0.5, gzip;q=0.8"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("br;q=0, gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("identity, deflate"), None);
+        assert_eq!(negotiate(""), None);
+    }
+}
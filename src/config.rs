@@ -1,39 +1,385 @@
-use comrak::{
-    plugins::syntect::SyntectAdapter, ComrakExtensionOptions, ComrakOptions, ComrakParseOptions,
-    ComrakPlugins, ComrakRenderOptions, ComrakRenderPlugins,
+use comrak::{ComrakExtensionOptions, ComrakOptions, ComrakParseOptions, ComrakRenderOptions};
+use gotham::{
+    hyper::header::{HeaderName, HeaderValue},
+    state::StateData,
 };
-use gotham::state::StateData;
+use log::LevelFilter;
+use toml::{value::Table, Value};
+
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    convert::TryFrom,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{highlight, logging, theme};
+
+/// Prefix of environment variables that override settings
+const ENV_PREFIX: &str = "MOGGER_";
 
 /// Application wide settings defined in configuration file.
 #[derive(Deserialize, StateData, Clone)]
+#[serde(default, deny_unknown_fields)]
 pub struct Settings {
     /// Postgres database url
     pub database_url: String,
     /// IP address to bind to
     pub host_address: String,
+    /// Seconds to wait for in-flight requests to finish when shutting down
+    pub shutdown_timeout: u64,
     /// Toggles for enabling and disabling features
     pub features: Features,
     /// Cookie settings
     pub cookie: Cookie,
+    /// Media settings
+    pub media: Media,
+    /// HTTP caching settings
+    pub cache: Cache,
+    /// Response compression settings
+    pub compression: Compression,
+    /// Logging settings
+    pub log: Log,
+    /// Metrics endpoint settings
+    pub metrics: Metrics,
+    /// Static assets and theme settings
+    pub theme: Theme,
+    /// HTTPS settings
+    pub tls: Tls,
+    /// Security headers added to responses
+    pub headers: Headers,
+    /// HTML sanitization policies
+    pub sanitize: Sanitize,
+    /// Markdown rendering settings
+    pub markdown: Markdown,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            database_url: String::new(),
+            host_address: String::from("localhost:6096"),
+            shutdown_timeout: 30,
+            features: Features::default(),
+            cookie: Cookie::default(),
+            media: Media::default(),
+            cache: Cache::default(),
+            compression: Compression::default(),
+            log: Log::default(),
+            metrics: Metrics::default(),
+            theme: Theme::default(),
+            tls: Tls::default(),
+            headers: Headers::default(),
+            sanitize: Sanitize::default(),
+            markdown: Markdown::default(),
+        }
+    }
 }
 
 impl Settings {
-    pub fn from_slice(data: &[u8]) -> Result<Self, toml::de::Error> {
-        toml::from_slice(data)
+    /// Loads settings from the configuration file at `path` if given, applies overrides from
+    /// `MOGGER_*` environment variables and validates the result.
+    pub fn load(path: Option<&Path>) -> Result<Self, failure::Error> {
+        let mut value = match path {
+            Some(path) => {
+                let data = fs::read(path).map_err(|e| {
+                    failure::format_err!("Failed to read {}: {}", path.display(), e)
+                })?;
+                toml::from_slice(&data)
+                    .map_err(|e| failure::format_err!("Invalid {}: {}", path.display(), e))?
+            }
+            None => Value::Table(Table::new()),
+        };
+        apply_env(&mut value, std::env::vars())?;
+        let settings: Settings = match value.clone().try_into() {
+            Ok(settings) => settings,
+            Err(_) => {
+                return Err(failure::format_err!(
+                    "Invalid configuration:\n  {}",
+                    invalid_settings(&value).join("\n  ")
+                ))
+            }
+        };
+
+        let problems = settings.validate();
+        if !problems.is_empty() {
+            return Err(failure::format_err!(
+                "Invalid configuration:\n  {}",
+                problems.join("\n  ")
+            ));
+        }
+        Ok(settings)
+    }
+
+    /// Checks the settings for values that can't work. Returns a description of every problem
+    /// found.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.database_url.is_empty() {
+            problems.push(String::from("database_url must be set"));
+        }
+        if !is_address(&self.host_address) {
+            problems.push(format!(
+                "host_address '{}' is not of the form host:port",
+                self.host_address
+            ));
+        }
+        if !(1..=100).contains(&self.media.quality) {
+            problems.push(String::from("media.quality must be between 1 and 100"));
+        }
+        if self.media.widths.contains(&0) {
+            problems.push(String::from("media.widths must not contain 0"));
+        }
+        for (name, value) in &[
+            ("pages", &self.cache.pages),
+            ("feeds", &self.cache.feeds),
+            ("api", &self.cache.api),
+            ("files", &self.cache.files),
+        ] {
+            if HeaderValue::from_str(value).is_err() {
+                problems.push(format!("cache.{} is not a valid header value", name));
+            }
+        }
+        if let Some(address) = &self.metrics.address {
+            if !is_address(address) {
+                problems.push(format!(
+                    "metrics.address '{}' is not of the form host:port",
+                    address
+                ));
+            }
+        }
+        if self.metrics.token.as_deref() == Some("") {
+            problems.push(String::from("metrics.token must not be empty if set"));
+        }
+        if !Path::new(&self.theme.assets).is_dir() {
+            problems.push(format!(
+                "theme.assets directory '{}' doesn't exist",
+                self.theme.assets
+            ));
+        }
+        if let Some(dir) = &self.theme.dir {
+            if !Path::new(dir).is_dir() {
+                problems.push(format!("theme.dir directory '{}' doesn't exist", dir));
+            }
+        }
+        if self.tls.enabled {
+            for (name, path) in &[("cert", &self.tls.cert), ("key", &self.tls.key)] {
+                if !Path::new(path).is_file() {
+                    problems.push(format!("tls.{} file '{}' doesn't exist", name, path));
+                }
+            }
+            if HeaderValue::from_str(&self.tls.hsts).is_err() {
+                problems.push(String::from("tls.hsts is not a valid header value"));
+            }
+        }
+        if let Some(address) = &self.tls.redirect_address {
+            if !self.tls.enabled {
+                problems.push(String::from("tls.redirect_address requires tls.enabled"));
+            }
+            if !is_address(address) {
+                problems.push(format!(
+                    "tls.redirect_address '{}' is not of the form host:port",
+                    address
+                ));
+            }
+        }
+        for (prefix, route) in &self.headers.routes {
+            for (name, value) in route {
+                if HeaderName::from_bytes(name.as_bytes()).is_err() {
+                    problems.push(format!(
+                        "headers.routes.\"{}\": invalid header {}",
+                        prefix, name
+                    ));
+                }
+                if HeaderValue::from_str(&value.replace(NONCE, "")).is_err() {
+                    problems.push(format!(
+                        "headers.routes.\"{}\".{} is not a valid header value",
+                        prefix, name
+                    ));
+                }
+            }
+        }
+        for (name, value) in &[
+            (
+                "content_security_policy",
+                &self.headers.content_security_policy,
+            ),
+            ("content_type_options", &self.headers.content_type_options),
+            ("referrer_policy", &self.headers.referrer_policy),
+            ("frame_options", &self.headers.frame_options),
+        ] {
+            if HeaderValue::from_str(&value.replace(NONCE, "")).is_err() {
+                problems.push(format!("headers.{} is not a valid header value", name));
+            }
+        }
+        for name in std::iter::once(&self.sanitize.default).chain(self.sanitize.groups.values()) {
+            if self.sanitize.policy(name).is_none() {
+                problems.push(format!("sanitize: no policy named '{}'", name));
+            }
+        }
+        for (name, policy) in &self.sanitize.policies {
+            if policy.attributes.values().flatten().any(|a| a == "class") {
+                problems.push(format!(
+                    "sanitize.policies.{}: allow classes with classes instead of attributes",
+                    name
+                ));
+            }
+        }
+        let highlight = &self.markdown.highlight;
+        if highlight.enabled && !highlight.classes {
+            let themes = highlight::themes();
+            if !themes.contains(&highlight.theme) {
+                problems.push(format!(
+                    "markdown.highlight.theme '{}' doesn't exist, available themes are {}",
+                    highlight.theme,
+                    themes.join(", ")
+                ));
+            }
+        }
+        for (language, command) in &self.markdown.diagrams.renderers {
+            if command.is_empty() {
+                problems.push(format!(
+                    "markdown.diagrams.renderers.{}: command must not be empty",
+                    language
+                ));
+            }
+        }
+        for (name, value) in &self.theme.colors {
+            if let Err(e) = theme::check_color(name, value) {
+                problems.push(format!("theme.colors.{}: {}", name, e));
+            }
+        }
+        problems
+    }
+}
+
+/// Checks if an address is of the form `host:port`.
+fn is_address(address: &str) -> bool {
+    match address.rsplit_once(':') {
+        Some((host, port)) => !host.is_empty() && port.parse::<u16>().is_ok(),
+        None => false,
+    }
+}
+
+/// Finds the configuration file to use. Is `path` if given, otherwise `/etc/mogger/mogger.toml`
+/// if it exists, otherwise `mogger.toml` in the current directory if it exists.
+pub fn find(path: Option<PathBuf>) -> Option<PathBuf> {
+    path.or_else(|| {
+        ["/etc/mogger/mogger.toml", "mogger.toml"]
+            .iter()
+            .map(PathBuf::from)
+            .find(|path| path.is_file())
+    })
+}
+
+/// Nests `value` in tables along `path`, making a configuration with just that setting.
+fn setting<S: AsRef<str>>(path: &[S], value: Value) -> Value {
+    path.iter().rev().fold(value, |value, key| {
+        Value::Table(std::iter::once((key.as_ref().to_owned(), value)).collect())
+    })
+}
+
+/// Finds every setting that can't be deserialized, as deserializing all of them at once stops at
+/// the first. Each setting is tried on its own, which works as every section has defaults, and
+/// sections that fail are searched for the settings they fail on.
+fn invalid_settings(value: &Value) -> Vec<String> {
+    fn search(path: &mut Vec<String>, table: &Table, problems: &mut Vec<String>) {
+        for (key, entry) in table {
+            path.push(key.clone());
+            if let Err(e) = setting(path, entry.clone()).try_into::<Settings>() {
+                let found = problems.len();
+                if let Value::Table(entries) = entry {
+                    search(path, entries, problems);
+                }
+                // The section fails as a whole, or on something that isn't a setting
+                if problems.len() == found {
+                    problems.push(e.to_string());
+                }
+            }
+            path.pop();
+        }
+    }
+
+    let mut problems = Vec::new();
+    match value {
+        Value::Table(table) => search(&mut Vec::new(), table, &mut problems),
+        _ => problems.push(String::from("Configuration must be a table")),
+    }
+    // Every setting in an unknown section fails the same way
+    problems.dedup();
+    problems
+}
+
+/// Applies settings overrides from environment variables to a parsed configuration file.
+///
+/// Variables are named `MOGGER_` followed by the setting's name in upper case, with a double
+/// underscore between section and key, e.g. `MOGGER_DATABASE_URL` or `MOGGER_COOKIE__SECURE`.
+/// Values are parsed as TOML values if the setting accepts them, and used as strings otherwise, so
+/// e.g. a token of `1234` stays a string.
+fn apply_env(
+    value: &mut Value,
+    vars: impl Iterator<Item = (String, String)>,
+) -> Result<(), failure::Error> {
+    for (name, raw) in vars {
+        let key = match name.strip_prefix(ENV_PREFIX) {
+            // MOGGER_CONFIG points to the configuration file and isn't a setting
+            Some("CONFIG") | None => continue,
+            Some(key) => key.to_lowercase(),
+        };
+        let mut table = value
+            .as_table_mut()
+            .ok_or_else(|| failure::err_msg("Configuration must be a table"))?;
+        let mut path: Vec<&str> = key.split("__").collect();
+        let parsed = toml::from_str::<Table>(&format!("value = {}", raw))
+            .ok()
+            .and_then(|mut t| t.remove("value"))
+            .filter(|parsed| {
+                let accepted = |value| setting(&path, value).try_into::<Settings>().is_ok();
+                accepted(parsed.clone()) || !accepted(Value::String(raw.clone()))
+            })
+            .unwrap_or(Value::String(raw));
+        let last = path.pop().unwrap_or_default();
+        for section in path {
+            table = table
+                .entry(section)
+                .or_insert_with(|| Value::Table(Table::new()))
+                .as_table_mut()
+                .ok_or_else(|| failure::format_err!("{}: {} is not a section", name, section))?;
+        }
+        table.insert(last.to_owned(), parsed);
+    }
+    Ok(())
+}
+
+/// Gets the default directory static assets are served from. Is `/usr/share/mogger` if it exists,
+/// otherwise the `STATIC_DIR` compile-time environment variable if defined, otherwise the local
+/// directory `static`.
+fn default_assets_dir() -> &'static str {
+    if Path::new("/usr/share/mogger").is_dir() {
+        "/usr/share/mogger"
+    } else if let Some(compile_env) = option_env!("STATIC_DIR") {
+        compile_env
+    } else {
+        "static"
     }
 }
 
 /// Feature toggles
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
 pub struct Features {
     /// Allow registering an account
     pub signups: bool,
     /// Allow unregistered users to make comments
     pub guest_comments: bool,
+    /// Show the Gravatar of users who haven't uploaded an avatar
+    pub gravatar: bool,
 }
 
 /// Cookie related settings
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
 pub struct Cookie {
     /// Require HTTPS for cookies
     pub secure: bool,
@@ -41,51 +387,649 @@ pub struct Cookie {
     pub domain: Option<String>,
 }
 
-/// Options for comment markdown formatting using comrak
-pub const COMRAK_OPTS: ComrakOptions = ComrakOptions {
-    extension: ComrakExtensionOptions {
-        strikethrough: true,
-        tagfilter: false,
-        table: true,
-        autolink: true,
-        tasklist: false,
-        superscript: false,
-        header_ids: None,
-        footnotes: true,
-        description_lists: false,
-        front_matter_delimiter: None,
-    },
-    parse: ComrakParseOptions {
-        smart: false,
-        default_info_string: None,
-    },
-    render: ComrakRenderOptions {
-        hardbreaks: false,
-        github_pre_lang: true,
-        width: 0,
-        unsafe_: false,
-        escape: true,
-    },
-};
+/// Settings for managed media files
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Media {
+    /// Directory media files are stored in and served from
+    pub dir: String,
+    /// Widths in pixels to generate resized versions of images at
+    pub widths: Vec<u32>,
+    /// Generate WebP versions of resized images
+    pub webp: bool,
+    /// JPEG and WebP quality of resized images
+    pub quality: u8,
+    /// Seconds between checking the media directory for new images while serving. 0 disables
+    /// checking, in which case new images are only processed on startup.
+    pub scan_interval: u64,
+}
+
+impl Default for Media {
+    fn default() -> Self {
+        Media {
+            dir: String::from("media"),
+            widths: vec![480, 960, 1440],
+            webp: true,
+            quality: 80,
+            scan_interval: 30,
+        }
+    }
+}
+
+/// `Cache-Control` header values for each class of route
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Cache {
+    /// HTML pages
+    pub pages: String,
+    /// RSS feeds
+    pub feeds: String,
+    /// JSON API responses
+    pub api: String,
+    /// Static assets and media files
+    pub files: String,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Cache {
+            pages: String::from("private, no-cache"),
+            feeds: String::from("public, max-age=300"),
+            api: String::from("private, no-cache"),
+            files: String::from("public, max-age=86400"),
+        }
+    }
+}
+
+/// Response compression settings
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Compression {
+    /// Compress responses with gzip or brotli when the client accepts it
+    pub enabled: bool,
+    /// Size in bytes below which responses are sent uncompressed
+    pub min_size: usize,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression {
+            enabled: true,
+            min_size: 1024,
+        }
+    }
+}
+
+/// Logging settings
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Log {
+    /// The most verbose level to log at
+    pub level: LevelFilter,
+    /// Output format: plain, json or journald
+    pub format: logging::Format,
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Log {
+            level: LevelFilter::Info,
+            format: logging::Format::Plain,
+        }
+    }
+}
+
+/// Metrics endpoint settings
+#[derive(Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Metrics {
+    /// Serve Prometheus metrics at /metrics
+    pub enabled: bool,
+    /// Address to serve metrics on instead of `host_address`
+    pub address: Option<String>,
+    /// Bearer token required to read metrics
+    pub token: Option<String>,
+}
+
+/// Static assets and theme settings. Values set here take precedence over the theme's.
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    /// Directory the built-in static assets are served from
+    pub assets: String,
+    /// Directory of a theme to apply on top of the built-in look
+    pub dir: Option<String>,
+    /// Site title
+    pub title: Option<String>,
+    /// Values of the CSS color variables, e.g. `accent = "#e8b8ae"`
+    pub colors: BTreeMap<String, String>,
+    /// Extra HTML to add to the end of `<head>`
+    pub head: Option<String>,
+    /// HTML replacing the default header with the logo and title
+    pub header: Option<String>,
+    /// HTML replacing the default footer
+    pub footer: Option<String>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            assets: String::from(default_assets_dir()),
+            dir: None,
+            title: None,
+            colors: BTreeMap::new(),
+            head: None,
+            header: None,
+            footer: None,
+        }
+    }
+}
+
+/// HTTPS settings
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tls {
+    /// Serve HTTPS instead of HTTP on `host_address`
+    pub enabled: bool,
+    /// Path to the PEM encoded certificate chain
+    pub cert: String,
+    /// Path to the PEM encoded private key
+    pub key: String,
+    /// The oldest TLS version to accept
+    pub min_version: TlsVersion,
+    /// Seconds between checking the certificate and key for changes. 0 disables checking, in
+    /// which case they are only reloaded on SIGHUP.
+    pub reload_interval: u64,
+    /// Address to redirect plain HTTP requests to HTTPS from
+    pub redirect_address: Option<String>,
+    /// `Strict-Transport-Security` header value. Not sent if empty.
+    pub hsts: String,
+}
+
+impl Default for Tls {
+    fn default() -> Self {
+        Tls {
+            enabled: false,
+            cert: String::new(),
+            key: String::new(),
+            min_version: TlsVersion::V1_2,
+            reload_interval: 60,
+            redirect_address: None,
+            hsts: String::from("max-age=31536000"),
+        }
+    }
+}
+
+/// Placeholder in a `Content-Security-Policy` replaced with the request's script nonce
+pub const NONCE: &str = "{nonce}";
+
+/// Security headers added to responses. Empty values aren't sent.
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Headers {
+    /// `Content-Security-Policy`. `{nonce}` is replaced with a source expression for the nonce
+    /// given to the inline scripts of the request's page.
+    pub content_security_policy: String,
+    /// `X-Content-Type-Options`
+    pub content_type_options: String,
+    /// `Referrer-Policy`
+    pub referrer_policy: String,
+    /// `X-Frame-Options`
+    pub frame_options: String,
+    /// Have browsers report policy violations to `/csp-report`, where they are logged
+    pub csp_report: bool,
+    /// Headers for paths starting with a prefix, by prefix. These override the headers above, with
+    /// longer prefixes taking precedence.
+    pub routes: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl Default for Headers {
+    fn default() -> Self {
+        Headers {
+            content_security_policy: String::from(
+                "default-src 'self'; script-src 'self' {nonce}; \
+                 style-src 'self' 'unsafe-inline'; img-src 'self' data: https:; \
+                 object-src 'none'; base-uri 'self'; form-action 'self'; frame-ancestors 'none'",
+            ),
+            content_type_options: String::from("nosniff"),
+            referrer_policy: String::from("strict-origin-when-cross-origin"),
+            frame_options: String::from("DENY"),
+            csp_report: true,
+            routes: BTreeMap::new(),
+        }
+    }
+}
 
-/// Options for aritcle markdown formatting using comrak
-pub const COMRAK_ARTICLE_OPTS: ComrakOptions = ComrakOptions {
-    render: ComrakRenderOptions {
-        unsafe_: true,
-        escape: false,
-        ..COMRAK_OPTS.render
-    },
-    ..COMRAK_OPTS
+/// Name of the built-in policy allowing formatting, links, images and tables
+pub const BASIC_POLICY: &str = "basic";
+/// Name of the built-in policy allowing any HTML
+pub const UNRESTRICTED_POLICY: &str = "unrestricted";
+
+static BASIC: Policy = Policy {
+    tags: Vec::new(),
+    attributes: BTreeMap::new(),
+    classes: BTreeMap::new(),
+    iframe_hosts: Vec::new(),
+    unrestricted: false,
+};
+static UNRESTRICTED: Policy = Policy {
+    tags: Vec::new(),
+    attributes: BTreeMap::new(),
+    classes: BTreeMap::new(),
+    iframe_hosts: Vec::new(),
+    unrestricted: true,
 };
 
-pub fn comrak_syntax_adapter() -> SyntectAdapter<'static> {
-    SyntectAdapter::new("base16-ocean.light")
+/// HTML sanitization of articles. Comments always use the basic policy.
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sanitize {
+    /// Policy for articles by members of groups without one of their own
+    pub default: String,
+    /// Policies for articles by the members of a group, by group
+    pub groups: BTreeMap<String, String>,
+    /// Custom policies by name
+    pub policies: BTreeMap<String, Policy>,
+}
+
+impl Default for Sanitize {
+    fn default() -> Self {
+        Sanitize {
+            default: String::from(BASIC_POLICY),
+            groups: BTreeMap::new(),
+            policies: BTreeMap::new(),
+        }
+    }
+}
+
+impl Sanitize {
+    /// Gets a policy by name, either a custom or a built-in one.
+    pub fn policy(&self, name: &str) -> Option<&Policy> {
+        match (self.policies.get(name), name) {
+            (Some(policy), _) => Some(policy),
+            (None, BASIC_POLICY) => Some(&BASIC),
+            (None, UNRESTRICTED_POLICY) => Some(&UNRESTRICTED),
+            (None, _) => None,
+        }
+    }
+
+    /// Gets the policy for articles by members of `group`. Falls back to the basic policy if the
+    /// group is unknown.
+    pub fn for_group(&self, group: Option<&str>) -> &Policy {
+        let name = group
+            .and_then(|group| self.groups.get(group))
+            .unwrap_or(&self.default);
+        self.policy(name).unwrap_or(&BASIC)
+    }
+
+    /// Gets the policy for articles written together by members of `groups`, which only allows
+    /// what all of their policies do.
+    pub fn for_groups<'a, I>(&self, groups: I) -> Cow<'_, Policy>
+    where
+        I: IntoIterator<Item = Option<&'a str>>,
+    {
+        let mut policies = groups.into_iter().map(|group| self.for_group(group));
+        let first = policies.next().unwrap_or_else(|| self.for_group(None));
+        policies.fold(Cow::Borrowed(first), |policy, other| {
+            match std::ptr::eq(&*policy, other) {
+                true => policy,
+                false => Cow::Owned(policy.intersection(other)),
+            }
+        })
+    }
+}
+
+/// A sanitization policy. Custom policies extend the basic one.
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    /// Tags allowed in addition to the basic ones
+    pub tags: Vec<String>,
+    /// Attributes allowed in addition to the basic ones, by tag
+    pub attributes: BTreeMap<String, Vec<String>>,
+    /// Classes allowed, by tag. Others are removed from `class` attributes.
+    pub classes: BTreeMap<String, Vec<String>>,
+    /// Hosts `<iframe>`s may be embedded from. Iframes are removed if empty.
+    pub iframe_hosts: Vec<String>,
+    /// Allow any HTML
+    pub unrestricted: bool,
+}
+
+impl Policy {
+    /// Gets a policy allowing only what both this one and `other` allow.
+    pub fn intersection(&self, other: &Policy) -> Policy {
+        match (self.unrestricted, other.unrestricted) {
+            (true, _) => return other.clone(),
+            (_, true) => return self.clone(),
+            _ => (),
+        }
+        let common = |a: &[String], b: &[String]| -> Vec<String> {
+            a.iter().filter(|item| b.contains(item)).cloned().collect()
+        };
+        type ByTag = BTreeMap<String, Vec<String>>;
+        let common_by_tag = |a: &ByTag, b: &ByTag| -> ByTag {
+            a.iter()
+                .filter_map(|(tag, items)| Some((tag.clone(), common(items, b.get(tag)?))))
+                .filter(|(_, items)| !items.is_empty())
+                .collect()
+        };
+        Policy {
+            tags: common(&self.tags, &other.tags),
+            attributes: common_by_tag(&self.attributes, &other.attributes),
+            classes: common_by_tag(&self.classes, &other.classes),
+            iframe_hosts: common(&self.iframe_hosts, &other.iframe_hosts),
+            unrestricted: false,
+        }
+    }
+}
+
+/// A TLS protocol version
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(try_from = "Value")]
+pub enum TlsVersion {
+    V1_2,
+    V1_3,
+}
+
+impl TryFrom<Value> for TlsVersion {
+    type Error = String;
+
+    // Accepts numbers as well so versions don't have to be quoted
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let version = match value {
+            Value::String(s) => s,
+            other => other.to_string(),
+        };
+        match version.as_str() {
+            "1.2" => Ok(TlsVersion::V1_2),
+            "1.3" => Ok(TlsVersion::V1_3),
+            _ => Err(format!(
+                "unsupported TLS version {}, expected 1.2 or 1.3",
+                version
+            )),
+        }
+    }
+}
+
+/// Markdown rendering settings
+#[derive(Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Markdown {
+    /// Extensions enabled for articles
+    pub articles: Extensions,
+    /// Extensions enabled for comments
+    pub comments: Extensions,
+    /// Syntax highlighting of code blocks in articles
+    pub highlight: Highlight,
+    /// Render `$inline$` and `$$display$$` LaTeX math in articles to MathML
+    pub math: bool,
+    /// Rendering of code blocks in articles to diagrams
+    pub diagrams: Diagrams,
+}
+
+/// Markdown extensions and rendering options
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Extensions {
+    /// `~~strikethrough~~`
+    pub strikethrough: bool,
+    /// GitHub style tables
+    pub table: bool,
+    /// Turn bare urls into links
+    pub autolink: bool,
+    /// `- [x]` task list items
+    pub tasklist: bool,
+    /// `^superscript^`
+    pub superscript: bool,
+    /// `[^1]` footnotes
+    pub footnotes: bool,
+    /// Description lists, with definitions starting with `: `
+    pub description_lists: bool,
+    /// Give headers ids and permalinks. Articles with a table of contents always get them.
+    pub header_ids: bool,
+    /// Turn quotes, dashes and ellipses into their typographic versions
+    pub smart: bool,
+    /// Render line breaks in paragraphs as `<br>`
+    pub hardbreaks: bool,
+}
+
+impl Default for Extensions {
+    fn default() -> Self {
+        Extensions {
+            strikethrough: true,
+            table: true,
+            autolink: true,
+            tasklist: false,
+            superscript: false,
+            footnotes: true,
+            description_lists: false,
+            header_ids: false,
+            smart: false,
+            hardbreaks: false,
+        }
+    }
+}
+
+impl Extensions {
+    /// Gets the comrak options for these settings. Raw HTML is passed through if `raw_html` is
+    /// true, and escaped otherwise.
+    pub fn options(&self, raw_html: bool) -> ComrakOptions {
+        ComrakOptions {
+            extension: ComrakExtensionOptions {
+                strikethrough: self.strikethrough,
+                tagfilter: false,
+                table: self.table,
+                autolink: self.autolink,
+                tasklist: self.tasklist,
+                superscript: self.superscript,
+                header_ids: self.header_ids.then(String::new),
+                footnotes: self.footnotes,
+                description_lists: self.description_lists,
+                front_matter_delimiter: None,
+            },
+            parse: ComrakParseOptions {
+                smart: self.smart,
+                default_info_string: None,
+            },
+            render: ComrakRenderOptions {
+                hardbreaks: self.hardbreaks,
+                github_pre_lang: true,
+                width: 0,
+                unsafe_: raw_html,
+                escape: !raw_html,
+            },
+        }
+    }
+}
+
+/// Syntax highlighting settings
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Highlight {
+    /// Highlight code blocks
+    pub enabled: bool,
+    /// Name of the built-in theme whose colors are set inline
+    pub theme: String,
+    /// Mark code up with CSS classes instead of inline colors, leaving the colors to the site's
+    /// stylesheets
+    pub classes: bool,
+}
+
+impl Default for Highlight {
+    fn default() -> Self {
+        Highlight {
+            enabled: true,
+            theme: String::from("base16-ocean.light"),
+            classes: false,
+        }
+    }
+}
+
+/// Diagram rendering settings
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Diagrams {
+    /// Commands rendering code blocks of a language to SVG, by language. The source is written to
+    /// the command's standard input and the SVG read from its standard output.
+    pub renderers: BTreeMap<String, Vec<String>>,
+    /// Directory rendered diagrams are cached in, named by a hash of their source
+    pub cache: String,
+    /// Seconds a renderer may run for before it's killed
+    pub timeout: u64,
+    /// Most code blocks rendered as diagrams per article or page. Later ones are shown as code.
+    pub limit: usize,
 }
 
-pub fn comrak_plugins<'a>(adapter: &'a SyntectAdapter) -> ComrakPlugins<'a> {
-    ComrakPlugins {
-        render: ComrakRenderPlugins {
-            codefence_syntax_highlighter: Some(adapter),
-        },
+impl Default for Diagrams {
+    fn default() -> Self {
+        Diagrams {
+            renderers: BTreeMap::new(),
+            cache: String::from("diagrams"),
+            timeout: 10,
+            limit: 20,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use toml::Value;
+
+    use super::{apply_env, invalid_settings, Sanitize, Settings, TlsVersion};
+
+    #[test]
+    fn env_overrides() {
+        let mut value: Value = toml::from_str(
+            "database_url = \"postgres://file\"\n[cookie]\nsecure = false\n[media]\nquality = 80",
+        )
+        .unwrap();
+        let vars = vec![
+            ("MOGGER_DATABASE_URL", "postgres://env"),
+            ("MOGGER_COOKIE__SECURE", "true"),
+            ("MOGGER_MEDIA__WIDTHS", "[320, 640]"),
+            ("MOGGER_METRICS__TOKEN", "\"1234\""),
+            ("MOGGER_MEDIA__QUALITY", "90"),
+            ("MOGGER_HOST_ADDRESS", "0.0.0.0:80"),
+            ("MOGGER_CONFIG", "/etc/mogger.toml"),
+            ("PATH", "/bin"),
+        ];
+        let vars = vars.into_iter().map(|(k, v)| (k.to_owned(), v.to_owned()));
+        apply_env(&mut value, vars).unwrap();
+        let settings: Settings = value.try_into().unwrap();
+
+        assert_eq!(settings.database_url, "postgres://env");
+        assert_eq!(settings.host_address, "0.0.0.0:80");
+        assert!(settings.cookie.secure);
+        assert_eq!(settings.media.widths, vec![320, 640]);
+        assert_eq!(settings.media.quality, 90);
+        assert_eq!(settings.metrics.token.as_deref(), Some("1234"));
+        assert!(settings.validate().is_empty());
+    }
+
+    #[test]
+    fn env_strings() {
+        let mut value = Value::Table(Default::default());
+        let vars = vec![
+            ("MOGGER_METRICS__TOKEN", "1234"),
+            ("MOGGER_COOKIE__DOMAIN", "true"),
+            ("MOGGER_DATABASE_URL", "1.5"),
+            ("MOGGER_MEDIA__QUALITY", "[90]"),
+        ];
+        let vars = vars.into_iter().map(|(k, v)| (k.to_owned(), v.to_owned()));
+        apply_env(&mut value, vars).unwrap();
+
+        // Values the setting doesn't accept either way are reported as they were written
+        assert!(value["media"]["quality"].is_array());
+        value["media"].as_table_mut().unwrap().remove("quality");
+        let settings: Settings = value.try_into().unwrap();
+        assert_eq!(settings.metrics.token.as_deref(), Some("1234"));
+        assert_eq!(settings.cookie.domain.as_deref(), Some("true"));
+        assert_eq!(settings.database_url, "1.5");
+    }
+
+    #[test]
+    fn invalid() {
+        let mut value: Value = toml::from_str(
+            "databse_url = \"postgres://file\"\n[media]\nquality = \"high\"\nqualty = 80\n\
+             widths = [480]\n[medai]\ndir = \"a\"\nwebp = true\n[tls]\nmin_version = 1.5",
+        )
+        .unwrap();
+        let vars = vec![(String::from("MOGGER_LOG__LEVL"), String::from("info"))];
+        apply_env(&mut value, vars.into_iter()).unwrap();
+        assert!(value.clone().try_into::<Settings>().is_err());
+
+        let problems = invalid_settings(&value);
+        let expected = [
+            "unknown field `databse_url`",
+            "unknown field `levl`",
+            "unknown field `medai`",
+            "invalid type: string \"high\", expected u8 for key `media.quality`",
+            "unknown field `qualty`",
+            "unsupported TLS version 1.5, expected 1.2 or 1.3 for key `tls.min_version`",
+        ];
+        assert_eq!(problems.len(), expected.len(), "{:?}", problems);
+        for (problem, expected) in problems.iter().zip(&expected) {
+            assert!(problem.starts_with(expected), "{}", problem);
+        }
+    }
+
+    #[test]
+    fn validation() {
+        let mut settings: Settings = toml::from_str("host_address = \"localhost\"").unwrap();
+        settings.media.quality = 0;
+        settings.cache.pages = String::from("no-cache\n");
+        assert_eq!(
+            settings.validate(),
+            vec![
+                "database_url must be set",
+                "host_address 'localhost' is not of the form host:port",
+                "media.quality must be between 1 and 100",
+                "cache.pages is not a valid header value",
+            ]
+        );
+    }
+
+    #[test]
+    fn group_policies() {
+        let sanitize: Sanitize = toml::from_str(
+            "[groups]\nadmin = \"unrestricted\"\neditor = \"media\"\nguest = \"video\"\n\
+             [policies.media]\ntags = [\"video\", \"audio\"]\niframe_hosts = [\"example.com\"]\n\
+             attributes = { video = [\"src\", \"controls\"], audio = [\"src\"] }\n\
+             [policies.video]\ntags = [\"video\"]\nattributes = { video = [\"src\", \"loop\"] }",
+        )
+        .unwrap();
+        assert!(sanitize.for_groups(vec![Some("admin")]).unrestricted);
+        let policy = sanitize.for_groups(vec![Some("admin"), Some("editor"), Some("admin")]);
+        assert_eq!(policy.tags, vec!["video", "audio"]);
+
+        let policy = sanitize.for_groups(vec![Some("editor"), Some("guest")]);
+        assert!(!policy.unrestricted);
+        assert_eq!(policy.tags, vec!["video"]);
+        assert_eq!(policy.attributes.len(), 1);
+        assert_eq!(policy.attributes["video"], vec!["src"]);
+        assert!(policy.iframe_hosts.is_empty());
+
+        // Anyone with the basic policy takes everything else away
+        let policy = sanitize.for_groups(vec![Some("admin"), None]);
+        assert!(!policy.unrestricted && policy.tags.is_empty());
+    }
+
+    #[test]
+    fn tls_version() {
+        let parse = |s| toml::from_str::<Settings>(s).map(|s| s.tls.min_version);
+        assert_eq!(parse("[tls]\nmin_version = 1.3").unwrap(), TlsVersion::V1_3);
+        assert_eq!(
+            parse("[tls]\nmin_version = \"1.2\"").unwrap(),
+            TlsVersion::V1_2
+        );
+        assert!(parse("[tls]\nmin_version = 1.1").is_err());
+
+        let settings: Settings =
+            toml::from_str("database_url = \"x\"\n[tls]\nredirect_address = \"localhost:80\"")
+                .unwrap();
+        assert_eq!(
+            settings.validate(),
+            vec!["tls.redirect_address requires tls.enabled"]
+        );
     }
 }
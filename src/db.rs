@@ -1,7 +1,15 @@
-use diesel_migrations::embed_migrations;
+use diesel::{connection::SimpleConnection, sql_types::Text, Connection as _, RunQueryDsl};
+use diesel_migrations::{embed_migrations, MigrationConnection};
 use gotham::state::{FromState, StateData};
 
-use std::sync::{Arc, Mutex, MutexGuard};
+use std::{
+    collections::HashSet,
+    ops::Deref,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Instant,
+};
+
+use crate::metrics;
 
 pub use diesel::pg::PgConnection as Connection;
 
@@ -9,6 +17,19 @@ pub type DieselResult<T> = Result<T, diesel::result::Error>;
 
 embed_migrations!();
 
+/// A migration embedded in the binary
+pub struct Migration {
+    /// The version diesel records the migration as applied under
+    pub version: &'static str,
+    /// The name of the migration's directory
+    pub name: &'static str,
+    /// The SQL that reverts the migration
+    down: &'static str,
+}
+
+// Defines MIGRATIONS, the list of embedded migrations ordered by version.
+include!(concat!(env!("OUT_DIR"), "/migrations.rs"));
+
 /// The wrapper for a database connection that can shared via gotham's state data
 #[derive(Clone, StateData)]
 pub struct DbConnection {
@@ -22,9 +43,7 @@ impl DbConnection {
         }
     }
 
-    pub fn from_state(
-        state: &gotham::state::State,
-    ) -> Result<MutexGuard<Connection>, failure::Error> {
+    pub fn from_state(state: &gotham::state::State) -> Result<ConnectionGuard<'_>, failure::Error> {
         Self::borrow_from(state).lock()
     }
 
@@ -32,19 +51,92 @@ impl DbConnection {
         self.connection.clone()
     }
 
-    pub fn lock(&self) -> Result<MutexGuard<Connection>, failure::Error> {
+    pub fn lock(&self) -> Result<ConnectionGuard<'_>, failure::Error> {
+        let start = Instant::now();
         match self.connection.lock() {
-            Ok(lock) => Ok(lock),
+            Ok(guard) => {
+                metrics::DB_LOCK_WAIT.observe(start.elapsed().as_secs_f64());
+                Ok(ConnectionGuard {
+                    guard,
+                    acquired: Instant::now(),
+                })
+            }
             Err(_) => Err(failure::err_msg("failed to get lock")),
         }
     }
 }
 
+/// A locked database connection. Records how long it was held when dropped.
+pub struct ConnectionGuard<'a> {
+    guard: MutexGuard<'a, Connection>,
+    acquired: Instant,
+}
+
+impl Deref for ConnectionGuard<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        &self.guard
+    }
+}
+
+impl Drop for ConnectionGuard<'_> {
+    fn drop(&mut self) {
+        metrics::DB_LOCK_HOLD.observe(self.acquired.elapsed().as_secs_f64());
+    }
+}
+
 pub fn connect(url: &str) -> Result<Connection, failure::Error> {
-    let connection = diesel::Connection::establish(url)?;
+    Ok(diesel::Connection::establish(url)?)
+}
+
+/// Runs all pending migrations.
+pub fn migrate(connection: &Connection) -> Result<(), failure::Error> {
+    let mut output = Vec::new();
+    embedded_migrations::run_with_output(connection, &mut output)?;
+    for line in String::from_utf8_lossy(&output).lines() {
+        log::info!("{}", line);
+    }
+    Ok(())
+}
 
-    // Run migrations.
-    embedded_migrations::run_with_output(&connection, &mut std::io::stdout())?;
+/// Reverts the most recently applied migration. Returns the reverted migration, or `None` if no
+/// migrations have been applied.
+pub fn revert_migration(
+    connection: &Connection,
+) -> Result<Option<&'static Migration>, failure::Error> {
+    let applied = applied_migrations(connection)?;
+    let migration = match MIGRATIONS
+        .iter()
+        .rev()
+        .find(|m| applied.contains(m.version))
+    {
+        Some(migration) => migration,
+        None => return Ok(None),
+    };
+    connection.transaction::<_, diesel::result::Error, _>(|| {
+        connection.batch_execute(migration.down)?;
+        diesel::sql_query("DELETE FROM __diesel_schema_migrations WHERE version = $1")
+            .bind::<Text, _>(migration.version)
+            .execute(connection)?;
+        Ok(())
+    })?;
+    log::info!("Reverted migration {}", migration.name);
+    Ok(Some(migration))
+}
+
+/// Gets the versions of the migrations that have been applied to the database.
+pub fn applied_migrations(connection: &Connection) -> DieselResult<HashSet<String>> {
+    // Creates the table diesel records applied migrations in if it doesn't exist yet
+    diesel_migrations::setup_database(connection)?;
+    connection.previously_run_migration_versions()
+}
 
-    Ok(connection)
+/// Gets the embedded migrations that haven't been applied to the database.
+pub fn pending_migrations(connection: &Connection) -> DieselResult<Vec<&'static Migration>> {
+    let applied = applied_migrations(connection)?;
+    Ok(MIGRATIONS
+        .iter()
+        .filter(|m| !applied.contains(m.version))
+        .collect())
 }
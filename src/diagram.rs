@@ -0,0 +1,372 @@
+//! Rendering of code blocks to inline SVG diagrams with local programs, like Graphviz's `dot`.
+//!
+//! Rendered diagrams are cached on disk by a hash of the renderer and source, so they're only
+//! rendered again when they change. The server renders them on a background thread, so no request
+//! waits for a renderer, and removes cached diagrams no article uses anymore when it starts.
+
+use ammonia::Builder;
+use sha2::{Digest, Sha256};
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{mpsc, Arc, Mutex, PoisonError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::config::Diagrams;
+
+/// SVG elements kept in rendered diagrams
+const SVG_TAGS: &[&str] = &[
+    "circle", "desc", "ellipse", "g", "line", "path", "polygon", "polyline", "rect", "svg", "text",
+    "title", "tspan",
+];
+
+/// Presentation attributes kept in rendered diagrams
+const SVG_ATTRIBUTES: &[&str] = &[
+    "class",
+    "cx",
+    "cy",
+    "d",
+    "dominant-baseline",
+    "fill",
+    "fill-opacity",
+    "font-family",
+    "font-size",
+    "font-style",
+    "font-weight",
+    "height",
+    "id",
+    "opacity",
+    "points",
+    "r",
+    "rx",
+    "ry",
+    "stroke",
+    "stroke-dasharray",
+    "stroke-linecap",
+    "stroke-linejoin",
+    "stroke-opacity",
+    "stroke-width",
+    "text-anchor",
+    "transform",
+    "viewBox",
+    "width",
+    "x",
+    "x1",
+    "x2",
+    "y",
+    "y1",
+    "y2",
+];
+
+/// Removes everything but drawing from an SVG image, so it can't run scripts or load resources.
+fn clean(svg: &str) -> String {
+    let start = svg.find("<svg").unwrap_or(0);
+    Builder::empty()
+        .tags(SVG_TAGS.iter().copied().collect::<HashSet<_>>())
+        .generic_attributes(SVG_ATTRIBUTES.iter().copied().collect::<HashSet<_>>())
+        .clean(&svg[start..])
+        .to_string()
+}
+
+/// Runs `command` with `input` as its standard input, killing it if it runs for longer than
+/// `timeout`.
+fn run(command: &[String], input: &str, timeout: Duration) -> Result<String, failure::Error> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| failure::err_msg("Empty command"))?;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // The pipes are handled on their own threads so a full one can't block the child
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = input.to_owned();
+    thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let output = thread::spawn(move || {
+        let mut output = String::new();
+        stdout.read_to_string(&mut output).map(|_| output)
+    });
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let errors = thread::spawn(move || {
+        let mut errors = String::new();
+        stderr.read_to_string(&mut errors).map(|_| errors)
+    });
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() > deadline {
+            child.kill()?;
+            child.wait()?;
+            return Err(failure::format_err!(
+                "{} timed out after {} seconds",
+                program,
+                timeout.as_secs()
+            ));
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    let output = output.join().expect("output reader panicked")?;
+    if !status.success() {
+        let errors = errors.join().expect("error reader panicked")?;
+        return Err(failure::format_err!(
+            "{} failed with {}: {}",
+            program,
+            status,
+            errors.trim()
+        ));
+    }
+    Ok(output)
+}
+
+/// A code block rendered as a diagram
+pub enum Diagram {
+    /// The rendered SVG
+    Svg(String),
+    /// Why the renderer failed
+    Failed(String),
+    /// Waiting to be rendered in the background
+    Pending,
+}
+
+/// Gets the file a diagram rendered with `command` is cached in.
+fn cache_path(settings: &Diagrams, command: &[String], source: &str) -> PathBuf {
+    let mut hasher = Sha256::new();
+    for arg in command {
+        hasher.update(arg);
+        hasher.update([0]);
+    }
+    hasher.update(source);
+    Path::new(&settings.cache).join(format!("{:x}.svg", hasher.finalize()))
+}
+
+/// Gets a diagram from the cache, or renders and caches it.
+fn render_cached(
+    settings: &Diagrams,
+    command: &[String],
+    source: &str,
+    path: &Path,
+) -> Result<String, failure::Error> {
+    if let Ok(svg) = fs::read_to_string(path) {
+        return Ok(clean(&svg));
+    }
+    let svg = run(command, source, Duration::from_secs(settings.timeout)).map_err(|e| {
+        log::warn!("Failed to render diagram with {}: {}", command[0], e);
+        e
+    })?;
+    // Caching is only an optimization, so failing to store a diagram isn't an error
+    if let Err(e) = fs::create_dir_all(&settings.cache).and_then(|_| fs::write(path, &svg)) {
+        log::warn!("Failed to cache diagram at {}: {}", path.display(), e);
+    }
+    Ok(clean(&svg))
+}
+
+/// Renders a code block of `language` to SVG, returning `None` if there's no renderer for it.
+pub fn render(settings: &Diagrams, language: &str, source: &str) -> Option<Diagram> {
+    let command = settings.renderers.get(language)?;
+    let path = cache_path(settings, command, source);
+    Some(match render_cached(settings, command, source, &path) {
+        Ok(svg) => Diagram::Svg(svg),
+        Err(e) => Diagram::Failed(e.to_string()),
+    })
+}
+
+/// A diagram waiting to be rendered
+struct Job {
+    path: PathBuf,
+    command: Vec<String>,
+    source: String,
+}
+
+/// Renders diagrams one at a time on a background thread. Failures are remembered until the
+/// server restarts, so a broken diagram isn't rendered again every time it's viewed.
+#[derive(Clone)]
+pub struct Background {
+    settings: Diagrams,
+    queue: Arc<Mutex<mpsc::Sender<Job>>>,
+    /// Diagrams that aren't cached, by cache file. `None` while they're waiting to be rendered,
+    /// and the error once they've failed.
+    uncached: Arc<Mutex<HashMap<PathBuf, Option<String>>>>,
+}
+
+impl Background {
+    pub fn new(settings: &Diagrams) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let uncached = Arc::new(Mutex::new(HashMap::new()));
+        let worker = (settings.clone(), uncached.clone());
+        // The thread stops once every sender is dropped
+        thread::spawn(move || {
+            let (settings, uncached) = worker;
+            for job in receiver {
+                let result = render_cached(&settings, &job.command, &job.source, &job.path);
+                let mut uncached = uncached.lock().unwrap_or_else(PoisonError::into_inner);
+                match result {
+                    Ok(_) => uncached.remove(&job.path),
+                    Err(e) => uncached.insert(job.path, Some(e.to_string())),
+                };
+            }
+        });
+        Background {
+            settings: settings.clone(),
+            queue: Arc::new(Mutex::new(sender)),
+            uncached,
+        }
+    }
+
+    /// Gets a code block of `language` from the cache, queueing it to be rendered if it isn't
+    /// cached yet. Returns `None` if there's no renderer for the language.
+    pub fn render(&self, language: &str, source: &str) -> Option<Diagram> {
+        let command = self.settings.renderers.get(language)?;
+        let path = cache_path(&self.settings, command, source);
+        if let Ok(svg) = fs::read_to_string(&path) {
+            return Some(Diagram::Svg(clean(&svg)));
+        }
+        let mut uncached = self.uncached.lock().unwrap_or_else(PoisonError::into_inner);
+        Some(match uncached.get(&path) {
+            Some(Some(error)) => Diagram::Failed(error.clone()),
+            Some(None) => Diagram::Pending,
+            None => {
+                let job = Job {
+                    path: path.clone(),
+                    command: command.clone(),
+                    source: source.to_owned(),
+                };
+                let queue = self.queue.lock().unwrap_or_else(PoisonError::into_inner);
+                match queue.send(job) {
+                    Ok(()) => {
+                        uncached.insert(path, None);
+                        Diagram::Pending
+                    }
+                    Err(_) => Diagram::Failed(String::from("The diagram renderer stopped")),
+                }
+            }
+        })
+    }
+}
+
+/// Removes the cached diagrams that aren't among `diagrams`, given by language and source.
+/// Returns how many were removed.
+pub fn prune<'a, I>(settings: &Diagrams, diagrams: I) -> io::Result<usize>
+where
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    let keep: HashSet<_> = diagrams
+        .into_iter()
+        .filter_map(|(language, source)| {
+            let command = settings.renderers.get(language)?;
+            Some(cache_path(settings, command, source))
+        })
+        .collect();
+    let entries = match fs::read_dir(&settings.cache) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut removed = 0;
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == "svg") && !keep.contains(&path) {
+            fs::remove_file(&path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use std::{thread, time::Duration};
+
+    use super::{clean, prune, render, Background, Diagram};
+    use crate::config::Diagrams;
+
+    #[test]
+    fn cleaning() {
+        let svg = clean(
+            "<?xml version=\"1.0\"?>\n<!-- comment -->\n\
+             <svg width=\"62pt\" viewBox=\"0 0 62 116\" onload=\"alert(1)\">\
+             <script>alert(1)</script><a xlink:href=\"javascript:alert(1)\">\
+             <text x=\"27\" font-size=\"14.00\">a</text></a>\
+             <foreignObject><iframe src=\"https://example.com\"></iframe></foreignObject></svg>",
+        );
+        assert_eq!(
+            svg,
+            "<svg width=\"62pt\" viewBox=\"0 0 62 116\">\
+             <text x=\"27\" font-size=\"14.00\">a</text></svg>"
+        );
+    }
+
+    #[test]
+    fn rendering() {
+        let cache = std::env::temp_dir().join(format!("mogger-diagrams-{}", std::process::id()));
+        let mut renderers = BTreeMap::new();
+        renderers.insert(String::from("svg"), vec![String::from("cat")]);
+        renderers.insert(
+            String::from("fail"),
+            vec![
+                String::from("sh"),
+                String::from("-c"),
+                String::from("exit 3"),
+            ],
+        );
+        let settings = Diagrams {
+            renderers,
+            cache: cache.to_string_lossy().into_owned(),
+            ..Diagrams::default()
+        };
+
+        let svg = "<svg><rect width=\"1\"></rect></svg>";
+        assert!(matches!(render(&settings, "svg", svg), Some(Diagram::Svg(s)) if s == svg));
+        assert_eq!(std::fs::read_dir(&cache).unwrap().count(), 1);
+        assert!(matches!(
+            render(&settings, "fail", ""),
+            Some(Diagram::Failed(_))
+        ));
+        assert!(render(&settings, "rust", "").is_none());
+
+        // Diagrams are pending until the background thread has rendered them
+        let background = Background::new(&settings);
+        let other = "<svg></svg>";
+        assert!(matches!(
+            background.render("svg", other),
+            Some(Diagram::Pending)
+        ));
+        assert!(matches!(
+            background.render("fail", ""),
+            Some(Diagram::Pending)
+        ));
+        let mut tries = 0;
+        while !matches!(background.render("fail", ""), Some(Diagram::Failed(_))) {
+            assert!(tries < 500, "diagram wasn't rendered in the background");
+            thread::sleep(Duration::from_millis(10));
+            tries += 1;
+        }
+        assert!(matches!(background.render("svg", other), Some(Diagram::Svg(s)) if s == other));
+        assert!(background.render("rust", "").is_none());
+
+        // Only diagrams that are still used are kept
+        assert_eq!(prune(&settings, vec![("svg", svg)]).unwrap(), 1);
+        assert_eq!(std::fs::read_dir(&cache).unwrap().count(), 1);
+        assert!(matches!(
+            render(&settings, "svg", svg),
+            Some(Diagram::Svg(_))
+        ));
+        std::fs::remove_dir_all(&cache).unwrap();
+        assert_eq!(prune(&settings, vec![]).unwrap(), 0);
+    }
+}
@@ -1,22 +1,35 @@
 //! Templates and request handlers for endpoints related to displaying articles
 use askama::Template;
+use diesel::Connection as _;
 use gotham::{
-    helpers::http::response::{create_empty_response, create_temporary_redirect as temp_redirect},
-    hyper::StatusCode,
+    helpers::http::response::{
+        create_empty_response, create_response, create_temporary_redirect as temp_redirect,
+    },
+    hyper::{
+        header::{HeaderValue, CONTENT_DISPOSITION},
+        StatusCode,
+    },
+    mime::Mime,
     state::{FromState, State},
 };
 
 use crate::{
     article::{self, Article, ArticleChanges, NewArticle},
+    caching,
     comment::{self, Comment},
     config::Settings,
     db::{Connection, DbConnection},
     document::{DocumentResult, TemplateExt},
     handler::articles::{ArticleIdPath, ArticlePath},
+    render::Renderer,
+    sanitize,
+    security::Nonce,
+    series::{self, Assignment, Position, Series},
+    theme::Theme,
     user::{
         Permission,
         Permission::{CreateArticle, EditArticle, EditForeignArticle},
-        Session,
+        Session, User,
     },
 };
 
@@ -24,11 +37,39 @@ use crate::{
 #[template(path = "article.html", escape = "none")]
 pub struct ArticleTemplate<'a> {
     article: Article,
-    author_name: String,
+    /// The author and co-authors
+    authors: Vec<User>,
     comments: Vec<CommentTemplate<'a>>,
     session: Option<&'a Session>,
     connection: &'a Connection,
+    theme: &'a Theme,
+    renderer: &'a Renderer,
     can_comment: bool,
+    series: Option<Position>,
+    /// Whether to show the Gravatar of authors without an avatar
+    gravatar: bool,
+}
+
+impl ArticleTemplate<'_> {
+    /// Escapes text written by authors, as this template doesn't escape anything by default.
+    fn escape(&self, text: &str) -> String {
+        sanitize::escape(text)
+    }
+
+    /// Makes the byline linking to the authors' profiles.
+    fn byline(&self, authors: &[User]) -> String {
+        article::byline_html(authors)
+    }
+
+    /// Renders an author's bio for their card under the article.
+    fn bio(&self, author: &User) -> String {
+        self.renderer.comment(&author.bio)
+    }
+
+    /// Gets the link to the page listing the articles with a tag.
+    fn tag_path(&self, tag: &str) -> String {
+        article::tag_path(tag)
+    }
 }
 
 #[derive(Template)]
@@ -37,6 +78,7 @@ pub struct CommentTemplate<'a> {
     pub comment: &'a Comment,
     pub children: Vec<CommentTemplate<'a>>,
     pub connection: &'a Connection,
+    pub renderer: &'a Renderer,
     pub session: Option<&'a Session>,
     pub can_comment: bool,
 }
@@ -45,6 +87,7 @@ impl<'a> CommentTemplate<'a> {
     pub fn from_node(
         tree: &'a comment::Node,
         connection: &'a Connection,
+        renderer: &'a Renderer,
         session: Option<&'a Session>,
         can_comment: bool,
     ) -> Self {
@@ -53,9 +96,12 @@ impl<'a> CommentTemplate<'a> {
             children: tree
                 .children
                 .iter()
-                .map(|child| CommentTemplate::from_node(child, connection, session, can_comment))
+                .map(|child| {
+                    CommentTemplate::from_node(child, connection, renderer, session, can_comment)
+                })
                 .collect(),
             connection,
+            renderer,
             session,
             can_comment,
         }
@@ -64,6 +110,7 @@ impl<'a> CommentTemplate<'a> {
     pub fn from_list(
         list: &'a [Comment],
         connection: &'a Connection,
+        renderer: &'a Renderer,
         session: Option<&'a Session>,
         can_comment: bool,
     ) -> Vec<Self> {
@@ -72,6 +119,7 @@ impl<'a> CommentTemplate<'a> {
                 comment,
                 children: Vec::new(),
                 connection,
+                renderer,
                 session,
                 can_comment,
             })
@@ -84,7 +132,23 @@ impl<'a> CommentTemplate<'a> {
 struct EditTemplate<'a> {
     session: Option<&'a Session>,
     connection: &'a Connection,
+    theme: &'a Theme,
+    nonce: &'a str,
     article: Option<Article>,
+    /// Title of the article's series
+    series: String,
+    /// All series, to choose from
+    series_list: Vec<Series>,
+    /// Usernames of the article's co-authors, separated by commas
+    coauthors: String,
+}
+
+/// The co-authors entered in the edit form
+#[derive(Deserialize)]
+struct CoauthorsForm {
+    /// Usernames separated by commas
+    #[serde(default)]
+    coauthors: String,
 }
 
 /// Display an article
@@ -92,7 +156,9 @@ pub fn view(state: &State) -> DocumentResult {
     let connection = &DbConnection::from_state(state)?;
     let id = &ArticlePath::borrow_from(state).id;
     let session = Session::try_borrow_from(state);
-    let can_comment = Settings::borrow_from(state).features.guest_comments || session.is_some();
+    let renderer = Renderer::borrow_from(state);
+    let features = &Settings::borrow_from(state).features;
+    let can_comment = features.guest_comments || session.is_some();
 
     let article = article::view(connection, id)?;
     // Return a 404 if the user isn't allowed to view the article
@@ -100,22 +166,57 @@ pub fn view(state: &State) -> DocumentResult {
         return Ok(create_empty_response(state, StatusCode::NOT_FOUND));
     }
 
+    // The page changes when the article is edited or a comment is added
+    let last_modified = comment::last_submitted(connection, article.id)?
+        .map_or(article.updated_at, |date| date.max(article.updated_at));
+
     let comments = comment::list(connection, article.id)?;
     let comments_template = comments
         .iter()
-        .map(|child| CommentTemplate::from_node(child, connection, session, can_comment))
+        .map(|child| CommentTemplate::from_node(child, connection, renderer, session, can_comment))
         .collect();
-    let author = article.user(connection)?;
+    let authors = article.authors(connection)?;
+    let series = series::position(connection, &article)?;
     // true if logged in or guest comments permitted
     let template = ArticleTemplate {
         article,
-        author_name: author.name,
+        series,
+        authors,
         comments: comments_template,
         session,
         connection,
+        theme: Theme::borrow_from(state),
+        renderer,
         can_comment,
+        gravatar: features.gravatar,
     };
-    let response = template.to_response(state);
+    let mut response = template.to_response(state);
+    caching::set_last_modified(&mut response, last_modified);
+    Ok(response)
+}
+
+/// Download an article as markdown with front matter
+pub fn export(state: &State) -> DocumentResult {
+    let connection = &DbConnection::from_state(state)?;
+    let id = &ArticlePath::borrow_from(state).id;
+    let session = Session::try_borrow_from(state);
+
+    let article = article::view(connection, id)?;
+    if !article.viewable(session, connection)? {
+        return Ok(create_empty_response(state, StatusCode::NOT_FOUND));
+    }
+
+    let mut response = create_response(
+        state,
+        StatusCode::OK,
+        "text/markdown; charset=utf-8".parse::<Mime>()?,
+        article.markdown(),
+    );
+    let disposition = format!("attachment; filename=\"{}.md\"", article.url);
+    if let Ok(value) = HeaderValue::from_str(&disposition) {
+        response.headers_mut().insert(CONTENT_DISPOSITION, value);
+    }
+    caching::set_last_modified(&mut response, article.updated_at);
     Ok(response)
 }
 
@@ -125,43 +226,89 @@ pub fn edit(state: &State) -> DocumentResult {
         Some(path) => Some(article::view(connection, &path.id.to_string())?),
         None => None,
     };
-    Ok(EditTemplate {
+    let series_list = series::list(connection)?;
+    let series = article
+        .as_ref()
+        .and_then(|article| article.series)
+        .and_then(|id| series_list.iter().find(|s| s.id == id))
+        .map(|s| s.title.clone())
+        .unwrap_or_default();
+    let coauthors = match &article {
+        Some(article) => article::coauthors(connection, article.id)?.join(", "),
+        None => String::new(),
+    };
+    let mut response = EditTemplate {
         session: Session::try_borrow_from(state),
         connection,
+        theme: Theme::borrow_from(state),
+        nonce: &Nonce::borrow_from(state).0,
         article,
+        series,
+        series_list,
+        coauthors,
     }
-    .to_response(state))
+    .to_response(state);
+    caching::set_no_store(&mut response);
+    Ok(response)
 }
 
 pub fn edit_post(state: &State, post: Vec<u8>) -> DocumentResult {
     let session = Session::try_borrow_from(state);
     let conn = &DbConnection::from_state(state)?;
+    let assignment: Assignment = serde_urlencoded::from_bytes(&post)?;
+    let form: CoauthorsForm = serde_urlencoded::from_bytes(&post)?;
+    let coauthors = article::parse_coauthors(&form.coauthors);
 
     let url = if let Some(path) = ArticleIdPath::try_borrow_from(state) {
-        let changes: ArticleChanges = serde_urlencoded::from_bytes(&post)?;
+        let mut changes: ArticleChanges = serde_urlencoded::from_bytes(&post)?;
+        changes.read_front_matter()?;
 
         // Check permissions
-        match session {
+        let session = match session {
             Some(s)
                 if s.allowed(EditForeignArticle, conn)?
                     || s.allowed(EditArticle, conn)?
-                        && s.user == article::author(conn, path.id)? =>
+                        && article::is_author(conn, path.id, &s.user)? =>
             {
+                s
             }
             _ => return Err(failure::err_msg("Permission denied")),
         };
+        // Co-authors may edit the article, but not who wrote it
+        let credits = session.allowed(EditForeignArticle, conn)?
+            || session.user == article::author(conn, path.id)?;
+        if !credits && coauthors != article::coauthors(conn, path.id)? {
+            return Err(failure::err_msg(
+                "Only the article's author can change its co-authors",
+            ));
+        }
 
-        article::edit(conn, path.id, &changes)?;
+        conn.transaction::<_, failure::Error, _>(|| {
+            article::edit(conn, path.id, &changes)?;
+            article::set_coauthors(conn, path.id, &coauthors)?;
+            series::assign(conn, path.id, &assignment)
+        })?;
+        let renderer = Renderer::borrow_from(state);
+        renderer.prepare(&changes.content);
+        renderer.invalidate(path.id);
         changes.url
     } else {
-        let new_article: NewArticle = serde_urlencoded::from_bytes(&post)?;
+        let mut new_article: NewArticle = serde_urlencoded::from_bytes(&post)?;
+        new_article.read_front_matter()?;
 
         match session {
-            Some(session) if session.allowed(CreateArticle, conn)? => (),
+            Some(session) if session.allowed(CreateArticle, conn)? => {
+                new_article.credit(&session.user, session.allowed(EditForeignArticle, conn)?)
+            }
             _ => return Err(failure::err_msg("Permission denied")),
         }
 
-        article::submit(conn, &new_article)?;
+        conn.transaction::<_, failure::Error, _>(|| {
+            let id = article::submit(conn, &new_article)?;
+            article::set_coauthors(conn, id, &coauthors)?;
+            series::assign(conn, id, &assignment)
+        })?;
+        Renderer::borrow_from(state).prepare(&new_article.content);
         new_article.url
     };
     // Redirect to page for the new article
@@ -0,0 +1,103 @@
+//! Page for importing articles from other blogs
+
+use askama::Template;
+use gotham::{
+    hyper::{header::CONTENT_TYPE, HeaderMap},
+    state::{FromState, State},
+};
+
+use std::path::PathBuf;
+
+use crate::{
+    db::{Connection, DbConnection},
+    document::{DocumentResult, TemplateExt},
+    import::{self, Authors, Report},
+    multipart,
+    theme::Theme,
+    user::{Permission, Session},
+};
+
+#[derive(Template)]
+#[template(path = "import.html")]
+pub struct ImportTemplate<'a> {
+    session: Option<&'a Session>,
+    connection: &'a Connection,
+    theme: &'a Theme,
+    allowed: bool,
+    report: Option<Report>,
+}
+
+/// Checks if a session may import articles, which may be attributed to other users.
+fn allowed(session: Option<&Session>, connection: &Connection) -> Result<bool, failure::Error> {
+    Ok(match session {
+        Some(session) => {
+            session.allowed(Permission::CreateArticle, connection)?
+                && session.allowed(Permission::EditForeignArticle, connection)?
+        }
+        None => false,
+    })
+}
+
+/// Import form
+pub fn import(state: &State) -> DocumentResult {
+    let connection = &DbConnection::from_state(state)?;
+    let session = Session::try_borrow_from(state);
+    Ok(ImportTemplate {
+        session,
+        connection,
+        theme: Theme::borrow_from(state),
+        allowed: allowed(session, connection)?,
+        report: None,
+    }
+    .to_response(state))
+}
+
+/// Imports the uploaded files and shows what was imported and skipped
+pub fn import_post(state: &State, post: Vec<u8>) -> DocumentResult {
+    let connection = &DbConnection::from_state(state)?;
+    let session = match Session::try_borrow_from(state) {
+        Some(session) if allowed(Some(session), connection)? => session,
+        _ => return Err(failure::err_msg("Permission denied")),
+    };
+
+    let content_type = HeaderMap::borrow_from(state)
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let mut authors = Authors {
+        default: Some(session.user.clone()),
+        ..Authors::default()
+    };
+    let mut files = Vec::new();
+    for part in multipart::parse(content_type, &post)? {
+        match (part.name.as_str(), part.filename) {
+            ("authors", _) => {
+                let text = String::from_utf8_lossy(part.data);
+                for line in text.lines().filter(|line| !line.trim().is_empty()) {
+                    let (author, user) = import::parse_mapping(line)?;
+                    authors.map.insert(author, user);
+                }
+            }
+            // Browsers send an empty file if none was chosen
+            ("files", Some(filename)) if !filename.is_empty() => {
+                files.push((PathBuf::from(filename), part.data.to_vec()))
+            }
+            _ => (),
+        }
+    }
+    if files.is_empty() {
+        return Err(failure::err_msg("No files were uploaded"));
+    }
+
+    let mut report = Report::default();
+    let posts = import::read_files(files, &mut report)?;
+    import::import(connection, posts, &authors, &mut report)?;
+    Ok(ImportTemplate {
+        session: Some(session),
+        connection,
+        theme: Theme::borrow_from(state),
+        allowed: true,
+        report: Some(report),
+    }
+    .to_response(state))
+}
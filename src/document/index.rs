@@ -1,17 +1,20 @@
-//! Module for the index, static info pages and initial signup
+//! Module for the index, tag pages and initial signup
 
 use askama::Template;
 use gotham::{
-    helpers::http::response::create_temporary_redirect as temp_redirect,
-    state::{FromState, State, StateData},
+    helpers::http::response::{create_empty_response, create_temporary_redirect as temp_redirect},
+    hyper::StatusCode,
     router::response::StaticResponseExtender,
+    state::{FromState, State, StateData},
 };
 
 use super::{DocumentResult, TemplateExt};
 use crate::{
     article::{self, Article},
     db::{Connection, DbConnection},
-    user::{self, Permission, Session},
+    render::Renderer,
+    theme::Theme,
+    user::{self, Permission, Session, User},
 };
 
 /// Page number in a paginated document
@@ -23,10 +26,20 @@ pub struct Page {
 #[derive(Template)]
 #[template(path = "index.html")]
 pub struct Index<'a> {
-    articles: Vec<Article>,
+    /// The articles on the page with their authors
+    articles: Vec<(Article, Vec<User>)>,
     page: i64,
     session: Option<&'a Session>,
     connection: &'a Connection,
+    theme: &'a Theme,
+    renderer: &'a Renderer,
+}
+
+impl Index<'_> {
+    /// Makes the byline linking to the authors' profiles.
+    fn byline(&self, authors: &[User]) -> String {
+        article::byline_html(authors)
+    }
 }
 
 /// Index. Shows a paginated list of published articles.
@@ -43,40 +56,73 @@ pub fn index(state: &State) -> DocumentResult {
         None => 1,
     };
     let articles = article::page(connection, page)?;
+    let authors = article::authors_of(connection, &articles)?;
 
     let session = Session::try_borrow_from(state);
 
     let template = Index {
-        articles,
+        articles: articles.into_iter().zip(authors).collect(),
         page,
         session,
         connection,
+        theme: Theme::borrow_from(state),
+        renderer: Renderer::borrow_from(state),
     };
     let reponse = template.to_response(state);
     Ok(reponse)
 }
 
+/// Tag whose articles to list
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct TagPath {
+    tag: String,
+}
+
 #[derive(Template)]
-#[template(path = "about.html")]
-pub struct AboutTemplate<'a> {
+#[template(path = "tag.html")]
+pub struct TagTemplate<'a> {
+    tag: &'a str,
+    /// The articles with the tag and their authors
+    articles: Vec<(Article, Vec<User>)>,
     session: Option<&'a Session>,
     connection: &'a Connection,
+    theme: &'a Theme,
+    renderer: &'a Renderer,
 }
 
-/// About page
-pub fn about(state: &State) -> DocumentResult {
+impl TagTemplate<'_> {
+    /// Makes the byline linking to the authors' profiles.
+    fn byline(&self, authors: &[User]) -> String {
+        article::byline_html(authors)
+    }
+}
+
+/// Lists the published articles with a tag
+pub fn tag(state: &State) -> DocumentResult {
     let connection = &DbConnection::from_state(state)?;
-    let template = AboutTemplate {
+    let tag = &TagPath::borrow_from(state).tag;
+    let articles = article::by_tag(connection, tag)?;
+    if articles.is_empty() {
+        return Ok(create_empty_response(state, StatusCode::NOT_FOUND));
+    }
+    let authors = article::authors_of(connection, &articles)?;
+    Ok(TagTemplate {
+        tag,
+        articles: articles.into_iter().zip(authors).collect(),
         session: Session::try_borrow_from(state),
         connection,
-    };
-    Ok(template.to_response(state))
+        theme: Theme::borrow_from(state),
+        renderer: Renderer::borrow_from(state),
+    }
+    .to_response(state))
 }
+
 #[derive(Template)]
 #[template(path = "initial-setup.html")]
 pub struct InitSetupTemplate<'a> {
     session: Option<&'a Session>,
     connection: &'a Connection,
+    theme: &'a Theme,
 }
 
 /// Initial setup (i.e. create admin user) form
@@ -85,6 +131,7 @@ pub fn init_setup(state: &State) -> DocumentResult {
     Ok(InitSetupTemplate {
         session: Session::try_borrow_from(state),
         connection,
+        theme: Theme::borrow_from(state),
     }
     .to_response(state))
 }
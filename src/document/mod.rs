@@ -8,7 +8,10 @@ use gotham::{
 };
 
 pub mod article;
+pub mod import;
 pub mod index;
+pub mod page;
+pub mod series;
 pub mod user;
 
 pub type DocumentResult = Result<Response<Body>, failure::Error>;
@@ -0,0 +1,170 @@
+//! Static pages and the forms for managing them
+
+use askama::Template;
+use gotham::{
+    helpers::http::response::{create_empty_response, create_temporary_redirect as temp_redirect},
+    hyper::StatusCode,
+    router::response::StaticResponseExtender,
+    state::{FromState, State, StateData},
+};
+
+use crate::{
+    caching,
+    db::{Connection, DbConnection},
+    document::{DocumentResult, TemplateExt},
+    page::{self, Page, PageChanges},
+    render::Renderer,
+    security::Nonce,
+    theme::Theme,
+    user::{Permission, Session},
+};
+
+/// Slug of the page to show
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct SlugPath {
+    slug: String,
+}
+
+/// Id of the page to edit
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct PageIdPath {
+    id: i32,
+}
+
+#[derive(Template)]
+#[template(path = "page.html")]
+pub struct PageTemplate<'a> {
+    page: Page,
+    session: Option<&'a Session>,
+    connection: &'a Connection,
+    theme: &'a Theme,
+    renderer: &'a Renderer,
+    editable: bool,
+}
+
+#[derive(Template)]
+#[template(path = "pages.html")]
+pub struct PagesTemplate<'a> {
+    pages: Vec<Page>,
+    session: Option<&'a Session>,
+    connection: &'a Connection,
+    theme: &'a Theme,
+    allowed: bool,
+}
+
+#[derive(Template)]
+#[template(path = "page-edit.html")]
+pub struct EditTemplate<'a> {
+    page: Option<Page>,
+    session: Option<&'a Session>,
+    connection: &'a Connection,
+    theme: &'a Theme,
+    nonce: &'a str,
+    allowed: bool,
+}
+
+/// Checks if a session may create, edit and delete pages, which editors of others' articles can.
+fn allowed(session: Option<&Session>, connection: &Connection) -> Result<bool, failure::Error> {
+    Ok(match session {
+        Some(session) => session.allowed(Permission::EditForeignArticle, connection)?,
+        None => false,
+    })
+}
+
+/// Redirects to a page after submitting a form.
+fn see_other(state: &State, location: String) -> DocumentResult {
+    let mut response = temp_redirect(state, location);
+    // Force method to be GET
+    *response.status_mut() = StatusCode::SEE_OTHER;
+    Ok(response)
+}
+
+/// Display a page
+pub fn view(state: &State) -> DocumentResult {
+    let connection = &DbConnection::from_state(state)?;
+    let session = Session::try_borrow_from(state);
+    let editable = allowed(session, connection)?;
+    let page = match page::view(connection, &SlugPath::borrow_from(state).slug) {
+        Ok(page) if page.visible || editable => page,
+        Ok(_) | Err(diesel::result::Error::NotFound) => {
+            return Ok(create_empty_response(state, StatusCode::NOT_FOUND))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let updated_at = page.updated_at;
+    let mut response = PageTemplate {
+        page,
+        session,
+        connection,
+        theme: Theme::borrow_from(state),
+        renderer: Renderer::borrow_from(state),
+        editable,
+    }
+    .to_response(state);
+    caching::set_last_modified(&mut response, updated_at);
+    Ok(response)
+}
+
+/// List of all pages, for managing them
+pub fn list(state: &State) -> DocumentResult {
+    let connection = &DbConnection::from_state(state)?;
+    let session = Session::try_borrow_from(state);
+    let allowed = allowed(session, connection)?;
+    Ok(PagesTemplate {
+        pages: match allowed {
+            true => page::list(connection)?,
+            false => Vec::new(),
+        },
+        session,
+        connection,
+        theme: Theme::borrow_from(state),
+        allowed,
+    }
+    .to_response(state))
+}
+
+/// Form for creating or editing a page
+pub fn edit(state: &State) -> DocumentResult {
+    let connection = &DbConnection::from_state(state)?;
+    let session = Session::try_borrow_from(state);
+    let page = match PageIdPath::try_borrow_from(state) {
+        Some(path) => Some(page::get(connection, path.id)?),
+        None => None,
+    };
+    let mut response = EditTemplate {
+        page,
+        session,
+        connection,
+        theme: Theme::borrow_from(state),
+        nonce: &Nonce::borrow_from(state).0,
+        allowed: allowed(session, connection)?,
+    }
+    .to_response(state);
+    caching::set_no_store(&mut response);
+    Ok(response)
+}
+
+pub fn edit_post(state: &State, post: Vec<u8>) -> DocumentResult {
+    let connection = &DbConnection::from_state(state)?;
+    if !allowed(Session::try_borrow_from(state), connection)? {
+        return Err(failure::err_msg("Permission denied"));
+    }
+    let changes: PageChanges = serde_urlencoded::from_bytes(&post)?;
+    match PageIdPath::try_borrow_from(state) {
+        Some(path) => page::edit(connection, path.id, &changes)?,
+        None => page::create(connection, &changes)?,
+    };
+    Renderer::borrow_from(state).prepare(&changes.content);
+    Theme::borrow_from(state).load_nav(connection)?;
+    see_other(state, format!("/{}", changes.slug))
+}
+
+pub fn delete_post(state: &State, _post: Vec<u8>) -> DocumentResult {
+    let connection = &DbConnection::from_state(state)?;
+    if !allowed(Session::try_borrow_from(state), connection)? {
+        return Err(failure::err_msg("Permission denied"));
+    }
+    page::delete(connection, PageIdPath::borrow_from(state).id)?;
+    Theme::borrow_from(state).load_nav(connection)?;
+    see_other(state, String::from("/pages"))
+}
@@ -0,0 +1,104 @@
+//! Landing pages of article series
+
+use askama::Template;
+use gotham::{
+    helpers::http::response::{create_empty_response, create_temporary_redirect as temp_redirect},
+    hyper::StatusCode,
+    router::response::StaticResponseExtender,
+    state::{FromState, State, StateData},
+};
+
+use crate::{
+    article::Article,
+    db::{Connection, DbConnection},
+    document::{DocumentResult, TemplateExt},
+    render::Renderer,
+    series::{self, Series, SeriesChanges},
+    theme::Theme,
+    user::{Permission, Session},
+};
+
+/// A series id or url
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct SeriesPath {
+    id: String,
+}
+
+#[derive(Template)]
+#[template(path = "series.html")]
+pub struct SeriesTemplate<'a> {
+    series: Series,
+    articles: Vec<Article>,
+    session: Option<&'a Session>,
+    connection: &'a Connection,
+    theme: &'a Theme,
+    renderer: &'a Renderer,
+    editable: bool,
+}
+
+/// Checks if a session may edit and delete series, which editors of others' articles can.
+fn allowed(session: Option<&Session>, connection: &Connection) -> Result<bool, failure::Error> {
+    Ok(match session {
+        Some(session) => session.allowed(Permission::EditForeignArticle, connection)?,
+        None => false,
+    })
+}
+
+/// Redirects to a page after submitting a form.
+fn see_other(state: &State, location: String) -> DocumentResult {
+    let mut response = temp_redirect(state, location);
+    // Force method to be GET
+    *response.status_mut() = StatusCode::SEE_OTHER;
+    Ok(response)
+}
+
+/// Shows a series' description and its published articles in order
+pub fn view(state: &State) -> DocumentResult {
+    let connection = &DbConnection::from_state(state)?;
+    let session = Session::try_borrow_from(state);
+    let series = match series::view(connection, &SeriesPath::borrow_from(state).id) {
+        Ok(series) => series,
+        Err(diesel::result::Error::NotFound) => {
+            return Ok(create_empty_response(state, StatusCode::NOT_FOUND))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let articles = series::articles(connection, series.id)?
+        .into_iter()
+        .filter(|article| article.visible)
+        .collect();
+    Ok(SeriesTemplate {
+        series,
+        articles,
+        session,
+        connection,
+        theme: Theme::borrow_from(state),
+        renderer: Renderer::borrow_from(state),
+        editable: allowed(session, connection)?,
+    }
+    .to_response(state))
+}
+
+/// Changes the title, url and description of a series
+pub fn edit_post(state: &State, post: Vec<u8>) -> DocumentResult {
+    let connection = &DbConnection::from_state(state)?;
+    if !allowed(Session::try_borrow_from(state), connection)? {
+        return Err(failure::err_msg("Permission denied"));
+    }
+    let series = series::view(connection, &SeriesPath::borrow_from(state).id)?;
+    let changes: SeriesChanges = serde_urlencoded::from_bytes(&post)?;
+    series::edit(connection, series.id, &changes)?;
+    Renderer::borrow_from(state).prepare(&changes.description);
+    see_other(state, format!("/series/{}", changes.url))
+}
+
+/// Deletes a series, leaving its articles
+pub fn delete_post(state: &State, _post: Vec<u8>) -> DocumentResult {
+    let connection = &DbConnection::from_state(state)?;
+    if !allowed(Session::try_borrow_from(state), connection)? {
+        return Err(failure::err_msg("Permission denied"));
+    }
+    let series = series::view(connection, &SeriesPath::borrow_from(state).id)?;
+    series::delete(connection, series.id)?;
+    see_other(state, String::from("/"))
+}
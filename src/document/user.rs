@@ -3,17 +3,21 @@
 use askama::Template;
 use cookie::{Cookie, SameSite};
 use gotham::{
-    helpers::http::response::create_temporary_redirect as temp_redirect,
-    hyper::{header, StatusCode},
+    helpers::http::response::{create_empty_response, create_temporary_redirect as temp_redirect},
+    hyper::{header, HeaderMap, StatusCode},
     state::{client_addr, FromState, State},
 };
 
 use crate::{
+    article::{self, Article},
     comment,
     config::Settings,
     db::{Connection, DbConnection},
     document::{article::CommentTemplate, DocumentResult, TemplateExt},
-    handler::users::UserPath,
+    handler::users::{UserPagePath, UserPath},
+    media, multipart,
+    render::Renderer,
+    theme::Theme,
     user::{
         self, Login, NewUser, PasswordChange, Permission, Session, User, UserDeletion, UserProfile,
     },
@@ -39,6 +43,7 @@ fn session_cookie<'a>(state: &State, id: &str) -> Cookie<'a> {
 pub struct LoginTemplate<'a> {
     session: Option<&'a Session>,
     connection: &'a Connection,
+    theme: &'a Theme,
 }
 
 #[derive(Template, Clone)]
@@ -46,6 +51,7 @@ pub struct LoginTemplate<'a> {
 pub struct LoginResultTemplate<'a> {
     session: Option<&'a Session>,
     connection: &'a Connection,
+    theme: &'a Theme,
 }
 
 /// Login form
@@ -54,6 +60,7 @@ pub fn login(state: &State) -> DocumentResult {
     Ok(LoginTemplate {
         session: Session::try_borrow_from(state),
         connection,
+        theme: Theme::borrow_from(state),
     }
     .to_response(state))
 }
@@ -67,6 +74,7 @@ pub fn login_post(state: &State, post: Vec<u8>) -> DocumentResult {
     let mut response = LoginResultTemplate {
         session: new_session.as_ref(),
         connection,
+        theme: Theme::borrow_from(state),
     }
     .to_response(state);
 
@@ -86,6 +94,7 @@ pub fn login_post(state: &State, post: Vec<u8>) -> DocumentResult {
 struct SignupTemplate<'a> {
     session: Option<&'a Session>,
     connection: &'a Connection,
+    theme: &'a Theme,
     signup_enabled: bool,
 }
 
@@ -95,6 +104,7 @@ pub fn signup(state: &State) -> DocumentResult {
     Ok(SignupTemplate {
         session: Session::try_borrow_from(state),
         connection,
+        theme: Theme::borrow_from(state),
         signup_enabled,
     }
     .to_response(state))
@@ -105,6 +115,7 @@ pub fn signup(state: &State) -> DocumentResult {
 struct SignupResultTemplate<'a> {
     session: Option<&'a Session>,
     connection: &'a Connection,
+    theme: &'a Theme,
 }
 
 pub fn signup_post(state: &State, post: Vec<u8>) -> DocumentResult {
@@ -122,7 +133,9 @@ pub fn signup_post(state: &State, post: Vec<u8>) -> DocumentResult {
             None => String::from("unavailable"),
         };
         // Log spam attempt
-        println!(
+        log::warn!(
+            user = new_user.id.as_str(),
+            client = addr.as_str();
             "Caught spam user with id '{}' and client IP '{}'",
             new_user.id, addr,
         );
@@ -139,6 +152,7 @@ pub fn signup_post(state: &State, post: Vec<u8>) -> DocumentResult {
     let mut response = SignupResultTemplate {
         session: Some(&session),
         connection,
+        theme: Theme::borrow_from(state),
     }
     .to_response(state);
     let cookie = session_cookie(state, &session.id);
@@ -153,6 +167,7 @@ pub fn signup_post(state: &State, post: Vec<u8>) -> DocumentResult {
 #[template(path = "logout.html")]
 struct LogoutTemplate<'a> {
     connection: &'a Connection,
+    theme: &'a Theme,
     session: Option<&'a Session>,
 }
 
@@ -166,6 +181,7 @@ pub fn logout(state: &State) -> DocumentResult {
 
     let mut response = LogoutTemplate {
         connection,
+        theme: Theme::borrow_from(state),
         session: None,
     }
     .to_response(state);
@@ -185,25 +201,61 @@ pub fn logout(state: &State) -> DocumentResult {
 #[template(path = "user.html")]
 struct UserTemplate<'a> {
     user: &'a User,
+    /// The user's published articles on this page
+    articles: Vec<Article>,
+    page: i64,
+    /// The user's comments, shown on the first page
     comments: &'a [CommentTemplate<'a>],
     session: Option<&'a Session>,
     connection: &'a Connection,
+    theme: &'a Theme,
+    renderer: &'a Renderer,
+    gravatar: bool,
 }
 
+impl UserTemplate<'_> {
+    /// Renders the user's bio, which is sanitized like comments as anyone can sign up.
+    fn bio(&self) -> String {
+        self.renderer.comment(&self.user.bio)
+    }
+}
+
+/// A user's public profile with their articles and comments
 pub fn view(state: &State) -> DocumentResult {
     let connection = &DbConnection::from_state(state)?;
     let session = Session::try_borrow_from(state);
 
-    let user_id = &UserPath::borrow_from(state).user;
-    let user = user::get(connection, user_id)?;
-    let comments = comment::by_user(connection, user_id)?;
-    let comment_templates = CommentTemplate::from_list(&comments, connection, session, false);
+    let renderer = Renderer::borrow_from(state);
+
+    let (user_id, page) = match UserPagePath::try_borrow_from(state) {
+        Some(path) => (&path.user, path.page),
+        None => (&UserPath::borrow_from(state).user, 1),
+    };
+    let user = match user::get(connection, user_id) {
+        Ok(user) => user,
+        Err(diesel::result::Error::NotFound) => {
+            return Ok(create_empty_response(state, StatusCode::NOT_FOUND))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let articles = article::by_author(connection, user_id, page)?;
+    let comments = match page {
+        1 => comment::by_user(connection, user_id)?,
+        _ => Vec::new(),
+    };
+    let comment_templates =
+        CommentTemplate::from_list(&comments, connection, renderer, session, false);
 
     let template = UserTemplate {
         user: &user,
+        articles,
+        page,
         comments: &comment_templates,
         session,
         connection,
+        theme: Theme::borrow_from(state),
+        renderer,
+        gravatar: Settings::borrow_from(state).features.gravatar,
     };
 
     Ok(template.to_response(state))
@@ -214,7 +266,9 @@ pub fn view(state: &State) -> DocumentResult {
 struct UserProfileTemplate<'a> {
     session: Option<&'a Session>,
     connection: &'a Connection,
+    theme: &'a Theme,
     user: &'a User,
+    gravatar: bool,
 }
 
 /// Form for editing your account
@@ -228,18 +282,23 @@ pub fn edit(state: &State) -> DocumentResult {
     let template = UserProfileTemplate {
         session,
         connection,
+        theme: Theme::borrow_from(state),
         user: &user,
+        gravatar: Settings::borrow_from(state).features.gravatar,
     };
     Ok(template.to_response(state))
 }
 
-// TODO: verify permissions are being checked
 /// Result for changing profile information
 pub fn profile_post(state: &State, post: Vec<u8>) -> DocumentResult {
     let profile: UserProfile = serde_urlencoded::from_bytes(&post)?;
     let connection = &DbConnection::from_state(state)?;
     let user_id = &UserPath::borrow_from(state).user;
 
+    let user = user::get(connection, user_id)?;
+    if !user.editable(Session::try_borrow_from(state), connection)? {
+        return Err(failure::err_msg("Permission denied"));
+    }
     user::edit_profile(connection, user_id, &profile)?;
 
     let mut response = temp_redirect(state, format!("/user/{}", user_id));
@@ -247,6 +306,40 @@ pub fn profile_post(state: &State, post: Vec<u8>) -> DocumentResult {
     Ok(response)
 }
 
+/// Result for uploading an avatar, or removing it if the form's `remove` button was used
+pub fn avatar_post(state: &State, post: Vec<u8>) -> DocumentResult {
+    let connection = &DbConnection::from_state(state)?;
+    let user_id = &UserPath::borrow_from(state).user;
+    let user = user::get(connection, user_id)?;
+    if !user.editable(Session::try_borrow_from(state), connection)? {
+        return Err(failure::err_msg("Permission denied"));
+    }
+
+    let content_type = HeaderMap::borrow_from(state)
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let parts = multipart::parse(content_type, &post)?;
+    let settings = &Settings::borrow_from(state).media;
+    let avatar = if parts.iter().any(|part| part.name == "remove") {
+        None
+    } else {
+        let upload = parts
+            .iter()
+            .find(|part| part.name == "avatar" && !part.data.is_empty())
+            .ok_or_else(|| failure::err_msg("No image was uploaded"))?;
+        Some(media::save_avatar(settings, user_id, upload.data)?)
+    };
+    user::set_avatar(connection, user_id, avatar.as_deref())?;
+    if let Some(old) = user.avatar.filter(|old| Some(old) != avatar.as_ref()) {
+        media::remove(settings, &old)?;
+    }
+
+    let mut response = temp_redirect(state, format!("/user/{}", user_id));
+    *response.status_mut() = StatusCode::SEE_OTHER;
+    Ok(response)
+}
+
 /// Result for changing password
 pub fn password_post(state: &State, post: Vec<u8>) -> DocumentResult {
     let change: PasswordChange = serde_urlencoded::from_bytes(&post)?;
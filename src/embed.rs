@@ -0,0 +1,468 @@
+//! Math and diagrams in articles. They're cut out of the markdown before it's rendered, leaving
+//! placeholders that are replaced with MathML and SVG once the rendered html has been sanitized.
+//!
+//! Math is recognized in text outside of code blocks and code spans. `$` only starts inline
+//! math when followed by a non-space, and only ends it when preceded by a non-space and not
+//! followed by a digit, so prices aren't mistaken for math. `\$` is a literal dollar sign.
+
+use crate::{config::Diagrams, diagram::Diagram, math, sanitize::escape};
+
+/// Start of a placeholder. Private use characters don't appear in articles, and go through
+/// rendering and sanitization untouched.
+const OPEN: char = '\u{E000}';
+/// End of a placeholder
+const CLOSE: char = '\u{E001}';
+
+enum Kind {
+    Math { tex: String, display: bool },
+    Diagram { language: String, source: String },
+}
+
+/// Math or a diagram cut out of an article
+pub struct Embed {
+    kind: Kind,
+    /// The markdown the embed was cut out of, put back where it can't be rendered
+    original: String,
+}
+
+/// An opening code fence
+struct Fence {
+    indent: usize,
+    marker: char,
+    len: usize,
+    language: String,
+}
+
+impl Fence {
+    fn open(line: &str) -> Option<Self> {
+        let trimmed = line.trim_start();
+        let marker = trimmed.chars().next().filter(|&c| c == '`' || c == '~')?;
+        let len = trimmed.chars().take_while(|&c| c == marker).count();
+        let info = &trimmed[len..];
+        if len < 3 || (marker == '`' && info.contains('`')) {
+            return None;
+        }
+        Some(Fence {
+            indent: line.len() - trimmed.len(),
+            marker,
+            len,
+            language: info.split_whitespace().next().unwrap_or("").to_owned(),
+        })
+    }
+
+    fn closed_by(&self, line: &str) -> bool {
+        let trimmed = line.trim();
+        trimmed.len() >= self.len && trimmed.chars().all(|c| c == self.marker)
+    }
+}
+
+/// Gets the width of a line's indentation, with tabs stopping at multiples of four.
+fn indentation(line: &str) -> usize {
+    let mut width = 0;
+    for c in line.chars() {
+        match c {
+            ' ' => width += 1,
+            '\t' => width += 4 - width % 4,
+            _ => break,
+        }
+    }
+    width
+}
+
+/// Gets the indentation of the content of a list item starting on `line`.
+fn list_item(line: &str) -> Option<usize> {
+    let trimmed = line.trim_start();
+    let digits = trimmed.chars().take_while(char::is_ascii_digit).count();
+    let marker = match (digits, trimmed[digits..].chars().next()?) {
+        (0, '-' | '*' | '+') => 1,
+        (1..=9, '.' | ')') => digits + 1,
+        _ => return None,
+    };
+    let rest = &trimmed[marker..];
+    let spaces = rest.len() - rest.trim_start_matches(' ').len();
+    match rest.trim().is_empty() {
+        true => Some(indentation(line) + marker + 1),
+        false if spaces > 0 => Some(indentation(line) + marker + spaces.min(4)),
+        false => None,
+    }
+}
+
+fn placeholder(index: usize) -> String {
+    format!("{}{}{}", OPEN, index, CLOSE)
+}
+
+/// Finds the end of inline math starting after the `$` at `start`. The next `$` on the line ends
+/// it if it can, otherwise the `$` doesn't start math. Inline math can't contain code spans.
+fn inline_end(chars: &[char], start: usize) -> Option<usize> {
+    if chars.get(start).is_none_or(|c| c.is_whitespace()) {
+        return None;
+    }
+    let mut i = start;
+    while i < chars.len() && chars[i] != '\n' {
+        match chars[i] {
+            '\\' => i += 1,
+            '`' => return None,
+            '$' => {
+                let closes = !chars[i - 1].is_whitespace()
+                    && !chars.get(i + 1).is_some_and(char::is_ascii_digit);
+                return Some(i).filter(|_| closes);
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Finds the `$$` ending display math starting at `start`.
+fn display_end(chars: &[char], start: usize) -> Option<usize> {
+    let mut i = start;
+    while i + 1 < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '$' if chars[i + 1] == '$' => return Some(i),
+            _ => (),
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Cuts the math out of text without code blocks.
+fn extract_math(text: &str, output: &mut String, embeds: &mut Vec<Embed>) {
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let (end, tex, display) = match chars[i] {
+            '\\' => {
+                let end = (i + 2).min(chars.len());
+                output.extend(&chars[i..end]);
+                i = end;
+                continue;
+            }
+            '`' => {
+                // Code spans are copied as they are, up to a run of backticks of the same length
+                let run = chars[i..].iter().take_while(|&&c| c == '`').count();
+                let mut end = i + run;
+                let mut j = end;
+                while j < chars.len() {
+                    let len = chars[j..].iter().take_while(|&&c| c == '`').count();
+                    if len == run {
+                        end = j + len;
+                        break;
+                    }
+                    j += len.max(1);
+                }
+                output.extend(&chars[i..end]);
+                i = end;
+                continue;
+            }
+            '$' if chars.get(i + 1) == Some(&'$') => match display_end(&chars, i + 2) {
+                Some(end) => (end + 2, &chars[i + 2..end], true),
+                None => {
+                    output.push_str("$$");
+                    i += 2;
+                    continue;
+                }
+            },
+            '$' => match inline_end(&chars, i + 1) {
+                Some(end) => (end + 1, &chars[i + 1..end], false),
+                None => {
+                    output.push('$');
+                    i += 1;
+                    continue;
+                }
+            },
+            c => {
+                output.push(c);
+                i += 1;
+                continue;
+            }
+        };
+        output.push_str(&placeholder(embeds.len()));
+        embeds.push(Embed {
+            kind: Kind::Math {
+                tex: tex.iter().collect(),
+                display,
+            },
+            original: chars[i..end].iter().collect(),
+        });
+        i = end;
+    }
+}
+
+/// Replaces math and diagrams in article markdown with placeholders. Code blocks past the
+/// diagram limit are left as code.
+pub fn extract(content: &str, math: bool, diagrams: &Diagrams) -> (String, Vec<Embed>) {
+    let mut output = String::with_capacity(content.len());
+    let mut embeds = Vec::new();
+    if !math && diagrams.renderers.is_empty() {
+        output.push_str(content);
+        return (output, embeds);
+    }
+
+    let mut text = String::new();
+    let mut diagram_count = 0;
+    // Where the content of the list item being read starts, and whether the last line was part of
+    // a paragraph, which indented lines continue rather than starting a code block
+    let mut list_indent = 0;
+    let mut paragraph = false;
+    let mut lines = content.split_inclusive('\n');
+    while let Some(line) = lines.next() {
+        let indent = indentation(line);
+        if line.trim().is_empty() {
+            paragraph = false;
+            text.push_str(line);
+            continue;
+        }
+        if !paragraph && indent >= list_indent + 4 {
+            // Indented code blocks are copied as they are
+            flush(&mut text, math, &mut output, &mut embeds);
+            output.push_str(line);
+            continue;
+        }
+        let fence = match Fence::open(line) {
+            Some(fence) => fence,
+            None => {
+                match list_item(line) {
+                    Some(content) => list_indent = content,
+                    None if !paragraph && indent < list_indent => list_indent = 0,
+                    None => (),
+                }
+                paragraph = !line.trim_start().starts_with('#');
+                text.push_str(line);
+                continue;
+            }
+        };
+        flush(&mut text, math, &mut output, &mut embeds);
+        paragraph = false;
+
+        let mut block = vec![line];
+        for line in lines.by_ref() {
+            block.push(line);
+            if fence.closed_by(line) {
+                break;
+            }
+        }
+        if !diagrams.renderers.contains_key(&fence.language) || diagram_count >= diagrams.limit {
+            output.extend(block);
+            continue;
+        }
+        diagram_count += 1;
+        let body = match block.last().is_some_and(|l| fence.closed_by(l)) && block.len() > 1 {
+            true => &block[1..block.len() - 1],
+            false => &block[1..],
+        };
+        // Content of fenced blocks is indented like the fence
+        let source = body
+            .iter()
+            .map(|l| {
+                let indent = l.len() - l.trim_start_matches(' ').len();
+                &l[indent.min(fence.indent)..]
+            })
+            .collect();
+        output.push_str(&format!(
+            "\n{}{}\n\n",
+            " ".repeat(fence.indent),
+            placeholder(embeds.len())
+        ));
+        embeds.push(Embed {
+            kind: Kind::Diagram {
+                language: fence.language,
+                source,
+            },
+            original: block.concat(),
+        });
+    }
+    flush(&mut text, math, &mut output, &mut embeds);
+    (output, embeds)
+}
+
+/// Moves text without code blocks to the output, cutting out its math if enabled.
+fn flush(text: &mut String, math: bool, output: &mut String, embeds: &mut Vec<Embed>) {
+    if math {
+        extract_math(text, output, embeds);
+    } else {
+        output.push_str(text);
+    }
+    text.clear();
+}
+
+/// Gets the language and source of the diagrams in article markdown.
+pub fn diagrams(content: &str, settings: &Diagrams) -> Vec<(String, String)> {
+    extract(content, false, settings)
+        .1
+        .into_iter()
+        .filter_map(|embed| match embed.kind {
+            Kind::Diagram { language, source } => Some((language, source)),
+            Kind::Math { .. } => None,
+        })
+        .collect()
+}
+
+/// Shows a diagram, falling back to its source if it isn't rendered.
+fn show_diagram(diagram: Option<Diagram>, source: &str) -> String {
+    let class = match diagram {
+        Some(Diagram::Svg(svg)) => return format!("<figure class=\"diagram\">{}</figure>", svg),
+        Some(Diagram::Failed(_)) => " class=\"diagram-error\"",
+        Some(Diagram::Pending) => " class=\"diagram-pending\"",
+        None => "",
+    };
+    format!("<pre{}><code>{}</code></pre>", class, escape(source))
+}
+
+/// Replaces the placeholders in rendered html with the math and diagrams they stand for, getting
+/// diagrams from `diagram` by language and source. Placeholders inside of tags, such as in
+/// attribute values, get the original markdown back.
+pub fn restore<F>(html: &str, embeds: &[Embed], mut diagram: F) -> String
+where
+    F: FnMut(&str, &str) -> Option<Diagram>,
+{
+    if embeds.is_empty() {
+        return html.to_owned();
+    }
+    let mut output = String::with_capacity(html.len());
+    let mut in_tag = false;
+    let mut quote = None;
+    let mut rest = html;
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        match (c, quote) {
+            (OPEN, _) => (),
+            ('"', None) | ('\'', None) if in_tag => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('<', None) => in_tag = true,
+            ('>', None) => in_tag = false,
+            _ => (),
+        }
+        if c != OPEN {
+            output.push(c);
+            continue;
+        }
+
+        // Characters that aren't part of a placeholder, like ones written in the content, are kept
+        let end = match rest.find(CLOSE) {
+            Some(end) => end,
+            None => {
+                output.push(c);
+                output.push_str(rest);
+                break;
+            }
+        };
+        let embed = match rest[..end].parse().ok().and_then(|i: usize| embeds.get(i)) {
+            Some(embed) => embed,
+            None => {
+                output.push(c);
+                continue;
+            }
+        };
+        rest = &rest[end + CLOSE.len_utf8()..];
+        if in_tag {
+            output.push_str(&escape(&embed.original));
+            continue;
+        }
+        match &embed.kind {
+            Kind::Math { tex, display } => output.push_str(&math::to_mathml(tex, *display)),
+            Kind::Diagram { language, source } => {
+                // Diagrams are blocks, so they're taken out of the paragraph they're rendered in
+                if output.ends_with("<p>") && rest.starts_with("</p>") {
+                    output.truncate(output.len() - "<p>".len());
+                    rest = &rest["</p>".len()..];
+                }
+                output.push_str(&show_diagram(diagram(language, source), source));
+            }
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{extract, restore, Kind, CLOSE, OPEN};
+    use crate::config::Diagrams;
+
+    #[test]
+    fn extraction() {
+        let content = "Costs $5 to $10, \\$x and `$y$` but $x^2$ and\n\n$$\n\\sum x\n$$\n\n\
+                       ```rust\nlet a = $b$;\n```\n\n    let c = $d$;\n\n- list\n\n  ~~~dot\n  a -> b\n  \
+                       ~~~\n\n- $e$\n\n      let f = $g$;\n";
+        let mut renderers = BTreeMap::new();
+        renderers.insert(String::from("dot"), vec![String::from("dot")]);
+        let diagrams = Diagrams {
+            renderers,
+            ..Diagrams::default()
+        };
+        let (markdown, embeds) = extract(content, true, &diagrams);
+        let placeholder = |i| format!("{}{}{}", OPEN, i, CLOSE);
+        assert_eq!(
+            markdown,
+            format!(
+                "Costs $5 to $10, \\$x and `$y$` but {} and\n\n{}\n\n\
+                 ```rust\nlet a = $b$;\n```\n\n    let c = $d$;\n\n- list\n\n\n  {}\n\n\n- {}\n\n      \
+                 let f = $g$;\n",
+                placeholder(0),
+                placeholder(1),
+                placeholder(2),
+                placeholder(3)
+            )
+        );
+        assert_eq!(embeds.len(), 4);
+        match &embeds[1].kind {
+            Kind::Math { tex, display } => {
+                assert_eq!((tex.as_str(), *display), ("\n\\sum x\n", true))
+            }
+            _ => panic!("expected math"),
+        }
+        match &embeds[2].kind {
+            Kind::Diagram { language, source } => {
+                assert_eq!((language.as_str(), source.as_str()), ("dot", "a -> b\n"))
+            }
+            _ => panic!("expected a diagram"),
+        }
+        assert_eq!(embeds[2].original, "  ~~~dot\n  a -> b\n  ~~~\n");
+
+        // Code blocks past the limit stay code
+        let limited = Diagrams {
+            limit: 1,
+            ..diagrams
+        };
+        let (markdown, embeds) = extract("```dot\na\n```\n```dot\nb\n```\n", false, &limited);
+        assert_eq!(
+            markdown,
+            format!("\n{}\n\n```dot\nb\n```\n", placeholder(0))
+        );
+        assert_eq!(embeds.len(), 1);
+
+        // Indented lines continue paragraphs rather than starting code blocks
+        let (_, embeds) = extract("a\n    $x$\n", true, &Diagrams::default());
+        assert_eq!(embeds.len(), 1);
+
+        // Nothing is touched unless enabled
+        let (markdown, embeds) = extract(content, false, &Diagrams::default());
+        assert_eq!((markdown.as_str(), embeds.len()), (content, 0));
+    }
+
+    #[test]
+    fn restoration() {
+        let (markdown, embeds) = extract("$x$ [$y$](/a)", true, &Diagrams::default());
+        let html = format!(
+            "<p>{}<a href=\"/a\" title=\"{}\">{}</a></p>",
+            &markdown[..markdown.find(' ').unwrap()],
+            &markdown[markdown.find('[').unwrap() + 1..markdown.find(']').unwrap()],
+            &markdown[markdown.find('[').unwrap() + 1..markdown.find(']').unwrap()],
+        );
+        let html = restore(&html, &embeds, |_, _| None);
+        assert!(html.starts_with("<p><math display=\"inline\">"));
+        assert!(html.contains("title=\"$y$\">"));
+        assert!(html.ends_with("</math></a></p>"));
+
+        // Stray placeholder characters don't cut the rest of the page off
+        let (markdown, embeds) = extract("$x$ a\u{E000}b", true, &Diagrams::default());
+        let html = restore(&format!("<p>{}</p>", markdown), &embeds, |_, _| None);
+        assert!(html.ends_with("</math> a\u{E000}b</p>"));
+        let html = restore("<p>\u{E000}a\u{E001}b</p>", &embeds, |_, _| None);
+        assert_eq!(html, "<p>\u{E000}a\u{E001}b</p>");
+    }
+}
@@ -0,0 +1,369 @@
+//! Front matter of articles written as markdown files, so posts written in an editor can be pasted
+//! in with their metadata, and exported back the same way.
+//!
+//! YAML front matter is delimited by `---` lines and TOML front matter by `+++` lines, as in Jekyll
+//! and Hugo. Their names for fields are understood too, like `slug`, `draft` and `description`.
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use serde_json::{Map, Value};
+use yaml_rust::{
+    parser::{Event, EventReceiver, Parser},
+    Yaml, YamlLoader,
+};
+
+use crate::article::Article;
+
+/// Formats of dates with an offset other than RFC 3339, as written by Jekyll
+const OFFSET_DATE_FORMATS: &[&str] = &["%Y-%m-%d %H:%M:%S %z", "%Y-%m-%d %H:%M %z"];
+/// Formats of dates without a time zone, which are taken to be UTC
+const DATE_FORMATS: &[&str] = &["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"];
+
+/// Tags as a list or comma separated
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Tags {
+    List(Vec<String>),
+    Text(String),
+}
+
+/// Parses a date in any of the formats we understand. Times with an offset are converted to UTC.
+fn parse_date(date: &str) -> Result<NaiveDateTime, failure::Error> {
+    let date = date.trim();
+    if let Ok(date) = DateTime::parse_from_rfc3339(date) {
+        return Ok(date.naive_utc());
+    }
+    for format in OFFSET_DATE_FORMATS {
+        if let Ok(date) = DateTime::parse_from_str(date, format) {
+            return Ok(date.naive_utc());
+        }
+    }
+    for format in DATE_FORMATS {
+        if let Ok(date) = NaiveDateTime::parse_from_str(date, format) {
+            return Ok(date);
+        }
+    }
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .ok_or_else(|| failure::format_err!("Invalid date in front matter: {}", date))
+}
+
+/// Metadata of an article. Fields that aren't set leave the article's unchanged.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct FrontMatter {
+    pub title: Option<String>,
+    #[serde(alias = "slug")]
+    pub url: Option<String>,
+    /// Only used when importing
+    pub author: Option<String>,
+    date: Option<String>,
+    /// Only used when restoring backups
+    #[serde(alias = "lastmod", alias = "last_modified_at")]
+    updated: Option<String>,
+    tags: Option<Tags>,
+    visible: Option<bool>,
+    /// Jekyll's name for `visible`
+    published: Option<bool>,
+    /// Hugo's opposite of `visible`
+    draft: Option<bool>,
+    #[serde(alias = "description", alias = "excerpt")]
+    summary: Option<String>,
+    pub toc: Option<bool>,
+}
+
+impl FrontMatter {
+    /// Whether the article is published
+    pub fn visible(&self) -> Option<bool> {
+        self.visible
+            .or(self.published)
+            .or_else(|| self.draft.map(|draft| !draft))
+    }
+
+    /// The time of publishing. Times with an offset are converted to UTC.
+    pub fn date(&self) -> Result<Option<NaiveDateTime>, failure::Error> {
+        self.date.as_deref().map(parse_date).transpose()
+    }
+
+    /// The time of the last edit, in the same formats as `date`.
+    pub fn updated(&self) -> Result<Option<NaiveDateTime>, failure::Error> {
+        self.updated.as_deref().map(parse_date).transpose()
+    }
+
+    /// The article's summary, or `Some(None)` to remove it if it's empty
+    pub fn summary(&self) -> Option<Option<String>> {
+        let summary = self.summary.as_ref()?.trim();
+        Some(Some(summary.to_owned()).filter(|s| !s.is_empty()))
+    }
+
+    /// The article's tags, without surrounding whitespace or empty ones
+    pub fn tags(&self) -> Option<Vec<String>> {
+        let tags: Vec<&str> = match self.tags.as_ref()? {
+            Tags::List(tags) => tags.iter().map(String::as_str).collect(),
+            Tags::Text(tags) => tags.split(',').collect(),
+        };
+        Some(clean_tags(tags))
+    }
+}
+
+/// Trims tags, dropping empty and repeated ones.
+pub fn clean_tags<'a>(tags: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let mut cleaned: Vec<String> = Vec::new();
+    for tag in tags.into_iter().map(str::trim) {
+        if !tag.is_empty() && !cleaned.iter().any(|t| t == tag) {
+            cleaned.push(tag.to_owned());
+        }
+    }
+    cleaned
+}
+
+/// Looks for aliases in YAML without loading it. Loading copies the aliased node for every alias,
+/// so a few nested ones are enough to use up all memory.
+#[derive(Default)]
+struct AliasCheck {
+    aliases: bool,
+}
+
+impl EventReceiver for AliasCheck {
+    fn on_event(&mut self, event: Event) {
+        self.aliases |= matches!(event, Event::Alias(_));
+    }
+}
+
+/// Parses YAML front matter, rejecting aliases.
+fn load_yaml(source: &str) -> Result<Value, failure::Error> {
+    let invalid = |e| failure::format_err!("Invalid front matter: {}", e);
+    let mut check = AliasCheck::default();
+    Parser::new(source.chars())
+        .load(&mut check, true)
+        .map_err(invalid)?;
+    if check.aliases {
+        return Err(failure::err_msg(
+            "Invalid front matter: aliases aren't supported",
+        ));
+    }
+    Ok(YamlLoader::load_from_str(source)
+        .map_err(invalid)?
+        .into_iter()
+        .next()
+        .map_or(Value::Null, from_yaml))
+}
+
+fn from_yaml(yaml: Yaml) -> Value {
+    match yaml {
+        Yaml::Real(real) => real
+            .parse()
+            .map(Value::Number)
+            .unwrap_or(Value::String(real)),
+        Yaml::Integer(integer) => Value::from(integer),
+        Yaml::String(string) => Value::String(string),
+        Yaml::Boolean(boolean) => Value::Bool(boolean),
+        Yaml::Array(array) => Value::Array(array.into_iter().map(from_yaml).collect()),
+        Yaml::Hash(hash) => Value::Object(
+            hash.into_iter()
+                .filter_map(|(key, value)| Some((key.into_string()?, from_yaml(value))))
+                .collect::<Map<_, _>>(),
+        ),
+        Yaml::Alias(_) | Yaml::Null | Yaml::BadValue => Value::Null,
+    }
+}
+
+fn from_toml(toml: toml::Value) -> Value {
+    match toml {
+        toml::Value::String(string) => Value::String(string),
+        toml::Value::Integer(integer) => Value::from(integer),
+        toml::Value::Float(float) => Value::from(float),
+        toml::Value::Boolean(boolean) => Value::Bool(boolean),
+        toml::Value::Datetime(datetime) => Value::String(datetime.to_string()),
+        toml::Value::Array(array) => Value::Array(array.into_iter().map(from_toml).collect()),
+        toml::Value::Table(table) => Value::Object(
+            table
+                .into_iter()
+                .map(|(key, value)| (key, from_toml(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// Splits the front matter off the start of an article, returning it along with the rest of the
+/// article.
+pub fn split(content: &str) -> Result<(Option<FrontMatter>, &str), failure::Error> {
+    let content = content.trim_start_matches('\u{feff}');
+    let mut lines = content.split_inclusive('\n');
+    let delimiter = match lines.next().map(str::trim_end) {
+        Some(delimiter @ ("---" | "+++")) => delimiter,
+        _ => return Ok((None, content)),
+    };
+
+    let start = content.find('\n').map_or(content.len(), |i| i + 1);
+    let mut end = start;
+    let mut rest = None;
+    for line in lines {
+        let trimmed = line.trim_end();
+        if trimmed == delimiter || (delimiter == "---" && trimmed == "...") {
+            rest = Some(end + line.len());
+            break;
+        }
+        end += line.len();
+    }
+    let rest = rest.ok_or_else(|| failure::err_msg("Front matter isn't closed"))?;
+
+    let source = &content[start..end];
+    let value = match delimiter {
+        "---" => load_yaml(source)?,
+        _ => from_toml(
+            toml::from_str(source)
+                .map_err(|e| failure::format_err!("Invalid front matter: {}", e))?,
+        ),
+    };
+    let front_matter = match value {
+        Value::Null => FrontMatter::default(),
+        value => serde_json::from_value(value)
+            .map_err(|e| failure::format_err!("Invalid front matter: {}", e))?,
+    };
+    Ok((
+        Some(front_matter),
+        content[rest..].trim_start_matches(['\r', '\n']),
+    ))
+}
+
+/// Exports an article as markdown with YAML front matter.
+pub fn export(article: &Article) -> String {
+    // JSON strings are valid YAML, and quote anything that needs to be
+    let quote = |s: &str| serde_json::to_string(s).unwrap_or_default();
+    let mut output = String::from("---\n");
+    output.push_str(&format!("title: {}\n", quote(&article.title)));
+    output.push_str(&format!("url: {}\n", quote(&article.url)));
+    output.push_str(&format!("author: {}\n", quote(&article.author)));
+    output.push_str(&format!(
+        "date: {}\n",
+        article.date.format("%Y-%m-%dT%H:%M:%SZ")
+    ));
+    output.push_str(&format!(
+        "updated: {}\n",
+        article.updated_at.format("%Y-%m-%dT%H:%M:%SZ")
+    ));
+    if !article.tags.is_empty() {
+        let tags: Vec<String> = article.tags.iter().map(|t| quote(t)).collect();
+        output.push_str(&format!("tags: [{}]\n", tags.join(", ")));
+    }
+    if let Some(summary) = &article.summary {
+        output.push_str(&format!("summary: {}\n", quote(summary)));
+    }
+    output.push_str(&format!("visible: {}\n", article.visible));
+    output.push_str(&format!("toc: {}\n", article.toc));
+    output.push_str("---\n\n");
+    output.push_str(&article.content);
+    if !output.ends_with('\n') {
+        output.push('\n');
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::{export, split};
+    use crate::article::Article;
+
+    #[test]
+    fn yaml() {
+        let content = "---\ntitle: \"Hello: world\"\nslug: hello\ndate: 2024-03-01 12:30\n\
+                       tags: [rust, \" web \", rust]\ndraft: true\ndescription: Short\n\
+                       layout: post\n---\n\n# Body\n";
+        let (front, body) = split(content).unwrap();
+        let front = front.unwrap();
+        assert_eq!(front.title.as_deref(), Some("Hello: world"));
+        assert_eq!(front.url.as_deref(), Some("hello"));
+        assert_eq!(
+            front.date().unwrap(),
+            NaiveDate::from_ymd_opt(2024, 3, 1)
+                .unwrap()
+                .and_hms_opt(12, 30, 0)
+        );
+        assert_eq!(front.tags().unwrap(), vec!["rust", "web"]);
+        assert_eq!(front.visible(), Some(false));
+        assert_eq!(front.summary(), Some(Some(String::from("Short"))));
+        assert_eq!(body, "# Body\n");
+    }
+
+    #[test]
+    fn aliases() {
+        // Each level refers to the one above ten times, which would take gigabytes to load
+        let mut content = String::from("---\na0: &a0 [lol]\n");
+        for i in 1..10 {
+            let refs = vec![format!("*a{}", i - 1); 10].join(", ");
+            content.push_str(&format!("a{}: &a{} [{}]\n", i, i, refs));
+        }
+        content.push_str("title: laughs\n---\n");
+        let error = split(&content).err().unwrap();
+        assert!(error.to_string().contains("aliases"));
+        // Anchors without aliases are harmless
+        let (front, _) = split("---\ntitle: &t Hello\n---\n").unwrap();
+        assert_eq!(front.unwrap().title.as_deref(), Some("Hello"));
+    }
+
+    #[test]
+    fn toml() {
+        let content =
+            "+++\ntitle = \"Hi\"\ndate = 2024-03-01T12:30:00+02:00\ntags = \"a, b\"\n+++\nBody";
+        let (front, body) = split(content).unwrap();
+        let front = front.unwrap();
+        assert_eq!(front.title.as_deref(), Some("Hi"));
+        assert_eq!(
+            front.date().unwrap(),
+            NaiveDate::from_ymd_opt(2024, 3, 1)
+                .unwrap()
+                .and_hms_opt(10, 30, 0)
+        );
+        assert_eq!(front.tags().unwrap(), vec!["a", "b"]);
+        assert_eq!(front.visible(), None);
+        assert_eq!(body, "Body");
+    }
+
+    #[test]
+    fn plain() {
+        let (front, body) = split("--- not front matter\n").unwrap();
+        assert!(front.is_none());
+        assert_eq!(body, "--- not front matter\n");
+        assert!(split("---\ntitle: unclosed\n").is_err());
+        assert!(split("---\ntitle: [1\n---\n").is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let date = NaiveDate::from_ymd_opt(2024, 3, 1)
+            .unwrap()
+            .and_hms_opt(12, 30, 0)
+            .unwrap();
+        let updated_at = date + chrono::Duration::days(2);
+        let article = Article {
+            id: 1,
+            title: String::from("Quotes \" and: colons"),
+            author: String::from("root"),
+            url: String::from("quotes"),
+            content: String::from("Body\n"),
+            date,
+            visible: true,
+            updated_at,
+            toc: false,
+            tags: vec![String::from("a"), String::from("b c")],
+            summary: Some(String::from("Line\nbreak")),
+            series: None,
+            series_part: None,
+        };
+        let exported = export(&article);
+        let (front, body) = split(&exported).unwrap();
+        let front = front.unwrap();
+        assert_eq!(front.title.as_deref(), Some(article.title.as_str()));
+        assert_eq!(front.url.as_deref(), Some("quotes"));
+        assert_eq!(front.date().unwrap(), Some(date));
+        assert_eq!(front.updated().unwrap(), Some(updated_at));
+        assert_eq!(front.tags().unwrap(), article.tags);
+        assert_eq!(front.summary(), Some(article.summary.clone()));
+        assert_eq!(front.visible(), Some(true));
+        assert_eq!(front.toc, Some(false));
+        assert_eq!(body, "Body\n");
+    }
+}
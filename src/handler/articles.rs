@@ -10,6 +10,8 @@ use gotham::mime::APPLICATION_JSON as JSON;
 
 use crate::{
     article::{self, ArticleChanges, NewArticle},
+    caching,
+    render::Renderer,
     user::{Permission, Session},
     DbConnection,
 };
@@ -50,7 +52,8 @@ pub fn view(state: &State) -> Result<Response<Body>, failure::Error> {
 
     let article = article::view(connection, id)?;
     let content = serde_json::to_string(&article)?;
-    let response = create_response(state, StatusCode::OK, JSON, content);
+    let mut response = create_response(state, StatusCode::OK, JSON, content);
+    caching::set_last_modified(&mut response, article.updated_at);
     Ok(response)
 }
 
@@ -58,14 +61,20 @@ pub fn submit(state: &State, post: Vec<u8>) -> Result<Response<Body>, failure::E
     let connection = &DbConnection::borrow_from(state).lock()?;
 
     // Check for CreateArticle permission
-    match Session::try_borrow_from(state) {
-        Some(session) if session.allowed(Permission::CreateArticle, connection)? => (),
+    let session = match Session::try_borrow_from(state) {
+        Some(session) if session.allowed(Permission::CreateArticle, connection)? => session,
         _ => return Err(failure::err_msg("Permission denied")),
-    }
+    };
 
-    let new: NewArticle = serde_json::from_slice(&post)?;
+    let mut new: NewArticle = serde_json::from_slice(&post)?;
+    new.read_front_matter()?;
+    new.credit(
+        &session.user,
+        session.allowed(Permission::EditForeignArticle, connection)?,
+    );
 
     article::submit(connection, &new)?;
+    Renderer::borrow_from(state).prepare(&new.content);
     Ok(create_empty_response(state, StatusCode::OK))
 }
 
@@ -78,15 +87,17 @@ pub fn edit(state: &State, post: Vec<u8>) -> Result<Response<Body>, failure::Err
         Some(session)
             if session.allowed(Permission::EditForeignArticle, connection)?
                 || session.allowed(Permission::EditArticle, connection)?
-                    && article::author(connection, id)? == session.user =>
-        {
-        }
+                    && article::is_author(connection, id, &session.user)? => {}
         _ => return Err(failure::err_msg("Permission denied")),
     }
 
-    let changes: ArticleChanges = serde_json::from_slice(&post)?;
+    let mut changes: ArticleChanges = serde_json::from_slice(&post)?;
+    changes.read_front_matter()?;
 
     article::edit(connection, id, &changes)?;
+    let renderer = Renderer::borrow_from(state);
+    renderer.prepare(&changes.content);
+    renderer.invalidate(id);
     Ok(create_empty_response(state, StatusCode::OK))
 }
 
@@ -0,0 +1,90 @@
+//! Handler for downloading a backup of the site
+use chrono::Utc;
+use futures::stream;
+use gotham::{
+    helpers::http::response::create_response,
+    hyper::{
+        header::{HeaderValue, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_LENGTH},
+        Body, Response, StatusCode,
+    },
+    mime,
+    state::{FromState, State},
+};
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, Read, Seek, SeekFrom},
+};
+
+use crate::{
+    backup,
+    config::Settings,
+    user::{Permission, Session},
+    DbConnection,
+};
+
+/// Size of the chunks backups are sent in
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Creates an anonymous temporary file, which is removed as soon as it's closed.
+fn temp_file() -> Result<File, failure::Error> {
+    let path = std::env::temp_dir().join(format!("mogger-backup-{:016x}", rand::random::<u64>()));
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    fs::remove_file(&path)?;
+    Ok(file)
+}
+
+/// Streams a file in chunks, so it never has to be in memory as a whole.
+fn file_body(file: File) -> Body {
+    Body::wrap_stream(stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let mut chunk = vec![0; CHUNK_SIZE];
+        match file.read(&mut chunk) {
+            Ok(0) => None,
+            Ok(len) => {
+                chunk.truncate(len);
+                Some((Ok(chunk), Some(file)))
+            }
+            Err(e) => Some((Err(e), None)),
+        }
+    }))
+}
+
+/// Serves a backup of the site. Only allowed for administrators, as it contains every user's email
+/// address.
+pub fn download(state: &State) -> Result<Response<Body>, failure::Error> {
+    let connection = &DbConnection::from_state(state)?;
+    match Session::try_borrow_from(state) {
+        Some(session) if session.allowed(Permission::All, connection)? => (),
+        _ => return Err(failure::err_msg("Permission denied")),
+    }
+
+    // Backups are written to a file rather than memory, as media files can make them large
+    let mut file = temp_file()?;
+    backup::export(
+        connection,
+        &Settings::borrow_from(state).media,
+        BufWriter::new(&file),
+    )?;
+    let length = file.seek(SeekFrom::End(0))?;
+    file.seek(SeekFrom::Start(0))?;
+
+    let media_type: mime::Mime = "application/gzip".parse()?;
+    let mut response = create_response(state, StatusCode::OK, media_type, file_body(file));
+    response
+        .headers_mut()
+        .insert(CONTENT_LENGTH, HeaderValue::from(length));
+    let disposition = format!(
+        "attachment; filename=\"mogger-backup-{}.tar.gz\"",
+        Utc::now().format("%Y-%m-%d")
+    );
+    // Neither stored nor buffered to tag it, which would defeat streaming it
+    let headers = response.headers_mut();
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    headers.insert(CONTENT_DISPOSITION, HeaderValue::from_str(&disposition)?);
+    Ok(response)
+}
@@ -12,6 +12,8 @@ use crate::{
     config::Settings,
     document::TemplateExt,
     handler::articles::ArticlePath,
+    metrics,
+    render::Renderer,
     user::{
         Permission::{DeleteComment, DeleteForeignComment, EditComment, EditForeignComment},
         Session,
@@ -67,7 +69,7 @@ pub fn render_content(state: &State) -> Result<Response<Body>, failure::Error> {
             state,
             StatusCode::OK,
             TEXT_HTML,
-            comment.formatted(),
+            comment.formatted(Renderer::borrow_from(state)),
         ))
     } else {
         Ok(create_empty_response(state, StatusCode::NOT_FOUND))
@@ -86,6 +88,7 @@ pub fn render(state: &State) -> Result<Response<Body>, failure::Error> {
             comment: &comment,
             children: Vec::new(),
             connection,
+            renderer: Renderer::borrow_from(state),
             session,
             can_comment,
         };
@@ -113,7 +116,13 @@ pub fn submit(state: &State, post: Vec<u8>) -> Result<Response<Body>, failure::E
         return Err(failure::err_msg("Permission denied"));
     }
 
+    let kind = if new.author.is_some() {
+        "user"
+    } else {
+        "guest"
+    };
     let submitted = comment::submit(connection, new)?;
+    metrics::COMMENTS_SUBMITTED.with_label_values(&[kind]).inc();
     let content = serde_json::to_string(&submitted)?;
     Ok(create_response(state, StatusCode::OK, JSON, content))
 }
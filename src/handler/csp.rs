@@ -0,0 +1,48 @@
+//! Handler for Content-Security-Policy violation reports
+use gotham::{
+    helpers::http::response::create_empty_response,
+    hyper::{Body, Response, StatusCode},
+    state::{request_id, State},
+};
+use serde_json::Value;
+
+/// Largest report that is accepted
+const MAX_REPORT_LEN: usize = 16 * 1024;
+
+/// Logs a violation report sent by a browser. Accepts both the `report-uri` format and batches
+/// from the Reporting API.
+pub fn report(state: &State, post: Vec<u8>) -> Result<Response<Body>, failure::Error> {
+    if post.len() > MAX_REPORT_LEN {
+        return Ok(create_empty_response(state, StatusCode::PAYLOAD_TOO_LARGE));
+    }
+    let reports = match serde_json::from_slice(&post) {
+        Ok(Value::Array(reports)) => reports
+            .into_iter()
+            .filter_map(|mut report| report.get_mut("body").map(Value::take))
+            .collect(),
+        Ok(Value::Object(mut report)) => report.remove("csp-report").into_iter().collect(),
+        _ => Vec::new(),
+    };
+    if reports.is_empty() {
+        return Ok(create_empty_response(state, StatusCode::BAD_REQUEST));
+    }
+
+    for report in reports {
+        // The two formats name the fields differently
+        let field = |names: &[&str]| {
+            names
+                .iter()
+                .find_map(|name| report.get(*name).and_then(Value::as_str))
+                .unwrap_or("-")
+                .to_owned()
+        };
+        log::warn!(
+            request_id = request_id(state),
+            document = field(&["document-uri", "documentURL"]).as_str(),
+            directive = field(&["effective-directive", "effectiveDirective", "violated-directive"]).as_str(),
+            blocked = field(&["blocked-uri", "blockedURL"]).as_str();
+            "Content security policy violation"
+        );
+    }
+    Ok(create_empty_response(state, StatusCode::NO_CONTENT))
+}
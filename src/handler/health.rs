@@ -0,0 +1,100 @@
+//! Health and readiness checks for service managers and load balancers
+use diesel::RunQueryDsl;
+use gotham::{
+    helpers::http::response::create_response,
+    hyper::{Body, Response, StatusCode},
+    mime::APPLICATION_JSON as JSON,
+    state::{FromState, State},
+};
+
+use std::path::Path;
+
+use crate::{config::Settings, db, DbConnection};
+
+/// The result of a single readiness check
+#[derive(Serialize)]
+struct Check {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Check {
+    fn from_result<T, E: std::fmt::Display>(result: Result<T, E>) -> Self {
+        match result {
+            Ok(_) => Check {
+                ok: true,
+                error: None,
+            },
+            Err(e) => Check {
+                ok: false,
+                error: Some(e.to_string()),
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    status: &'static str,
+    database: Check,
+    migrations: Check,
+    assets: Check,
+}
+
+/// Liveness check. Succeeds as long as the process is able to handle requests.
+pub fn healthz(state: &State) -> Result<Response<Body>, failure::Error> {
+    let content = serde_json::json!({ "status": "ok" }).to_string();
+    Ok(create_response(state, StatusCode::OK, JSON, content))
+}
+
+/// Readiness check. Succeeds if the database is reachable and fully migrated, and the static
+/// assets directory exists.
+pub fn readyz(state: &State) -> Result<Response<Body>, failure::Error> {
+    let (database, migrations) = match DbConnection::from_state(state) {
+        Ok(connection) => {
+            let database = diesel::sql_query("SELECT 1").execute(&*connection);
+            let migrations = db::pending_migrations(&connection)
+                .map_err(failure::Error::from)
+                .and_then(|pending| match pending.as_slice() {
+                    [] => Ok(()),
+                    pending => {
+                        let names: Vec<&str> = pending.iter().map(|m| m.name).collect();
+                        Err(failure::format_err!(
+                            "pending migrations: {}",
+                            names.join(", ")
+                        ))
+                    }
+                });
+            (Check::from_result(database), Check::from_result(migrations))
+        }
+        Err(e) => {
+            let error = Err::<(), _>(e);
+            (
+                Check::from_result(error.as_ref()),
+                Check::from_result(error.as_ref()),
+            )
+        }
+    };
+    let assets_dir = &Settings::borrow_from(state).theme.assets;
+    let assets = Check::from_result(if Path::new(assets_dir).is_dir() {
+        Ok(())
+    } else {
+        Err(format!("assets directory '{}' not found", assets_dir))
+    });
+
+    let ready = database.ok && migrations.ok && assets.ok;
+    let readiness = Readiness {
+        status: if ready { "ok" } else { "unavailable" },
+        database,
+        migrations,
+        assets,
+    };
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let content = serde_json::to_string(&readiness)?;
+    Ok(create_response(state, status, JSON, content))
+}
@@ -0,0 +1,62 @@
+//! Handler for serving Prometheus metrics
+use gotham::{
+    helpers::http::response::{create_empty_response, create_response},
+    hyper::{
+        header::{HeaderMap, AUTHORIZATION, WWW_AUTHENTICATE},
+        Body, Response, StatusCode,
+    },
+    state::{FromState, State},
+};
+use prometheus::{Encoder, TextEncoder};
+
+use crate::{comment, config::Settings, metrics, user, DbConnection};
+
+/// Compares a secret in time that doesn't depend on where it differs, so it can't be guessed one
+/// byte at a time.
+fn secret_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Serves all registered metrics in the Prometheus text format. Requires a bearer token if one is
+/// configured.
+pub fn metrics(state: &State) -> Result<Response<Body>, failure::Error> {
+    if let Some(ref token) = Settings::borrow_from(state).metrics.token {
+        let authorized = HeaderMap::borrow_from(state)
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|value| secret_eq(value.as_bytes(), token.as_bytes()));
+        if !authorized {
+            let mut response = create_empty_response(state, StatusCode::UNAUTHORIZED);
+            response
+                .headers_mut()
+                .insert(WWW_AUTHENTICATE, "Bearer".parse()?);
+            return Ok(response);
+        }
+    }
+
+    {
+        let connection = &DbConnection::from_state(state)?;
+        metrics::ACTIVE_SESSIONS.set(user::active_sessions(connection)?);
+        metrics::PENDING_COMMENTS.set(comment::pending_count(connection)?);
+    }
+
+    let encoder = TextEncoder::new();
+    let mut buf = Vec::new();
+    encoder.encode(&prometheus::gather(), &mut buf)?;
+    let media_type: gotham::mime::Mime = encoder.format_type().parse()?;
+    Ok(create_response(state, StatusCode::OK, media_type, buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::secret_eq;
+
+    #[test]
+    fn secrets() {
+        assert!(secret_eq(b"1234", b"1234"));
+        assert!(!secret_eq(b"1234", b"1235"));
+        assert!(!secret_eq(b"1234", b"12345"));
+        assert!(!secret_eq(b"", b"1234"));
+    }
+}
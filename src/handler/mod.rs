@@ -3,9 +3,9 @@ use futures::prelude::*;
 use gotham::{
     handler::HandlerFuture,
     helpers::http::response::create_response,
-    hyper::{body, Body, Response, StatusCode},
-    state::{FromState, State},
+    hyper::{body, Body, Response, StatusCode, Uri},
     mime,
+    state::{request_id, FromState, State},
 };
 
 use std::pin::Pin;
@@ -13,12 +13,17 @@ use std::pin::Pin;
 use crate::{
     db::{Connection, DbConnection},
     document::TemplateExt,
+    theme::Theme,
     user::{Permission, Session},
 };
 
 pub mod articles;
+pub mod backup;
 pub mod comments;
+pub mod csp;
+pub mod health;
 pub mod index;
+pub mod metrics;
 pub mod rss;
 pub mod users;
 
@@ -27,6 +32,7 @@ pub mod users;
 struct ErrorTemplate<'a> {
     session: Option<&'a Session>,
     connection: &'a Connection,
+    theme: &'a Theme,
     error: String,
 }
 
@@ -51,6 +57,7 @@ pub fn error_response(state: &State, error: impl std::fmt::Display) -> Response<
         let template = ErrorTemplate {
             session: Session::try_borrow_from(state),
             connection,
+            theme: Theme::borrow_from(state),
             error: error.to_string(),
         };
         template.to_response(state)
@@ -67,7 +74,16 @@ pub fn error_response(state: &State, error: impl std::fmt::Display) -> Response<
 pub fn response(state: &State, result: Result<Response<Body>, failure::Error>) -> Response<Body> {
     match result {
         Ok(response) => response,
-        Err(error) => error_response(state, error),
+        Err(error) => {
+            let chain: Vec<String> = error.iter_chain().map(|cause| cause.to_string()).collect();
+            log::error!(
+                request_id = request_id(state),
+                path = Uri::borrow_from(state).path(),
+                chain:? = chain;
+                "{}", error
+            );
+            error_response(state, error)
+        }
     }
 }
 
@@ -1,14 +1,17 @@
 //! Handler for serving an rss feed
 use chrono::{DateTime, NaiveDateTime, Utc};
 use gotham::{
-    helpers::http::response::create_response,
+    helpers::http::response::{create_empty_response, create_response},
     hyper::{Body, Response, StatusCode},
-    state::{FromState, State},
     mime,
+    state::{FromState, State},
 };
 use rss::{ChannelBuilder, Item, ItemBuilder};
 
-use crate::{article, article::Article, DbConnection};
+use crate::{
+    article, article::Article, caching, db::Connection, handler::users::UserPath, user,
+    DbConnection,
+};
 
 impl From<Article> for Item {
     fn from(a: Article) -> Self {
@@ -36,19 +39,33 @@ fn date_format(date: NaiveDateTime) -> String {
         .to_string()
 }
 
-/// Serves an RSS encoded feed of articles
-pub fn rss(state: &State) -> Result<Response<Body>, failure::Error> {
-    let connection = &DbConnection::borrow_from(state).lock()?;
-
-    let articles = article::list(connection)?;
+/// Encodes articles as an RSS feed with the given title and description.
+fn feed(
+    state: &State,
+    connection: &Connection,
+    articles: Vec<Article>,
+    title: &str,
+    description: &str,
+) -> Result<Response<Body>, failure::Error> {
     let last_change = articles.get(0).map(|art| date_format(art.date));
-    let items: Vec<Item> = articles.into_iter().map(Into::into).collect();
+    let last_modified = articles.iter().map(|art| art.updated_at).max();
+    let authors = article::authors_of(connection, &articles)?;
+    let items: Vec<Item> = articles
+        .into_iter()
+        .zip(authors)
+        .map(|(article, authors)| {
+            let names: Vec<String> = authors.into_iter().map(|user| user.name).collect();
+            let mut item: Item = article.into();
+            item.set_author(article::byline(&names));
+            item
+        })
+        .collect();
 
     let mut buf = Vec::new();
     let channel = ChannelBuilder::default()
-        .title(env!("CARGO_PKG_NAME"))
+        .title(title)
         .link(env!("CARGO_PKG_HOMEPAGE"))
-        .description(env!("CARGO_PKG_DESCRIPTION"))
+        .description(description)
         .last_build_date(last_change.clone())
         .pub_date(last_change)
         .items(items)
@@ -57,5 +74,44 @@ pub fn rss(state: &State) -> Result<Response<Body>, failure::Error> {
     channel.pretty_write_to(&mut buf, b' ', 4)?;
 
     let media_type: mime::Mime = "application/rss+xml".parse().unwrap();
-    Ok(create_response(state, StatusCode::OK, media_type, buf))
+    let mut response = create_response(state, StatusCode::OK, media_type, buf);
+    if let Some(date) = last_modified {
+        caching::set_last_modified(&mut response, date);
+    }
+    Ok(response)
+}
+
+/// Serves an RSS encoded feed of articles
+pub fn rss(state: &State) -> Result<Response<Body>, failure::Error> {
+    let connection = &DbConnection::borrow_from(state).lock()?;
+
+    let articles = article::list(connection)?;
+    feed(
+        state,
+        connection,
+        articles,
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_DESCRIPTION"),
+    )
+}
+
+/// Serves an RSS encoded feed of the latest articles a user wrote or co-wrote
+pub fn user_rss(state: &State) -> Result<Response<Body>, failure::Error> {
+    let connection = &DbConnection::borrow_from(state).lock()?;
+
+    let user = match user::get(connection, &UserPath::borrow_from(state).user) {
+        Ok(user) => user,
+        Err(diesel::result::Error::NotFound) => {
+            return Ok(create_empty_response(state, StatusCode::NOT_FOUND))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let articles = article::by_author(connection, &user.id, 1)?;
+    feed(
+        state,
+        connection,
+        articles,
+        &format!("{} - {}", env!("CARGO_PKG_NAME"), user.name),
+        &format!("Articles by {}", user.name),
+    )
 }
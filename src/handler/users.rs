@@ -17,6 +17,13 @@ pub struct UserPath {
     pub user: String,
 }
 
+/// A page of the articles on a user's profile
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct UserPagePath {
+    pub user: String,
+    pub page: i64,
+}
+
 pub fn create(state: &State, post: Vec<u8>) -> Result<Response<Body>, failure::Error> {
     let session = Session::try_borrow_from(state);
     if session.is_none() && !Settings::borrow_from(state).features.signups {
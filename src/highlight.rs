@@ -0,0 +1,174 @@
+//! Syntax highlighting of code blocks, either with the colors of a built-in theme set inline or
+//! with CSS classes for the site's stylesheets to color.
+
+use comrak::adapters::SyntaxHighlighterAdapter;
+use syntect::{
+    easy::HighlightLines,
+    highlighting::{Theme, ThemeSet},
+    html::{
+        append_highlighted_html_for_styled_line, css_for_theme_with_class_style, ClassStyle,
+        ClassedHTMLGenerator, IncludeBackground,
+    },
+    parsing::{SyntaxReference, SyntaxSet},
+    util::LinesWithEndings,
+};
+
+use std::collections::HashMap;
+
+use crate::{config, sanitize::escape};
+
+/// Prefix of the classes code is marked up with
+pub const CLASS_PREFIX: &str = "hl-";
+
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed {
+    prefix: CLASS_PREFIX,
+};
+
+/// Gets the names of the built-in themes.
+pub fn themes() -> Vec<String> {
+    ThemeSet::load_defaults().themes.into_keys().collect()
+}
+
+/// Generates a stylesheet coloring code marked up with classes like the built-in theme `name`.
+pub fn css(name: &str) -> Result<String, failure::Error> {
+    let theme = ThemeSet::load_defaults()
+        .themes
+        .remove(name)
+        .ok_or_else(|| failure::format_err!("No theme named '{}'", name))?;
+    Ok(css_for_theme_with_class_style(&theme, CLASS_STYLE))
+}
+
+/// Builds an opening tag with the given attributes.
+fn opening_tag(tag: &str, attributes: &HashMap<String, String>) -> String {
+    let mut names: Vec<&String> = attributes.keys().collect();
+    names.sort();
+    let mut html = format!("<{}", tag);
+    for name in names {
+        html.push_str(&format!(" {}=\"{}\"", name, escape(&attributes[name])));
+    }
+    html.push('>');
+    html
+}
+
+/// Syntax highlighter for comrak
+pub struct Highlighter {
+    syntax_set: SyntaxSet,
+    /// The theme colors are taken from. Code is marked up with classes if unset.
+    theme: Option<Theme>,
+}
+
+impl Highlighter {
+    pub fn new(settings: &config::Highlight) -> Result<Self, failure::Error> {
+        let theme = match settings.classes {
+            true => None,
+            false => Some(
+                ThemeSet::load_defaults()
+                    .themes
+                    .remove(&settings.theme)
+                    .ok_or_else(|| failure::format_err!("No theme named '{}'", settings.theme))?,
+            ),
+        };
+        Ok(Highlighter {
+            syntax_set: SyntaxSet::load_defaults_newlines(),
+            theme,
+        })
+    }
+
+    /// Finds the syntax for a language, guessing it from the first line if it isn't known.
+    fn syntax(&self, lang: Option<&str>, code: &str) -> &SyntaxReference {
+        lang.filter(|lang| !lang.is_empty())
+            .and_then(|lang| self.syntax_set.find_syntax_by_token(lang))
+            .or_else(|| self.syntax_set.find_syntax_by_first_line(code))
+            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text())
+    }
+}
+
+impl SyntaxHighlighterAdapter for Highlighter {
+    fn highlight(&self, lang: Option<&str>, code: &str) -> String {
+        let syntax = self.syntax(lang, code);
+        match &self.theme {
+            Some(theme) => {
+                let mut highlighter = HighlightLines::new(syntax, theme);
+                let mut html = String::new();
+                for line in LinesWithEndings::from(code) {
+                    let regions = highlighter.highlight(line, &self.syntax_set);
+                    append_highlighted_html_for_styled_line(
+                        &regions,
+                        IncludeBackground::No,
+                        &mut html,
+                    );
+                }
+                html
+            }
+            None => {
+                let mut generator = ClassedHTMLGenerator::new_with_class_style(
+                    syntax,
+                    &self.syntax_set,
+                    CLASS_STYLE,
+                );
+                for line in LinesWithEndings::from(code) {
+                    generator.parse_html_for_line_which_includes_newline(line);
+                }
+                generator.finalize()
+            }
+        }
+    }
+
+    fn build_pre_tag(&self, attributes: &HashMap<String, String>) -> String {
+        let mut attributes = attributes.clone();
+        match self.theme.as_ref().and_then(|t| t.settings.background) {
+            Some(c) => {
+                let style = format!("background-color:#{:02x}{:02x}{:02x};", c.r, c.g, c.b);
+                attributes.insert(String::from("style"), style);
+            }
+            None if self.theme.is_none() => {
+                attributes.insert(String::from("class"), format!("{}code", CLASS_PREFIX));
+            }
+            None => (),
+        }
+        opening_tag("pre", &attributes)
+    }
+
+    fn build_code_tag(&self, attributes: &HashMap<String, String>) -> String {
+        opening_tag("code", attributes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use comrak::adapters::SyntaxHighlighterAdapter;
+
+    use std::collections::HashMap;
+
+    use super::Highlighter;
+    use crate::config::Highlight;
+
+    #[test]
+    fn classes() {
+        let settings = Highlight {
+            classes: true,
+            ..Highlight::default()
+        };
+        let highlighter = Highlighter::new(&settings).unwrap();
+        let html = highlighter.highlight(Some("rust"), "fn main() {}\n");
+        assert!(html.contains("<span class=\"hl-storage hl-type hl-function hl-rust\">fn</span>"));
+        assert!(!html.contains("style="));
+        let mut attributes = HashMap::new();
+        attributes.insert(String::from("lang"), String::from("\"rust\""));
+        assert_eq!(
+            highlighter.build_pre_tag(&attributes),
+            "<pre class=\"hl-code\" lang=\"&quot;rust&quot;\">"
+        );
+    }
+
+    #[test]
+    fn inline() {
+        let highlighter = Highlighter::new(&Highlight::default()).unwrap();
+        let html = highlighter.highlight(Some("rust"), "fn main() {}\n");
+        assert!(html.starts_with("<span style=\"color:#"));
+        assert_eq!(
+            highlighter.build_pre_tag(&HashMap::new()),
+            "<pre style=\"background-color:#eff1f5;\">"
+        );
+    }
+}
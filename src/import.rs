@@ -0,0 +1,824 @@
+//! Importing articles and comments from other blogging platforms, either from a WordPress export
+//! (WXR) or the markdown files of a Jekyll or Hugo site.
+//!
+//! Posts are first read into [`Post`]s, then added to the database by [`import`]. Anything that
+//! can't be imported is added to the [`Report`] along with the reason, and the rest is imported
+//! anyway.
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use diesel::{Connection as _, OptionalExtension};
+use percent_encoding::percent_decode_str;
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    article::{self, NewArticle},
+    comment::{self, NewComment},
+    db::Connection,
+    front_matter, user,
+};
+
+/// File extensions of posts in Jekyll and Hugo sites
+const POST_EXTENSIONS: &[&str] = &["md", "markdown", "html", "htm"];
+
+/// Tags that WordPress doesn't wrap in paragraphs
+const BLOCK_TAGS: &[&str] = &[
+    "address",
+    "blockquote",
+    "div",
+    "dl",
+    "figure",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "iframe",
+    "ol",
+    "p",
+    "pre",
+    "table",
+    "ul",
+];
+
+/// A post read from another blog
+pub struct Post {
+    /// Where the post was read from, for reporting
+    pub source: String,
+    pub title: String,
+    pub url: String,
+    /// The author's name on the other blog
+    pub author: Option<String>,
+    /// The post's content as markdown
+    pub content: String,
+    pub date: Option<NaiveDateTime>,
+    pub visible: bool,
+    pub tags: Vec<String>,
+    pub summary: Option<String>,
+    pub comments: Vec<PostComment>,
+}
+
+/// A comment on a post read from another blog
+pub struct PostComment {
+    /// The comment's id on the other blog
+    pub id: String,
+    /// The id of the comment's parent on the other blog
+    pub parent: Option<String>,
+    /// The commenter's user name on the other blog, if they had an account
+    pub user: Option<String>,
+    /// The commenter's display name
+    pub name: String,
+    /// The comment's content as markdown
+    pub content: String,
+    pub date: Option<NaiveDateTime>,
+    pub visible: bool,
+}
+
+/// Something that wasn't imported
+pub struct Skipped {
+    pub item: String,
+    pub reason: String,
+}
+
+impl fmt::Display for Skipped {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.item, self.reason)
+    }
+}
+
+/// What was imported and what was skipped
+#[derive(Default)]
+pub struct Report {
+    pub articles: usize,
+    pub comments: usize,
+    pub skipped: Vec<Skipped>,
+}
+
+impl Report {
+    fn skip(&mut self, item: impl Into<String>, reason: impl fmt::Display) {
+        self.skipped.push(Skipped {
+            item: item.into(),
+            reason: reason.to_string(),
+        });
+    }
+}
+
+/// Which users the authors of imported posts and comments become
+#[derive(Default)]
+pub struct Authors {
+    /// Names of authors on the other blog mapped to user ids. Authors that aren't in the map
+    /// don't become users, even if one has the same name, as anyone could have taken it there.
+    pub map: HashMap<String, String>,
+    /// The user posts are attributed to if their author isn't mapped to a user
+    pub default: Option<String>,
+}
+
+impl Authors {
+    /// Gets the user an author on the other blog becomes, if any.
+    fn user(&self, name: Option<&str>) -> Option<String> {
+        self.map.get(name?).cloned()
+    }
+}
+
+/// Parses a mapping of an author to a user, written as `author=user`.
+pub fn parse_mapping(mapping: &str) -> Result<(String, String), failure::Error> {
+    match mapping.split_once('=') {
+        Some((author, user)) if !author.trim().is_empty() && !user.trim().is_empty() => {
+            Ok((author.trim().to_owned(), user.trim().to_owned()))
+        }
+        _ => Err(failure::format_err!(
+            "Invalid author mapping '{}', expected author=user",
+            mapping
+        )),
+    }
+}
+
+/// An element of an XML document
+#[derive(Default)]
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    text: String,
+    children: Vec<Element>,
+}
+
+impl Element {
+    fn new(start: &BytesStart) -> Result<Element, failure::Error> {
+        let mut attributes = Vec::new();
+        for attribute in start.attributes() {
+            let attribute = attribute?;
+            let key = String::from_utf8_lossy(attribute.key.as_ref()).into_owned();
+            attributes.push((key, attribute.unescape_value()?.into_owned()));
+        }
+        Ok(Element {
+            name: String::from_utf8_lossy(start.name().as_ref()).into_owned(),
+            attributes,
+            ..Element::default()
+        })
+    }
+
+    /// Parses a document, returning an element containing its root element.
+    fn parse(xml: &str) -> Result<Element, failure::Error> {
+        let mut reader = Reader::from_str(xml);
+        let mut stack = vec![Element::default()];
+        loop {
+            match reader.read_event()? {
+                Event::Start(start) => stack.push(Element::new(&start)?),
+                Event::Empty(start) => {
+                    let element = Element::new(&start)?;
+                    Element::last(&mut stack)?.children.push(element);
+                }
+                Event::Text(text) => Element::last(&mut stack)?.text.push_str(&text.unescape()?),
+                Event::CData(data) => {
+                    let data = String::from_utf8_lossy(&data).into_owned();
+                    Element::last(&mut stack)?.text.push_str(&data);
+                }
+                Event::End(_) => {
+                    let element = stack.pop().filter(|_| !stack.is_empty());
+                    let element = element.ok_or_else(|| failure::err_msg("Unbalanced XML"))?;
+                    Element::last(&mut stack)?.children.push(element);
+                }
+                Event::Eof => break,
+                _ => (),
+            }
+        }
+        match (stack.pop(), stack.is_empty()) {
+            (Some(root), true) => Ok(root),
+            _ => Err(failure::err_msg("Unclosed XML element")),
+        }
+    }
+
+    /// Gets the element being parsed.
+    fn last(stack: &mut [Element]) -> Result<&mut Element, failure::Error> {
+        stack
+            .last_mut()
+            .ok_or_else(|| failure::err_msg("Unbalanced XML"))
+    }
+
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    /// Gets the trimmed text of a child element, or nothing if there is no such child.
+    fn text_of(&self, name: &str) -> &str {
+        self.child(name).map_or("", |child| child.text.trim())
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Wraps blocks of text separated by blank lines in paragraphs and turns line breaks into `<br>`,
+/// like WordPress does when showing posts.
+fn autop(html: &str) -> String {
+    let html = html.replace("\r\n", "\n");
+    let mut blocks = Vec::new();
+    let mut current = String::new();
+    for block in html.split("\n\n") {
+        if !current.is_empty() {
+            current.push_str("\n\n");
+        }
+        current.push_str(block);
+        // Blank lines in preformatted text don't end it
+        if current.matches("<pre").count() > current.matches("</pre>").count() {
+            continue;
+        }
+        blocks.push(std::mem::take(&mut current));
+    }
+    blocks.push(current);
+
+    let mut output = String::new();
+    for block in blocks.iter().map(|block| block.trim()) {
+        let tag: String = block
+            .strip_prefix('<')
+            .unwrap_or_default()
+            .chars()
+            .take_while(char::is_ascii_alphanumeric)
+            .collect();
+        if block.is_empty() {
+            continue;
+        } else if BLOCK_TAGS.contains(&tag.to_ascii_lowercase().as_str()) {
+            output.push_str(block);
+        } else {
+            output.push_str(&format!("<p>{}</p>", block.replace('\n', "<br>\n")));
+        }
+        output.push('\n');
+    }
+    output
+}
+
+fn html_to_markdown(html: &str) -> String {
+    html2md::parse_html(html).trim().to_owned()
+}
+
+/// Parses a date as written in WordPress exports.
+fn wordpress_date(date: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S").ok()
+}
+
+/// Reads the posts and their comments from a WordPress export.
+pub fn wxr(xml: &str, report: &mut Report) -> Result<Vec<Post>, failure::Error> {
+    let document = Element::parse(xml)?;
+    let channel = document
+        .child("rss")
+        .and_then(|rss| rss.child("channel"))
+        .ok_or_else(|| failure::err_msg("Not a WordPress export"))?;
+
+    // Comments refer to their authors by user id, not by login
+    let logins: HashMap<&str, &str> = channel
+        .children("wp:author")
+        .map(|author| {
+            (
+                author.text_of("wp:author_id"),
+                author.text_of("wp:author_login"),
+            )
+        })
+        .collect();
+
+    let mut posts = Vec::new();
+    // Pages, attachments, menu items etc. are reported together
+    let mut others: Vec<(&str, usize)> = Vec::new();
+    for item in channel.children("item") {
+        let title = item.text_of("title");
+        let source = format!("\"{}\"", title);
+
+        match item.text_of("wp:post_type") {
+            "post" => (),
+            kind => {
+                match others.iter_mut().find(|(other, _)| *other == kind) {
+                    Some((_, count)) => *count += 1,
+                    None => others.push((kind, 1)),
+                }
+                continue;
+            }
+        }
+        let visible = match item.text_of("wp:status") {
+            "publish" => true,
+            status @ ("trash" | "auto-draft" | "inherit") => {
+                report.skip(source, format_args!("the post's status is {}", status));
+                continue;
+            }
+            _ => false,
+        };
+
+        let mut comments = Vec::new();
+        for comment in item.children("wp:comment") {
+            let item = format!(
+                "comment {} on \"{}\"",
+                comment.text_of("wp:comment_id"),
+                title
+            );
+            match comment.text_of("wp:comment_type") {
+                "" | "comment" => (),
+                kind => {
+                    report.skip(item, format_args!("{}s aren't imported", kind));
+                    continue;
+                }
+            }
+            let visible = match comment.text_of("wp:comment_approved") {
+                "1" => true,
+                "0" => false,
+                status => {
+                    report.skip(item, format_args!("the comment is marked as {}", status));
+                    continue;
+                }
+            };
+            let name = match comment.text_of("wp:comment_author") {
+                "" => "Anonymous",
+                name => name,
+            };
+            comments.push(PostComment {
+                id: comment.text_of("wp:comment_id").to_owned(),
+                parent: Some(comment.text_of("wp:comment_parent"))
+                    .filter(|parent| !matches!(*parent, "" | "0"))
+                    .map(str::to_owned),
+                user: logins
+                    .get(comment.text_of("wp:comment_user_id"))
+                    .map(|login| login.to_string()),
+                name: name.to_owned(),
+                content: html_to_markdown(&autop(comment.text_of("wp:comment_content"))),
+                date: wordpress_date(comment.text_of("wp:comment_date_gmt"))
+                    .or_else(|| wordpress_date(comment.text_of("wp:comment_date"))),
+                visible,
+            });
+        }
+
+        let tags = item
+            .children("category")
+            .filter(|category| category.attribute("domain") == Some("post_tag"))
+            .map(|category| category.text.trim());
+        let summary = html_to_markdown(&autop(item.text_of("excerpt:encoded")));
+        posts.push(Post {
+            source,
+            title: title.to_owned(),
+            // Slugs with other characters than ASCII are percent encoded
+            url: percent_decode_str(item.text_of("wp:post_name"))
+                .decode_utf8_lossy()
+                .into_owned(),
+            author: Some(item.text_of("dc:creator").to_owned()).filter(|a| !a.is_empty()),
+            content: html_to_markdown(&autop(item.text_of("content:encoded"))),
+            date: wordpress_date(item.text_of("wp:post_date_gmt"))
+                .or_else(|| {
+                    DateTime::parse_from_rfc2822(item.text_of("pubDate"))
+                        .ok()
+                        .map(|date| date.naive_utc())
+                })
+                .or_else(|| wordpress_date(item.text_of("wp:post_date"))),
+            visible,
+            tags: front_matter::clean_tags(tags),
+            summary: Some(summary).filter(|summary| !summary.is_empty()),
+            comments,
+        });
+    }
+    for (kind, count) in others {
+        report.skip(
+            format!("items of type {} ({})", kind, count),
+            "only posts are imported",
+        );
+    }
+    Ok(posts)
+}
+
+/// Reads a post from a file of a Jekyll or Hugo site.
+fn site_post(path: &Path, source: String, text: &str) -> Result<Post, failure::Error> {
+    let (front_matter, body) = front_matter::split(text)?;
+    let front_matter =
+        front_matter.ok_or_else(|| failure::err_msg("the file has no front matter"))?;
+    let title = front_matter
+        .title
+        .clone()
+        .filter(|title| !title.trim().is_empty())
+        .ok_or_else(|| failure::err_msg("the post has no title"))?;
+
+    let name = |path: &Path| {
+        path.file_stem()
+            .and_then(|name| name.to_str())
+            .map(str::to_owned)
+    };
+    let mut stem = name(path).unwrap_or_default();
+    // Hugo page bundles are named after their directory
+    if stem == "index" {
+        stem = path.parent().and_then(name).unwrap_or_default();
+    }
+    // Names of Jekyll posts start with their date
+    let (file_date, slug) = match stem.get(..11).filter(|prefix| prefix.ends_with('-')) {
+        Some(prefix) => match NaiveDate::parse_from_str(&prefix[..10], "%Y-%m-%d") {
+            Ok(date) => (date.and_hms_opt(0, 0, 0), &stem[11..]),
+            Err(_) => (None, stem.as_str()),
+        },
+        None => (None, stem.as_str()),
+    };
+    let draft = path.components().any(|part| part.as_os_str() == "_drafts");
+    let html = matches!(
+        path.extension().and_then(|extension| extension.to_str()),
+        Some("html" | "htm")
+    );
+
+    Ok(Post {
+        source,
+        title,
+        url: front_matter.url.clone().unwrap_or_else(|| slug.to_owned()),
+        author: front_matter.author.clone(),
+        content: match html {
+            true => html_to_markdown(body),
+            false => body.to_owned(),
+        },
+        date: front_matter.date()?.or(file_date),
+        visible: front_matter.visible().unwrap_or(true) && !draft,
+        tags: front_matter.tags().unwrap_or_default(),
+        summary: front_matter.summary().flatten(),
+        comments: Vec::new(),
+    })
+}
+
+/// Checks if a file may be a post by its extension.
+fn is_post_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| POST_EXTENSIONS.contains(&extension))
+}
+
+/// Reads the posts from the files of a Jekyll or Hugo site, given by their paths within the site
+/// and contents. Files that aren't posts are ignored, and if there are no `_posts` or `content`
+/// directories every file is taken to be a post.
+pub fn site(files: Vec<(PathBuf, Vec<u8>)>, report: &mut Report) -> Vec<Post> {
+    let has = |dir: &str| {
+        files
+            .iter()
+            .any(|(path, _)| path.components().any(|part| part.as_os_str() == dir))
+    };
+    // Layouts and other pages are in other directories
+    let dirs: &[&str] = if has("_posts") || has("_drafts") {
+        &["_posts", "_drafts"]
+    } else if has("content") {
+        &["content"]
+    } else {
+        &[]
+    };
+
+    let mut posts = Vec::new();
+    for (path, data) in files {
+        let in_dirs = dirs.is_empty()
+            || path
+                .components()
+                .any(|part| dirs.iter().any(|dir| part.as_os_str() == *dir));
+        // Hugo's `_index.md` files are lists of posts
+        let hidden = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_none_or(|name| name.starts_with(&['_', '.'][..]));
+        if !is_post_file(&path) || !in_dirs || hidden {
+            continue;
+        }
+
+        let source = path.display().to_string();
+        let text = match String::from_utf8(data) {
+            Ok(text) => text,
+            Err(_) => {
+                report.skip(source, "the file isn't valid UTF-8");
+                continue;
+            }
+        };
+        match site_post(&path, source.clone(), &text) {
+            Ok(post) => posts.push(post),
+            Err(e) => report.skip(source, e),
+        }
+    }
+    posts
+}
+
+/// Reads the files in a directory that may be posts, along with their paths within it.
+pub fn read_dir(dir: &Path) -> Result<Vec<(PathBuf, Vec<u8>)>, failure::Error> {
+    let mut files = Vec::new();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(relative) = dirs.pop() {
+        for entry in fs::read_dir(dir.join(&relative))? {
+            let entry = entry?;
+            let path = relative.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                // Skip .git and the like
+                if !entry.file_name().to_string_lossy().starts_with('.') {
+                    dirs.push(path);
+                }
+            } else if is_post_file(&path) {
+                files.push((path, fs::read(entry.path())?));
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Reads posts from files: a WordPress export if it's a single XML file, otherwise the files of
+/// a Jekyll or Hugo site.
+pub fn read_files(
+    files: Vec<(PathBuf, Vec<u8>)>,
+    report: &mut Report,
+) -> Result<Vec<Post>, failure::Error> {
+    match files.as_slice() {
+        [(path, data)] if path.extension().is_some_and(|e| e == "xml") => {
+            wxr(std::str::from_utf8(data)?, report)
+        }
+        _ => Ok(site(files, report)),
+    }
+}
+
+/// Adds a post and its comments to the database, returning the number of comments added and the
+/// comments that were skipped.
+fn import_post(
+    connection: &Connection,
+    post: Post,
+    authors: &Authors,
+) -> Result<(usize, Vec<Skipped>), failure::Error> {
+    let author = authors
+        .user(post.author.as_deref())
+        .or_else(|| authors.default.clone())
+        .ok_or_else(|| {
+            failure::format_err!(
+                "there is no user for the author '{}'",
+                post.author.as_deref().unwrap_or_default()
+            )
+        })?;
+    // Hugo urls are paths
+    let url = match post.url.trim_matches('/').rsplit('/').next() {
+        Some(url) if !url.is_empty() => url.to_owned(),
+        _ => article::slug(&post.title),
+    };
+    if article::id_from_url(connection, &url).optional()?.is_some() {
+        return Err(failure::format_err!("the url '{}' is already in use", url));
+    }
+
+    let new = NewArticle {
+        title: post.title.clone(),
+        url,
+        content: post.content,
+        author,
+        visible: post.visible,
+        toc: false,
+        tags: post.tags,
+        summary: post.summary,
+        date: post.date,
+    };
+    let id = article::submit(connection, &new)?;
+
+    // Replies can only be added after their parents
+    let mut ids: HashMap<String, i32> = HashMap::new();
+    let mut skipped = Vec::new();
+    let mut pending = post.comments;
+    while !pending.is_empty() {
+        let (ready, waiting): (Vec<_>, Vec<_>) = pending.into_iter().partition(|comment| {
+            comment
+                .parent
+                .as_ref()
+                .is_none_or(|parent| ids.contains_key(parent))
+        });
+        if ready.is_empty() {
+            for comment in waiting {
+                skipped.push(Skipped {
+                    item: format!("comment {} on \"{}\"", comment.id, post.title),
+                    reason: String::from("the comment it replies to wasn't imported"),
+                });
+            }
+            break;
+        }
+        for comment in ready {
+            let user = authors.user(comment.user.as_deref());
+            let new = NewComment {
+                parent: comment
+                    .parent
+                    .as_ref()
+                    .and_then(|parent| ids.get(parent))
+                    .copied(),
+                article: id,
+                name: match user {
+                    Some(_) => None,
+                    None => Some(comment.name),
+                },
+                author: user,
+                content: comment.content,
+                visible: comment.visible,
+            };
+            let date = comment
+                .date
+                .or(post.date)
+                .unwrap_or_else(|| Utc::now().naive_utc());
+            let submitted = comment::submit_at(connection, &new, date)?;
+            ids.insert(comment.id, submitted.id);
+        }
+        pending = waiting;
+    }
+    Ok((ids.len(), skipped))
+}
+
+/// Adds posts and their comments to the database. Each post is added along with its comments or
+/// not at all, and posts that fail to be added are reported as skipped.
+pub fn import(
+    connection: &Connection,
+    posts: Vec<Post>,
+    authors: &Authors,
+    report: &mut Report,
+) -> Result<(), failure::Error> {
+    for id in authors.map.values().chain(&authors.default) {
+        if user::get(connection, id).optional()?.is_none() {
+            return Err(failure::format_err!("No such user '{}'", id));
+        }
+    }
+
+    for post in posts {
+        let source = post.source.clone();
+        match connection.transaction(|| import_post(connection, post, authors)) {
+            Ok((comments, skipped)) => {
+                report.articles += 1;
+                report.comments += comments;
+                report.skipped.extend(skipped);
+            }
+            Err(e) => report.skip(source, e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use std::path::PathBuf;
+
+    use super::{autop, parse_mapping, site, wxr, Authors, Report};
+
+    const WXR: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:content="http://purl.org/rss/1.0/modules/content/"
+    xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:wp="http://wordpress.org/export/1.2/"
+    xmlns:excerpt="http://wordpress.org/export/1.2/excerpt/">
+<channel>
+    <wp:author><wp:author_id>2</wp:author_id><wp:author_login>amanda</wp:author_login></wp:author>
+    <item>
+        <title>Hello &amp; welcome</title>
+        <pubDate>Wed, 01 May 2019 10:00:00 +0000</pubDate>
+        <dc:creator><![CDATA[amanda]]></dc:creator>
+        <content:encoded><![CDATA[First <b>paragraph</b>
+
+Second]]></content:encoded>
+        <excerpt:encoded><![CDATA[]]></excerpt:encoded>
+        <wp:post_date_gmt>2019-05-01 12:00:00</wp:post_date_gmt>
+        <wp:post_name>hello-%c3%a5</wp:post_name>
+        <wp:status>publish</wp:status>
+        <wp:post_type>post</wp:post_type>
+        <category domain="category" nicename="misc"><![CDATA[Misc]]></category>
+        <category domain="post_tag" nicename="rust"><![CDATA[Rust]]></category>
+        <wp:comment>
+            <wp:comment_id>5</wp:comment_id>
+            <wp:comment_author><![CDATA[Guest]]></wp:comment_author>
+            <wp:comment_date_gmt>2019-05-02 08:00:00</wp:comment_date_gmt>
+            <wp:comment_content><![CDATA[Nice]]></wp:comment_content>
+            <wp:comment_approved>1</wp:comment_approved>
+            <wp:comment_type></wp:comment_type>
+            <wp:comment_parent>0</wp:comment_parent>
+            <wp:comment_user_id>0</wp:comment_user_id>
+        </wp:comment>
+        <wp:comment>
+            <wp:comment_id>6</wp:comment_id>
+            <wp:comment_author><![CDATA[Amanda]]></wp:comment_author>
+            <wp:comment_content><![CDATA[Thanks]]></wp:comment_content>
+            <wp:comment_approved>1</wp:comment_approved>
+            <wp:comment_parent>5</wp:comment_parent>
+            <wp:comment_user_id>2</wp:comment_user_id>
+        </wp:comment>
+        <wp:comment>
+            <wp:comment_id>7</wp:comment_id>
+            <wp:comment_approved>spam</wp:comment_approved>
+        </wp:comment>
+    </item>
+    <item>
+        <title>About</title>
+        <wp:post_type>page</wp:post_type>
+    </item>
+</channel>
+</rss>"#;
+
+    #[test]
+    fn wordpress() {
+        let mut report = Report::default();
+        let posts = wxr(WXR, &mut report).unwrap();
+        assert_eq!(posts.len(), 1);
+        let post = &posts[0];
+        assert_eq!(post.title, "Hello & welcome");
+        assert_eq!(post.url, "hello-å");
+        assert_eq!(post.author.as_deref(), Some("amanda"));
+        assert_eq!(post.content, "First **paragraph**\n\nSecond");
+        assert_eq!(
+            post.date,
+            NaiveDate::from_ymd_opt(2019, 5, 1)
+                .unwrap()
+                .and_hms_opt(12, 0, 0)
+        );
+        assert!(post.visible);
+        assert_eq!(post.tags, vec!["Rust"]);
+        assert_eq!(post.summary, None);
+
+        assert_eq!(post.comments.len(), 2);
+        assert_eq!(post.comments[0].user, None);
+        assert_eq!(post.comments[0].name, "Guest");
+        assert_eq!(post.comments[1].parent.as_deref(), Some("5"));
+        assert_eq!(post.comments[1].user.as_deref(), Some("amanda"));
+
+        let skipped: Vec<String> = report.skipped.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            skipped,
+            vec![
+                "comment 7 on \"Hello & welcome\": the comment is marked as spam",
+                "items of type page (1): only posts are imported",
+            ]
+        );
+    }
+
+    #[test]
+    fn paragraphs() {
+        assert_eq!(
+            autop("One\nline\n\n<pre>a\n\nb</pre>\n\n<ul><li>x</li></ul>"),
+            "<p>One<br>\nline</p>\n<pre>a\n\nb</pre>\n<ul><li>x</li></ul>\n"
+        );
+    }
+
+    #[test]
+    fn jekyll() {
+        let file = |path: &str, text: &str| (PathBuf::from(path), text.as_bytes().to_vec());
+        let files = vec![
+            file("_layouts/post.html", "---\nlayout: default\n---\n"),
+            file(
+                "_posts/2020-01-02-first-post.md",
+                "---\ntitle: First\n---\nBody\n",
+            ),
+            file("_drafts/later.md", "---\ntitle: Later\nslug: soon\n---\n"),
+            file("_posts/2020-01-03-plain.md", "No front matter"),
+        ];
+        let mut report = Report::default();
+        let posts = site(files, &mut report);
+        assert_eq!(posts.len(), 2);
+        assert_eq!(posts[0].url, "first-post");
+        assert_eq!(
+            posts[0].date,
+            NaiveDate::from_ymd_opt(2020, 1, 2)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+        );
+        assert!(posts[0].visible);
+        assert_eq!(posts[1].url, "soon");
+        assert!(!posts[1].visible);
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(report.skipped[0].item, "_posts/2020-01-03-plain.md");
+    }
+
+    #[test]
+    fn hugo() {
+        let file = |path: &str, text: &str| (PathBuf::from(path), text.as_bytes().to_vec());
+        let files = vec![
+            file("content/_index.md", "---\ntitle: Posts\n---\n"),
+            file(
+                "content/posts/bundle/index.md",
+                "+++\ntitle = \"Bundle\"\ndraft = true\n+++\n",
+            ),
+            file("themes/x/layouts/index.html", "---\ntitle: Theme\n---\n"),
+        ];
+        let mut report = Report::default();
+        let posts = site(files, &mut report);
+        assert_eq!(posts.len(), 1);
+        assert_eq!(posts[0].url, "bundle");
+        assert!(!posts[0].visible);
+        assert!(report.skipped.is_empty());
+    }
+
+    #[test]
+    fn authors() {
+        let authors = Authors {
+            map: vec![parse_mapping(" Amanda = amanda ").unwrap()]
+                .into_iter()
+                .collect(),
+            default: Some(String::from("root")),
+        };
+        assert_eq!(authors.user(Some("Amanda")).as_deref(), Some("amanda"));
+        // Names that happen to be users aren't trusted without a mapping
+        assert_eq!(authors.user(Some("amanda")), None);
+        assert_eq!(authors.user(Some("root")), None);
+        assert_eq!(authors.user(None), None);
+        assert!(parse_mapping("amanda").is_err());
+    }
+}
@@ -0,0 +1,191 @@
+//! Leveled, structured logging and per-request access logs.
+//!
+//! Log records are written to stderr as plain text, JSON lines, or in a journald friendly format
+//! where the level is given as a syslog priority prefix and timestamps are left to the journal.
+
+use chrono::{SecondsFormat, Utc};
+use futures::prelude::*;
+use gotham::{
+    handler::HandlerFuture,
+    hyper::{header::HeaderValue, Method, Uri},
+    middleware::{Middleware, NewMiddleware},
+    state::{request_id, FromState, State},
+};
+use log::{
+    kv::{self, Key, Value, VisitSource},
+    Level, LevelFilter, Log, Metadata, Record,
+};
+use serde_json::{Map, Value as Json};
+
+use std::{fmt::Write as _, io::Write as _, pin::Pin, time::Instant};
+
+use crate::{config, user::Session};
+
+/// Output format of log records
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Human readable lines with a timestamp
+    Plain,
+    /// One JSON object per line
+    Json,
+    /// Human readable lines prefixed with a syslog priority, for running under systemd
+    Journald,
+}
+
+struct Logger {
+    level: LevelFilter,
+    format: Format,
+}
+
+/// Collects the key-value pairs of a record as plain text.
+struct PlainVisitor<'a>(&'a mut String);
+
+impl<'kvs> VisitSource<'kvs> for PlainVisitor<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let value = value.to_string();
+        if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"') {
+            write!(self.0, " {}={:?}", key, value)?;
+        } else {
+            write!(self.0, " {}={}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Collects the key-value pairs of a record into a JSON object.
+struct JsonVisitor<'a>(&'a mut Map<String, Json>);
+
+impl<'kvs> VisitSource<'kvs> for JsonVisitor<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let json = if let Some(b) = value.to_bool() {
+            Json::from(b)
+        } else if let Some(n) = value.to_u64() {
+            Json::from(n)
+        } else if let Some(n) = value.to_i64() {
+            Json::from(n)
+        } else if let Some(n) = value.to_f64() {
+            Json::from(n)
+        } else {
+            Json::from(value.to_string())
+        };
+        self.0.insert(key.to_string(), json);
+        Ok(())
+    }
+}
+
+/// Syslog priority of a log level.
+fn priority(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+impl Logger {
+    fn format(&self, record: &Record) -> String {
+        match self.format {
+            Format::Json => {
+                let mut object = Map::new();
+                let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+                object.insert("time".into(), timestamp.into());
+                object.insert("level".into(), record.level().as_str().into());
+                object.insert("target".into(), record.target().into());
+                object.insert("message".into(), record.args().to_string().into());
+                let _ = record.key_values().visit(&mut JsonVisitor(&mut object));
+                Json::Object(object).to_string()
+            }
+            Format::Plain | Format::Journald => {
+                let mut line = if self.format == Format::Journald {
+                    format!("<{}>", priority(record.level()))
+                } else {
+                    let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+                    format!("{} {:5} ", timestamp, record.level())
+                };
+                let _ = write!(line, "{}: {}", record.target(), record.args());
+                let _ = record.key_values().visit(&mut PlainVisitor(&mut line));
+                line
+            }
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let line = self.format(record);
+            // There's nowhere left to report a failure to write a log line to.
+            let _ = writeln!(std::io::stderr().lock(), "{}", line);
+        }
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+/// Installs the global logger. Must only be called once.
+pub fn init(settings: &config::Log) -> Result<(), failure::Error> {
+    let logger = Logger {
+        level: settings.level,
+        format: settings.format,
+    };
+    log::set_boxed_logger(Box::new(logger)).map_err(|e| failure::err_msg(e.to_string()))?;
+    log::set_max_level(settings.level);
+    Ok(())
+}
+
+/// Middleware that logs every request along with its status, latency and user, and tags the
+/// response with the request's id.
+#[derive(Clone, NewMiddleware)]
+pub struct RequestLogMiddleware;
+
+impl Middleware for RequestLogMiddleware {
+    fn call<C>(self, state: State, chain: C) -> Pin<Box<HandlerFuture>>
+    where
+        C: FnOnce(State) -> Pin<Box<HandlerFuture>>,
+    {
+        let start = Instant::now();
+        let method = Method::borrow_from(&state).clone();
+        let path = Uri::borrow_from(&state).path().to_owned();
+
+        chain(state)
+            .then(move |mut result| {
+                let latency = start.elapsed().as_secs_f64() * 1000.0;
+                let (state, status) = match result {
+                    Ok((ref state, ref mut response)) => {
+                        if let Ok(id) = HeaderValue::from_str(request_id(state)) {
+                            response.headers_mut().insert("x-request-id", id);
+                        }
+                        (state, response.status().as_u16())
+                    }
+                    Err((ref state, ref error)) => {
+                        log::error!(
+                            request_id = request_id(state);
+                            "Failed to handle request: {}", error.cause()
+                        );
+                        (state, error.status().as_u16())
+                    }
+                };
+                let user = Session::try_borrow_from(state).map_or("-", |s| s.user.as_str());
+                log::info!(
+                    target: "access",
+                    request_id = request_id(state),
+                    method = method.as_str(),
+                    path = path.as_str(),
+                    status = status,
+                    latency_ms = latency,
+                    user = user;
+                    "{} {} {}", method, path, status
+                );
+                future::ready(result)
+            })
+            .boxed()
+    }
+}
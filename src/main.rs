@@ -10,16 +10,42 @@ extern crate diesel_migrations;
 extern crate serde;
 
 pub mod article;
+pub mod backup;
+pub mod caching;
+pub mod cli;
 pub mod comment;
+pub mod compression;
 pub mod config;
 pub mod date_format;
 pub mod db;
+pub mod diagram;
 pub mod document;
+pub mod embed;
+pub mod front_matter;
 pub mod handler;
+pub mod highlight;
+pub mod import;
+pub mod logging;
+pub mod math;
+pub mod media;
+pub mod metrics;
+pub mod multipart;
+pub mod page;
+pub mod render;
+pub mod routes;
+pub mod sanitize;
 pub mod schema;
+pub mod security;
+pub mod series;
+pub mod server;
+pub mod static_site;
+pub mod theme;
+pub mod tls;
+pub mod toc;
 pub mod user;
 
 use gotham::{
+    handler::{FileOptions, FilePathExtractor},
     hyper::{Body, Response, StatusCode},
     middleware::cookie::CookieParser,
     middleware::state::StateMiddleware,
@@ -31,9 +57,26 @@ use gotham::{
     state::State,
 };
 
-use std::{borrow::Cow, path::Path};
-
-use crate::{config::Settings, db::DbConnection, user::SessionMiddleware};
+use clap::Parser;
+use futures::{future, FutureExt};
+use listenfd::ListenFd;
+
+use std::{path::Path, sync::Arc, time::Duration};
+
+use crate::{
+    caching::CacheMiddleware,
+    cli::Command,
+    compression::CompressionMiddleware,
+    config::Settings,
+    db::DbConnection,
+    logging::RequestLogMiddleware,
+    metrics::MetricsMiddleware,
+    render::Renderer,
+    security::SecurityHeadersMiddleware,
+    theme::{AssetHandler, Theme},
+    tls::HstsMiddleware,
+    user::SessionMiddleware,
+};
 
 /// Response extender for 404 errors
 pub struct NotFound;
@@ -46,34 +89,59 @@ impl ResponseExtender<Body> for NotFound {
 }
 
 /// Builds the request router
-fn router(settings: Settings) -> Router {
-    // The directory static assets are served from. Is:
-    // STATIC_DIR environment varible if defined, otherwise
-    // STATIC_DIR compile-time environment variable if defined, otherwise
-    // local directory 'static'
-    let assets_dir: Cow<str> = if Path::new("/usr/share/mogger").is_dir() {
-        "/usr/share/mogger".into()
-    } else if let Some(compile_env) = option_env!("STATIC_DIR") {
-        compile_env.into()
-    } else {
-        "static".into()
-    };
+fn router(
+    settings: Settings,
+    connection: DbConnection,
+    theme: Theme,
+    renderer: Renderer,
+) -> Result<Router, failure::Error> {
+    // Serve precompressed .br and .gz siblings of files when present
+    let precompressed = settings.compression.enabled;
+    let assets = AssetHandler::new(
+        |dir| {
+            FileOptions::new(dir)
+                .with_cache_control(&settings.cache.files)
+                .with_gzip(precompressed)
+                .with_brotli(precompressed)
+                .build()
+        },
+        Path::new(&settings.theme.assets),
+        settings.theme.dir.as_deref().map(Path::new),
+    );
+    let media = FileOptions::new(&settings.media.dir)
+        .with_cache_control(&settings.cache.files)
+        .build();
+
+    // Serve metrics here unless they have an address of their own
+    let serve_metrics = settings.metrics.enabled && settings.metrics.address.is_none();
 
     // Set up shared state
-    let connection = DbConnection::from_url(&settings.database_url);
     let state_mw = StateMiddleware::new(connection);
+    let renderer_mw = StateMiddleware::new(renderer);
+    let hsts_mw = HstsMiddleware::new(&settings.tls)?;
+    let headers_mw = SecurityHeadersMiddleware::new(&settings.headers)?;
+    let csp_report = settings.headers.csp_report;
     let settings_mw = StateMiddleware::new(settings);
+    let theme_mw = StateMiddleware::new(theme);
     // Build pipeline
     let (chain, pipelines) = single_pipeline(
         new_pipeline()
+            .add(RequestLogMiddleware)
+            .add(MetricsMiddleware)
+            .add(hsts_mw)
+            .add(headers_mw)
             .add(state_mw)
             .add(settings_mw)
+            .add(renderer_mw)
+            .add(theme_mw)
+            .add(CompressionMiddleware)
+            .add(CacheMiddleware)
             .add(CookieParser)
             .add(SessionMiddleware)
             .build(),
     );
 
-    build_router(chain, pipelines, |route| {
+    Ok(build_router(chain, pipelines, |route| {
         use crate::handler::{articles, users};
         route.get("/").to(handler!(document::index::index));
         route
@@ -88,8 +156,15 @@ fn router(settings: Settings) -> Router {
             .post("/initial-setup")
             .to(body_handler!(document::index::init_setup_post));
 
-        route.get("/about").to(handler!(document::index::about));
+        route
+            .get("/tag/:tag")
+            .with_path_extractor::<document::index::TagPath>()
+            .to(handler!(document::index::tag));
 
+        route
+            .get("/article/:id/markdown")
+            .with_path_extractor::<articles::ArticlePath>()
+            .to(handler!(document::article::export));
         route
             .get("/article/:id")
             .with_path_extractor::<articles::ArticlePath>()
@@ -99,6 +174,14 @@ fn router(settings: Settings) -> Router {
             .get("/user/:user")
             .with_path_extractor::<users::UserPath>()
             .to(handler!(document::user::view));
+        route
+            .get("/user/:user/page/:page")
+            .with_path_extractor::<users::UserPagePath>()
+            .to(handler!(document::user::view));
+        route
+            .get("/user/:user/feed.rss")
+            .with_path_extractor::<users::UserPath>()
+            .to(handler!(handler::rss::user_rss));
         route
             .get("/user/:user/edit")
             .with_path_extractor::<users::UserPath>()
@@ -107,6 +190,10 @@ fn router(settings: Settings) -> Router {
             .post("/user/:user/profile")
             .with_path_extractor::<users::UserPath>()
             .to(body_handler!(document::user::profile_post));
+        route
+            .post("/user/:user/avatar")
+            .with_path_extractor::<users::UserPath>()
+            .to(body_handler!(document::user::avatar_post));
         route
             .post("/user/:user/password")
             .with_path_extractor::<users::UserPath>()
@@ -141,6 +228,12 @@ fn router(settings: Settings) -> Router {
             .with_path_extractor::<articles::ArticleIdPath>()
             .to(body_handler!(document::article::edit_post));
 
+        route.get("/import").to(handler!(document::import::import));
+        route
+            .post("/import")
+            .to(body_handler!(document::import::import_post));
+        route.get("/backup").to(handler!(handler::backup::download));
+
         route.scope("/api", |route| {
             route.scope("/articles", |route| {
                 route.get("/list").to(handler!(articles::list));
@@ -212,27 +305,246 @@ fn router(settings: Settings) -> Router {
             });
         });
 
-        route.get("/file/*").to_dir(&*assets_dir);
+        route
+            .get("/series/:id")
+            .with_path_extractor::<document::series::SeriesPath>()
+            .to(handler!(document::series::view));
+        route
+            .post("/series/:id")
+            .with_path_extractor::<document::series::SeriesPath>()
+            .to(body_handler!(document::series::edit_post));
+        route
+            .post("/series/:id/delete")
+            .with_path_extractor::<document::series::SeriesPath>()
+            .to(body_handler!(document::series::delete_post));
+
+        route.get("/pages").to(handler!(document::page::list));
+        route.get("/pages/edit").to(handler!(document::page::edit));
+        route
+            .post("/pages/edit")
+            .to(body_handler!(document::page::edit_post));
+        route
+            .get("/pages/edit/:id")
+            .with_path_extractor::<document::page::PageIdPath>()
+            .to(handler!(document::page::edit));
+        route
+            .post("/pages/edit/:id")
+            .with_path_extractor::<document::page::PageIdPath>()
+            .to(body_handler!(document::page::edit_post));
+        route
+            .post("/pages/delete/:id")
+            .with_path_extractor::<document::page::PageIdPath>()
+            .to(body_handler!(document::page::delete_post));
+
+        route
+            .get("/file/*")
+            .with_path_extractor::<FilePathExtractor>()
+            .to_new_handler(assets);
+        route.get("/media/*").to_dir(media);
 
         route.get("/feed.rss").to(handler!(handler::rss::rss));
 
+        route.get("/healthz").to(handler!(handler::health::healthz));
+        route.get("/readyz").to(handler!(handler::health::readyz));
+
+        // Static pages, after the routes their slugs can't clash with. Routes are listed in
+        // `routes::ROUTES` as well.
+        route
+            .get("/:slug")
+            .with_path_extractor::<document::page::SlugPath>()
+            .to(handler!(document::page::view));
+
+        if csp_report {
+            route
+                .post(security::REPORT_PATH)
+                .to(body_handler!(handler::csp::report));
+        }
+
+        if serve_metrics {
+            route
+                .get("/metrics")
+                .to(handler!(handler::metrics::metrics));
+        }
+
         // Error responders
         route.add_response_extender(StatusCode::NOT_FOUND, NotFound);
+    }))
+}
+
+/// Builds the router for serving metrics on a separate address
+fn metrics_router(settings: Settings, connection: DbConnection) -> Router {
+    let (chain, pipelines) = single_pipeline(
+        new_pipeline()
+            .add(StateMiddleware::new(connection))
+            .add(StateMiddleware::new(settings))
+            .build(),
+    );
+
+    build_router(chain, pipelines, |route| {
+        route
+            .get("/metrics")
+            .to(handler!(handler::metrics::metrics));
+        route.add_response_extender(StatusCode::NOT_FOUND, NotFound);
     })
 }
 
-fn main() -> Result<(), failure::Error> {
+/// Router redirecting plain HTTP requests to HTTPS
+fn redirect_router(https_port: Option<u16>) -> Router {
+    let (chain, pipelines) = single_pipeline(
+        new_pipeline()
+            .add(StateMiddleware::new(tls::HttpsPort(https_port)))
+            .build(),
+    );
+
+    build_router(chain, pipelines, |route| {
+        route.get_or_head("/").to(tls::redirect);
+        route.get_or_head("/*").to(tls::redirect);
+    })
+}
+
+fn main() {
+    if let Err(e) = run(cli::Args::parse()) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(args: cli::Args) -> Result<(), failure::Error> {
     // Read settings
-    let path = if Path::new("/etc/mogger/mogger.toml").is_file() {
-        Path::new("/etc/mogger/mogger.toml")
-    } else {
-        Path::new("mogger.toml")
-    };
-    let data = std::fs::read(path)?;
-    let settings = Settings::from_slice(&data)?;
+    let path = config::find(args.config);
+    let settings = Settings::load(path.as_deref())?;
+    logging::init(&settings.log)?;
+
+    match args.command.unwrap_or_default() {
+        Command::Serve { no_migrate } => serve(settings, !no_migrate),
+        Command::Build { out, full } => build(settings, &out, full),
+        command => cli::run(command, &settings, path.as_deref()),
+    }
+}
+
+/// Renders the site to static files in `out`.
+fn build(settings: Settings, out: &Path, full: bool) -> Result<(), failure::Error> {
+    media::process_all(&settings.media)?;
+
+    let theme = Theme::load(&settings.theme)?;
+    let connection = DbConnection::from_url(&settings.database_url);
+    if full {
+        article::clear_rendered(&*connection.lock()?)?;
+    }
+    theme.load_nav(&*connection.lock()?)?;
+    let renderer = Renderer::new(&settings)?;
+    let main = router(
+        settings.clone(),
+        connection.clone(),
+        theme.clone(),
+        renderer,
+    )?;
+    let summary = static_site::build(main, &connection, &settings, &theme, out, full)?;
+    println!(
+        "Rendered {} pages, {} unchanged, removed {}, copied {} files",
+        summary.rendered, summary.unchanged, summary.removed, summary.copied
+    );
+    Ok(())
+}
+
+/// Runs the web server, applying pending migrations first if `migrate` is set.
+fn serve(settings: Settings, migrate: bool) -> Result<(), failure::Error> {
     let address = settings.host_address.clone();
+    let timeout = Duration::from_secs(settings.shutdown_timeout);
+
+    media::process_all(&settings.media)?;
+
+    let theme = Theme::load(&settings.theme)?;
+    let connection = DbConnection::from_url(&settings.database_url);
+    if migrate {
+        db::migrate(&*connection.lock()?)?;
+    }
+    theme.load_nav(&*connection.lock()?)?;
+    // Cached renderings depend on settings and media files that may have changed since last run
+    article::clear_rendered(&*connection.lock()?)?;
+    let renderer = Renderer::new(&settings)?.with_background_diagrams();
+    let pruned = renderer.prune_diagrams(&*connection.lock()?)?;
+    if pruned > 0 {
+        log::info!("Removed {} unused diagrams", pruned);
+    }
 
-    println!("Running at {}", &address);
-    gotham::start(address, router(settings))?;
+    let tls = settings.tls.clone();
+    let certificate = match tls.enabled {
+        true => Some(Arc::new(tls::Certificate::load(&tls)?)),
+        false => None,
+    };
+    let acceptor = match &certificate {
+        Some(certificate) => Some(tls::acceptor(&tls, certificate.clone())?),
+        None => None,
+    };
+
+    // Use the sockets passed by systemd if socket activated. They're assigned in the order site,
+    // metrics, HTTPS redirect, skipping those that aren't configured.
+    let mut fds = ListenFd::from_env();
+    let mut fd_index = 0..;
+    let listener = server::listener(&mut fds, fd_index.next().unwrap_or(0), &address)?;
+    let local_address = listener.local_addr()?;
+    let metrics = &settings.metrics;
+    let metrics_listener = match metrics.address.as_ref().filter(|_| metrics.enabled) {
+        Some(metrics_address) => {
+            let index = fd_index.next().unwrap_or(0);
+            let listener = server::listener(&mut fds, index, metrics_address)?;
+            log::info!("Serving metrics at {}", listener.local_addr()?);
+            Some((
+                listener,
+                metrics_router(settings.clone(), connection.clone()),
+            ))
+        }
+        None => None,
+    };
+    let redirect_listener = match tls.redirect_address.as_ref().filter(|_| tls.enabled) {
+        Some(redirect_address) => {
+            let index = fd_index.next().unwrap_or(0);
+            let listener = server::listener(&mut fds, index, redirect_address)?;
+            log::info!("Redirecting to HTTPS from {}", listener.local_addr()?);
+            let port = Some(local_address.port()).filter(|&port| port != 443);
+            Some((listener, redirect_router(port)))
+        }
+        None => None,
+    };
+    let reload_interval = Duration::from_secs(tls.reload_interval);
+    let scan_interval = Duration::from_secs(settings.media.scan_interval);
+    let (media_renderer, media_connection) = (renderer.clone(), connection.clone());
+    media::watch(settings.media.clone(), scan_interval, move || {
+        media_renderer.clear(&*media_connection.lock()?)
+    });
+    let main = router(settings, connection.clone(), theme, renderer)?;
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async {
+        let shutdown = server::shutdown_signal()?.shared();
+        let mut servers = Vec::new();
+        match acceptor {
+            Some(acceptor) => {
+                let serve = server::serve_tls(listener, acceptor, main, shutdown.clone(), timeout);
+                servers.push(serve.boxed());
+            }
+            None => servers.push(server::serve(listener, main, shutdown.clone(), timeout).boxed()),
+        }
+        for (listener, router) in metrics_listener.into_iter().chain(redirect_listener) {
+            servers.push(server::serve(listener, router, shutdown.clone(), timeout).boxed());
+        }
+        if let Some(certificate) = certificate {
+            tokio::spawn(async move {
+                if let Err(e) = certificate.watch(reload_interval).await {
+                    log::error!("Failed to watch TLS certificate: {}", e);
+                }
+            });
+        }
+        let scheme = if tls.enabled { "https" } else { "http" };
+        log::info!("Running at {}://{}", scheme, local_address);
+        server::notify_ready();
+        future::try_join_all(servers).await?;
+        Ok::<_, failure::Error>(())
+    })?;
+    // Drop the requests that didn't finish in time, so this is the last handle to the database
+    runtime.shutdown_timeout(Duration::from_secs(1));
+    drop(connection);
+    log::info!("Closed database connection");
     Ok(())
 }
@@ -0,0 +1,739 @@
+//! Conversion of LaTeX math to MathML, so equations can be rendered without client-side scripts.
+//!
+//! Only the commonly used subset of LaTeX is understood: scripts, fractions, roots, accents,
+//! delimiters, font styles, the usual symbols and the matrix and alignment environments. Anything
+//! else is rendered as an error in place, leaving the rest of the equation intact.
+
+use crate::sanitize::escape;
+
+/// Identifiers, the greek letters and other symbols set as variables
+const IDENTIFIERS: &[(&str, &str)] = &[
+    ("alpha", "α"),
+    ("beta", "β"),
+    ("gamma", "γ"),
+    ("delta", "δ"),
+    ("epsilon", "ϵ"),
+    ("varepsilon", "ε"),
+    ("zeta", "ζ"),
+    ("eta", "η"),
+    ("theta", "θ"),
+    ("vartheta", "ϑ"),
+    ("iota", "ι"),
+    ("kappa", "κ"),
+    ("lambda", "λ"),
+    ("mu", "μ"),
+    ("nu", "ν"),
+    ("xi", "ξ"),
+    ("pi", "π"),
+    ("varpi", "ϖ"),
+    ("rho", "ρ"),
+    ("varrho", "ϱ"),
+    ("sigma", "σ"),
+    ("varsigma", "ς"),
+    ("tau", "τ"),
+    ("upsilon", "υ"),
+    ("phi", "ϕ"),
+    ("varphi", "φ"),
+    ("chi", "χ"),
+    ("psi", "ψ"),
+    ("omega", "ω"),
+    ("infty", "∞"),
+    ("partial", "∂"),
+    ("nabla", "∇"),
+    ("emptyset", "∅"),
+    ("varnothing", "∅"),
+    ("hbar", "ℏ"),
+    ("ell", "ℓ"),
+    ("aleph", "ℵ"),
+    ("Re", "ℜ"),
+    ("Im", "ℑ"),
+    ("angle", "∠"),
+    ("triangle", "△"),
+];
+
+/// Upright capital greek letters
+const CAPITALS: &[(&str, &str)] = &[
+    ("Gamma", "Γ"),
+    ("Delta", "Δ"),
+    ("Theta", "Θ"),
+    ("Lambda", "Λ"),
+    ("Xi", "Ξ"),
+    ("Pi", "Π"),
+    ("Sigma", "Σ"),
+    ("Upsilon", "Υ"),
+    ("Phi", "Φ"),
+    ("Psi", "Ψ"),
+    ("Omega", "Ω"),
+];
+
+/// Operators, relations and punctuation
+const OPERATORS: &[(&str, &str)] = &[
+    ("pm", "±"),
+    ("mp", "∓"),
+    ("times", "×"),
+    ("div", "÷"),
+    ("cdot", "⋅"),
+    ("ast", "∗"),
+    ("star", "⋆"),
+    ("circ", "∘"),
+    ("bullet", "∙"),
+    ("oplus", "⊕"),
+    ("otimes", "⊗"),
+    ("leq", "≤"),
+    ("le", "≤"),
+    ("geq", "≥"),
+    ("ge", "≥"),
+    ("neq", "≠"),
+    ("ne", "≠"),
+    ("approx", "≈"),
+    ("equiv", "≡"),
+    ("sim", "∼"),
+    ("simeq", "≃"),
+    ("cong", "≅"),
+    ("propto", "∝"),
+    ("ll", "≪"),
+    ("gg", "≫"),
+    ("subset", "⊂"),
+    ("supset", "⊃"),
+    ("subseteq", "⊆"),
+    ("supseteq", "⊇"),
+    ("in", "∈"),
+    ("notin", "∉"),
+    ("ni", "∋"),
+    ("cup", "∪"),
+    ("cap", "∩"),
+    ("setminus", "∖"),
+    ("land", "∧"),
+    ("wedge", "∧"),
+    ("lor", "∨"),
+    ("vee", "∨"),
+    ("neg", "¬"),
+    ("lnot", "¬"),
+    ("forall", "∀"),
+    ("exists", "∃"),
+    ("to", "→"),
+    ("rightarrow", "→"),
+    ("leftarrow", "←"),
+    ("gets", "←"),
+    ("Rightarrow", "⇒"),
+    ("Leftarrow", "⇐"),
+    ("leftrightarrow", "↔"),
+    ("Leftrightarrow", "⇔"),
+    ("iff", "⟺"),
+    ("implies", "⟹"),
+    ("mapsto", "↦"),
+    ("uparrow", "↑"),
+    ("downarrow", "↓"),
+    ("mid", "∣"),
+    ("parallel", "∥"),
+    ("perp", "⊥"),
+    ("ldots", "…"),
+    ("dots", "…"),
+    ("cdots", "⋯"),
+    ("vdots", "⋮"),
+    ("ddots", "⋱"),
+    ("colon", ":"),
+    ("prime", "′"),
+];
+
+/// Operators with limits set below and above them in display math
+const LARGE_OPERATORS: &[(&str, &str)] = &[
+    ("sum", "∑"),
+    ("prod", "∏"),
+    ("coprod", "∐"),
+    ("bigcup", "⋃"),
+    ("bigcap", "⋂"),
+    ("bigoplus", "⨁"),
+    ("bigotimes", "⨂"),
+];
+
+/// Integrals, which are large but take scripts at their side
+const INTEGRALS: &[(&str, &str)] = &[("int", "∫"), ("iint", "∬"), ("iiint", "∭"), ("oint", "∮")];
+
+/// Named functions, set upright
+const FUNCTIONS: &[&str] = &[
+    "sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos", "arctan", "sinh", "cosh", "tanh",
+    "log", "ln", "lg", "exp", "det", "dim", "ker", "deg", "gcd", "hom", "arg", "Pr",
+];
+
+/// Functions with limits, like the large operators
+const LIMIT_FUNCTIONS: &[&str] = &["lim", "limsup", "liminf", "max", "min", "sup", "inf"];
+
+/// Delimiters that can follow `\left` and `\right`
+const DELIMITERS: &[(&str, &str)] = &[
+    ("{", "{"),
+    ("}", "}"),
+    ("|", "‖"),
+    ("langle", "⟨"),
+    ("rangle", "⟩"),
+    ("lfloor", "⌊"),
+    ("rfloor", "⌋"),
+    ("lceil", "⌈"),
+    ("rceil", "⌉"),
+    ("vert", "|"),
+    ("Vert", "‖"),
+];
+
+/// Accents set over their argument
+const ACCENTS: &[(&str, &str)] = &[
+    ("hat", "^"),
+    ("widehat", "^"),
+    ("bar", "¯"),
+    ("overline", "¯"),
+    ("vec", "→"),
+    ("dot", "˙"),
+    ("ddot", "¨"),
+    ("tilde", "~"),
+    ("widetilde", "~"),
+];
+
+/// Widths of the spacing commands
+const SPACES: &[(&str, &str)] = &[
+    (",", "0.1667em"),
+    (":", "0.2222em"),
+    (">", "0.2222em"),
+    (";", "0.2778em"),
+    (" ", "0.3333em"),
+    ("quad", "1em"),
+    ("qquad", "2em"),
+];
+
+fn lookup<'a>(table: &[(&str, &'a str)], name: &str) -> Option<&'a str> {
+    table.iter().find(|(n, _)| *n == name).map(|(_, s)| *s)
+}
+
+/// Maps a letter or digit to its bold, double-struck or script form.
+fn styled(c: char, style: &str) -> char {
+    let offset = |base: u32, from: char| char::from_u32(base + (c as u32 - from as u32));
+    let mapped = match (style, c) {
+        ("mathbf", 'A'..='Z') => offset(0x1D400, 'A'),
+        ("mathbf", 'a'..='z') => offset(0x1D41A, 'a'),
+        ("mathbf", '0'..='9') => offset(0x1D7CE, '0'),
+        ("mathbb", 'C') => Some('ℂ'),
+        ("mathbb", 'H') => Some('ℍ'),
+        ("mathbb", 'N') => Some('ℕ'),
+        ("mathbb", 'P') => Some('ℙ'),
+        ("mathbb", 'Q') => Some('ℚ'),
+        ("mathbb", 'R') => Some('ℝ'),
+        ("mathbb", 'Z') => Some('ℤ'),
+        ("mathbb", 'A'..='Z') => offset(0x1D538, 'A'),
+        ("mathbb", '0'..='9') => offset(0x1D7D8, '0'),
+        ("mathcal", 'B') => Some('ℬ'),
+        ("mathcal", 'E') => Some('ℰ'),
+        ("mathcal", 'F') => Some('ℱ'),
+        ("mathcal", 'H') => Some('ℋ'),
+        ("mathcal", 'I') => Some('ℐ'),
+        ("mathcal", 'L') => Some('ℒ'),
+        ("mathcal", 'M') => Some('ℳ'),
+        ("mathcal", 'R') => Some('ℛ'),
+        ("mathcal", 'A'..='Z') => offset(0x1D49C, 'A'),
+        _ => None,
+    };
+    mapped.unwrap_or(c)
+}
+
+/// Where a row of math ends
+#[derive(PartialEq)]
+enum End {
+    /// At the end of the input
+    Input,
+    /// At a closing brace
+    Group,
+    /// At `\right`
+    Right,
+    /// At `&`, `\\` or `\end`
+    Cell,
+}
+
+/// How deeply groups, arguments and scripts may be nested. Deeper formulas are cut off with an
+/// error rather than overflowing the stack.
+const MAX_DEPTH: usize = 64;
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    /// The number of elements being parsed that contain the current one
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    /// Reads the name of a command, after its backslash.
+    fn command(&mut self) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+            self.pos += 1;
+        }
+        if self.pos == start && self.peek().is_some() {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    /// Gets the name of the command at the current position, without consuming it.
+    fn next_command(&self) -> String {
+        let rest = &self.chars[self.pos + 1..];
+        let len = match rest.iter().take_while(|c| c.is_ascii_alphabetic()).count() {
+            0 => rest.len().min(1),
+            len => len,
+        };
+        rest[..len].iter().collect()
+    }
+
+    /// Reads the text of a braced argument without interpreting it, or a single character.
+    fn text_argument(&mut self) -> String {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => {
+                self.pos += 1;
+                let mut depth = 0;
+                let mut text = String::new();
+                while let Some(c) = self.peek() {
+                    self.pos += 1;
+                    match c {
+                        '{' => depth += 1,
+                        '}' if depth == 0 => break,
+                        '}' => depth -= 1,
+                        _ => (),
+                    }
+                    text.push(c);
+                }
+                text
+            }
+            Some(c) => {
+                self.pos += 1;
+                c.to_string()
+            }
+            None => String::new(),
+        }
+    }
+
+    /// Reads an optional `[...]` argument.
+    fn optional_argument(&mut self) -> Option<String> {
+        self.skip_whitespace();
+        if self.peek() != Some('[') {
+            return None;
+        }
+        let start = self.pos + 1;
+        let end = self.chars[start..].iter().position(|&c| c == ']')? + start;
+        self.pos = end + 1;
+        let mut parser = Parser {
+            chars: self.chars[start..end].to_vec(),
+            pos: 0,
+            depth: self.depth,
+        };
+        Some(parser.row(&End::Input))
+    }
+
+    /// Parses a required argument into a single element.
+    fn argument(&mut self) -> String {
+        self.skip_whitespace();
+        self.atom().unwrap_or_else(|| String::from("<mrow></mrow>"))
+    }
+
+    /// Reads a delimiter after `\left` or `\right`. `.` is the empty delimiter.
+    fn delimiter(&mut self) -> String {
+        self.skip_whitespace();
+        let delimiter = match self.peek() {
+            Some('\\') => {
+                self.pos += 1;
+                let name = self.command();
+                lookup(DELIMITERS, &name).unwrap_or("").to_owned()
+            }
+            Some('.') | None => {
+                self.pos += 1;
+                String::new()
+            }
+            Some(c) => {
+                self.pos += 1;
+                c.to_string()
+            }
+        };
+        match delimiter.is_empty() {
+            true => String::new(),
+            false => format!(
+                "<mo fence=\"true\" stretchy=\"true\">{}</mo>",
+                escape(&delimiter)
+            ),
+        }
+    }
+
+    /// Parses elements until `end`, wrapping them in a row if there's more than one.
+    fn row(&mut self, end: &End) -> String {
+        let mut elements = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.at_end(end) {
+                break;
+            }
+            match self.atom() {
+                Some(element) => {
+                    let element = self.scripts(element);
+                    elements.push(element);
+                }
+                None => break,
+            }
+        }
+        match elements.len() {
+            1 => elements.remove(0),
+            _ => format!("<mrow>{}</mrow>", elements.concat()),
+        }
+    }
+
+    /// Checks if the input is at the end of the current row, without consuming the end.
+    fn at_end(&self, end: &End) -> bool {
+        match self.peek() {
+            None => true,
+            Some('}') => *end == End::Group,
+            Some('&') => *end == End::Cell,
+            Some('\\') => {
+                let name = self.next_command();
+                match end {
+                    End::Right => name == "right",
+                    End::Cell => name == "\\" || name == "end",
+                    _ => false,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// Attaches any sub- and superscripts following an element to it.
+    fn scripts(&mut self, base: String) -> String {
+        let limits = base.contains("movablelimits");
+        let mut sub = None;
+        let mut sup = None;
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some('_') if sub.is_none() => {
+                    self.pos += 1;
+                    sub = Some(self.argument());
+                }
+                Some('^') if sup.is_none() => {
+                    self.pos += 1;
+                    sup = Some(self.argument());
+                }
+                Some('\'') => {
+                    let mut primes = String::new();
+                    while self.peek() == Some('\'') {
+                        self.pos += 1;
+                        primes.push('′');
+                    }
+                    let prime = format!("<mo>{}</mo>", primes);
+                    sup = Some(match sup {
+                        Some(sup) => format!("<mrow>{}{}</mrow>", prime, sup),
+                        None => prime,
+                    });
+                }
+                _ => break,
+            }
+        }
+        let (under, over, both) = match limits {
+            true => ("munder", "mover", "munderover"),
+            false => ("msub", "msup", "msubsup"),
+        };
+        match (sub, sup) {
+            (Some(sub), Some(sup)) => format!("<{0}>{1}{2}{3}</{0}>", both, base, sub, sup),
+            (Some(sub), None) => format!("<{0}>{1}{2}</{0}>", under, base, sub),
+            (None, Some(sup)) => format!("<{0}>{1}{2}</{0}>", over, base, sup),
+            (None, None) => base,
+        }
+    }
+
+    /// Parses a single element, a group or a command with its arguments.
+    fn atom(&mut self) -> Option<String> {
+        if self.depth >= MAX_DEPTH {
+            // Skip the rest of the formula instead of descending any further
+            self.pos = self.chars.len();
+            return Some(String::from(
+                "<merror><mtext>Formula is nested too deeply</mtext></merror>",
+            ));
+        }
+        self.depth += 1;
+        let element = self.element();
+        self.depth -= 1;
+        element
+    }
+
+    /// Parses the element at the current position, see `atom`.
+    fn element(&mut self) -> Option<String> {
+        let c = self.peek()?;
+        self.pos += 1;
+        let element = match c {
+            '{' => {
+                let row = self.row(&End::Group);
+                if self.peek() == Some('}') {
+                    self.pos += 1;
+                }
+                row
+            }
+            '}' | '&' => format!("<merror><mtext>{}</mtext></merror>", c),
+            '\\' => self.command_element(),
+            '0'..='9' => {
+                let mut number = c.to_string();
+                while let Some(c @ ('0'..='9' | '.')) = self.peek() {
+                    self.pos += 1;
+                    number.push(c);
+                }
+                format!("<mn>{}</mn>", number)
+            }
+            c if c.is_alphabetic() => format!("<mi>{}</mi>", c),
+            '-' => String::from("<mo>−</mo>"),
+            '*' => String::from("<mo>∗</mo>"),
+            '_' | '^' => return Some(self.scripts(String::from("<mrow></mrow>"))),
+            c => format!("<mo>{}</mo>", escape(&c.to_string())),
+        };
+        Some(element)
+    }
+
+    /// Parses a command, after its backslash.
+    fn command_element(&mut self) -> String {
+        let name = self.command();
+        if let Some(s) = lookup(IDENTIFIERS, &name) {
+            return format!("<mi>{}</mi>", s);
+        }
+        if let Some(s) = lookup(CAPITALS, &name) {
+            return format!("<mi mathvariant=\"normal\">{}</mi>", s);
+        }
+        if let Some(s) = lookup(OPERATORS, &name) {
+            return format!("<mo>{}</mo>", escape(s));
+        }
+        if let Some(s) = lookup(LARGE_OPERATORS, &name) {
+            return format!("<mo largeop=\"true\" movablelimits=\"true\">{}</mo>", s);
+        }
+        if let Some(s) = lookup(INTEGRALS, &name) {
+            return format!("<mo largeop=\"true\">{}</mo>", s);
+        }
+        if FUNCTIONS.contains(&name.as_str()) {
+            return format!("<mi>{}</mi>", name);
+        }
+        if LIMIT_FUNCTIONS.contains(&name.as_str()) {
+            return format!("<mo movablelimits=\"true\" form=\"prefix\">{}</mo>", name);
+        }
+        if let Some(width) = lookup(SPACES, &name) {
+            return format!("<mspace width=\"{}\"></mspace>", width);
+        }
+        if let Some(accent) = lookup(ACCENTS, &name) {
+            let base = self.argument();
+            return format!(
+                "<mover accent=\"true\">{}<mo stretchy=\"true\">{}</mo></mover>",
+                base, accent
+            );
+        }
+        match name.as_str() {
+            "frac" | "dfrac" | "tfrac" => {
+                let numerator = self.argument();
+                let denominator = self.argument();
+                format!("<mfrac>{}{}</mfrac>", numerator, denominator)
+            }
+            "binom" => {
+                let n = self.argument();
+                let k = self.argument();
+                format!(
+                    "<mrow><mo>(</mo><mfrac linethickness=\"0\">{}{}</mfrac><mo>)</mo></mrow>",
+                    n, k
+                )
+            }
+            "sqrt" => match self.optional_argument() {
+                Some(index) => format!("<mroot>{}{}</mroot>", self.argument(), index),
+                None => format!("<msqrt>{}</msqrt>", self.argument()),
+            },
+            "text" | "textrm" | "mbox" => {
+                format!("<mtext>{}</mtext>", escape(&self.text_argument()))
+            }
+            "mathrm" | "operatorname" => format!(
+                "<mi mathvariant=\"normal\">{}</mi>",
+                escape(&self.text_argument())
+            ),
+            "mathbf" | "mathbb" | "mathcal" => {
+                let text: String = self
+                    .text_argument()
+                    .chars()
+                    .filter(|c| !c.is_whitespace())
+                    .map(|c| styled(c, &name))
+                    .collect();
+                format!("<mi>{}</mi>", escape(&text))
+            }
+            "left" => {
+                let open = self.delimiter();
+                let body = self.row(&End::Right);
+                let close = match self.peek() {
+                    Some('\\') => {
+                        self.pos += 1;
+                        self.command();
+                        self.delimiter()
+                    }
+                    _ => String::new(),
+                };
+                format!("<mrow>{}{}{}</mrow>", open, body, close)
+            }
+            "begin" => self.environment(),
+            // Line breaks outside of environments and unsupported spacing are dropped
+            "\\" | "!" | "displaystyle" | "limits" | "nolimits" => String::new(),
+            "{" | "}" | "$" | "%" | "&" | "#" | "_" | "|" => {
+                format!("<mo>{}</mo>", escape(&name.replace('|', "‖")))
+            }
+            _ => format!("<merror><mtext>\\{}</mtext></merror>", escape(&name)),
+        }
+    }
+
+    /// Parses the body of an environment into a table, after `\begin`.
+    fn environment(&mut self) -> String {
+        let name = self.text_argument();
+        let (open, close) = match name.as_str() {
+            "pmatrix" => ("(", ")"),
+            "bmatrix" => ("[", "]"),
+            "Bmatrix" | "cases" => ("{", ""),
+            "vmatrix" => ("|", "|"),
+            "Vmatrix" => ("‖", "‖"),
+            _ => ("", ""),
+        };
+        let close = match name.as_str() {
+            "Bmatrix" => "}",
+            _ => close,
+        };
+        let align = match name.as_str() {
+            "aligned" | "align" | "align*" | "split" => " columnalign=\"right left\"",
+            "cases" => " columnalign=\"left left\"",
+            _ => "",
+        };
+
+        let mut rows = Vec::new();
+        let mut cells = Vec::new();
+        loop {
+            let cell = self.row(&End::Cell);
+            cells.push(format!("<mtd>{}</mtd>", cell));
+            match self.peek() {
+                Some('&') => self.pos += 1,
+                Some('\\') => {
+                    self.pos += 1;
+                    let command = self.command();
+                    rows.push(format!("<mtr>{}</mtr>", cells.concat()));
+                    cells.clear();
+                    if command == "end" {
+                        self.text_argument();
+                        break;
+                    }
+                }
+                _ => {
+                    rows.push(format!("<mtr>{}</mtr>", cells.concat()));
+                    break;
+                }
+            }
+        }
+
+        let table = format!("<mtable{}>{}</mtable>", align, rows.concat());
+        let fence = |delimiter: &str| match delimiter.is_empty() {
+            true => String::new(),
+            false => format!("<mo fence=\"true\" stretchy=\"true\">{}</mo>", delimiter),
+        };
+        match open.is_empty() {
+            true => table,
+            false => format!("<mrow>{}{}{}</mrow>", fence(open), table, fence(close)),
+        }
+    }
+}
+
+/// Converts LaTeX math to a MathML element. Display math is set in its own block.
+pub fn to_mathml(tex: &str, display: bool) -> String {
+    let mut parser = Parser {
+        chars: tex.chars().collect(),
+        pos: 0,
+        depth: 0,
+    };
+    let mut body = parser.row(&End::Input);
+    // Stray closing braces end the outermost row early
+    while parser.peek().is_some() {
+        parser.pos += 1;
+        let rest = parser.row(&End::Input);
+        body = format!(
+            "<mrow>{}<merror><mtext>}}</mtext></merror>{}</mrow>",
+            body, rest
+        );
+    }
+    format!(
+        "<math display=\"{}\"><semantics>{}<annotation encoding=\"application/x-tex\">{}</annotation></semantics></math>",
+        if display { "block" } else { "inline" },
+        body,
+        escape(tex.trim())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_mathml;
+
+    /// Gets the MathML of an equation without the wrapping and annotation.
+    fn body(tex: &str) -> String {
+        let html = to_mathml(tex, false);
+        let start = html.find("<semantics>").unwrap() + "<semantics>".len();
+        let end = html.find("<annotation").unwrap();
+        html[start..end].to_owned()
+    }
+
+    #[test]
+    fn conversion() {
+        assert_eq!(
+            body("x^2 + y_i"),
+            "<mrow><msup><mi>x</mi><mn>2</mn></msup><mo>+</mo><msub><mi>y</mi><mi>i</mi></msub></mrow>"
+        );
+        assert_eq!(
+            body(r"\frac{a}{b} \leq \sqrt[3]{\alpha}"),
+            "<mrow><mfrac><mi>a</mi><mi>b</mi></mfrac><mo>≤</mo><mroot><mi>α</mi><mn>3</mn></mroot></mrow>"
+        );
+        assert_eq!(
+            body(r"\sum_{i=1}^n i"),
+            "<mrow><munderover><mo largeop=\"true\" movablelimits=\"true\">∑</mo>\
+             <mrow><mi>i</mi><mo>=</mo><mn>1</mn></mrow><mi>n</mi></munderover><mi>i</mi></mrow>"
+        );
+        assert_eq!(
+            body(r"\left( x \right) \in \mathbb{R}"),
+            "<mrow><mrow><mo fence=\"true\" stretchy=\"true\">(</mo><mi>x</mi>\
+             <mo fence=\"true\" stretchy=\"true\">)</mo></mrow><mo>∈</mo><mi>ℝ</mi></mrow>"
+        );
+        assert_eq!(
+            body(r"\begin{pmatrix} a & b \\ c & d \end{pmatrix}"),
+            "<mrow><mo fence=\"true\" stretchy=\"true\">(</mo><mtable>\
+             <mtr><mtd><mi>a</mi></mtd><mtd><mi>b</mi></mtd></mtr>\
+             <mtr><mtd><mi>c</mi></mtd><mtd><mi>d</mi></mtd></mtr></mtable>\
+             <mo fence=\"true\" stretchy=\"true\">)</mo></mrow>"
+        );
+        assert_eq!(body(r"f'"), "<msup><mi>f</mi><mo>′</mo></msup>");
+    }
+
+    #[test]
+    fn escaping() {
+        let html = to_mathml(r"a < b \text{<script>} \foo{}", true);
+        assert!(html.starts_with("<math display=\"block\">"));
+        assert!(!html.contains("<script"));
+        assert!(html.contains("<mo>&lt;</mo>"));
+        assert!(html.contains("<merror><mtext>\\foo</mtext></merror>"));
+        assert!(html.contains("application/x-tex\">a &lt; b"));
+    }
+
+    #[test]
+    fn nesting() {
+        // Run with a small stack like the server's worker threads
+        for nested in &["{", "^", r"\frac"] {
+            let tex = nested.repeat(10_000);
+            let html = std::thread::Builder::new()
+                .stack_size(2 * 1024 * 1024)
+                .spawn(move || to_mathml(&tex, false))
+                .unwrap()
+                .join()
+                .unwrap();
+            assert!(html.contains("Formula is nested too deeply"));
+        }
+        assert!(body("{{{x}}}").contains("<mi>x</mi>"));
+    }
+}
@@ -0,0 +1,688 @@
+//! Processing of managed media files.
+//!
+//! Raster images in the media directory are stripped of EXIF metadata and get resized derivatives
+//! generated at each configured width. Metadata is stripped without re-encoding the original, and
+//! only its orientation is kept. Derivatives are stored next to the original, named
+//! `<name>-<width>w.<extension>`.
+
+use flate2::Crc;
+use image::{
+    codecs::jpeg::JpegEncoder, imageops::FilterType, metadata::Orientation, DynamicImage,
+    ImageDecoder, ImageFormat, ImageReader,
+};
+use sha2::{Digest, Sha256};
+
+use std::{
+    collections::HashMap,
+    convert::{TryFrom, TryInto},
+    fs, io,
+    io::Cursor,
+    path::{Component, Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crate::config::Media;
+
+/// The url prefix media files are served from.
+pub const URL_PREFIX: &str = "/media/";
+
+/// Header of the Exif segment in JPEG files.
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+
+/// Signature every PNG file starts with.
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Directory in the media directory uploaded avatars are stored in.
+const AVATAR_DIR: &str = "avatars";
+
+/// The largest avatar that can be uploaded, in bytes.
+const MAX_AVATAR_SIZE: usize = 2 * 1024 * 1024;
+
+/// Gets the format of a file if it's a raster image we generate derivatives for.
+fn raster_format(path: &Path) -> Option<ImageFormat> {
+    match ImageFormat::from_path(path).ok()? {
+        format @ ImageFormat::Jpeg | format @ ImageFormat::Png => Some(format),
+        _ => None,
+    }
+}
+
+/// Gets the path of the derivative of `path` with the given width and extension.
+fn derivative_path(path: &Path, width: u32, extension: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}-{}w.{}", stem, width, extension))
+}
+
+/// Checks if a file is a generated derivative, i.e. its name ends with `-<digits>w`.
+fn is_derivative(path: &Path) -> bool {
+    let stem = match path.file_stem().and_then(|stem| stem.to_str()) {
+        Some(stem) => stem,
+        None => return false,
+    };
+    match stem
+        .rsplit_once('-')
+        .and_then(|(_, width)| width.strip_suffix('w'))
+    {
+        Some(width) => !width.is_empty() && width.bytes().all(|b| b.is_ascii_digit()),
+        None => false,
+    }
+}
+
+/// Writes a file through a temporary file, so an interrupted write never leaves a truncated image
+/// behind. The temporary file's name is unique, so files being replaced at the same time, even
+/// ones differing only by extension, don't share it.
+fn replace(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{:016x}.part", rand::random::<u64>()));
+    let temp = path.with_file_name(name);
+    let result = fs::write(&temp, data).and_then(|_| fs::rename(&temp, path));
+    if result.is_err() {
+        fs::remove_file(&temp).ok();
+    }
+    result
+}
+
+/// Encodes an image to the given path. JPEG and WebP images are encoded lossily at `quality`.
+fn save(
+    image: &DynamicImage,
+    path: &Path,
+    format: ImageFormat,
+    quality: u8,
+) -> Result<(), failure::Error> {
+    let mut data = Vec::new();
+    match format {
+        ImageFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut data, quality))?,
+        ImageFormat::WebP if image.color().has_alpha() => {
+            let rgba = image.to_rgba8();
+            let encoder = webp::Encoder::from_rgba(&rgba, rgba.width(), rgba.height());
+            data.extend_from_slice(&encoder.encode(f32::from(quality)));
+        }
+        ImageFormat::WebP => {
+            let rgb = image.to_rgb8();
+            let encoder = webp::Encoder::from_rgb(&rgb, rgb.width(), rgb.height());
+            data.extend_from_slice(&encoder.encode(f32::from(quality)));
+        }
+        format => image.write_to(&mut Cursor::new(&mut data), format)?,
+    }
+    replace(path, &data)?;
+    Ok(())
+}
+
+/// Builds an Exif block holding nothing but an orientation tag.
+fn orientation_exif(orientation: Orientation) -> Vec<u8> {
+    let mut exif = b"MM\0\x2a\0\0\0\x08".to_vec();
+    // A single IFD entry: tag 0x0112, one SHORT, padded to four bytes
+    exif.extend_from_slice(&[0, 1, 0x01, 0x12, 0, 3, 0, 0, 0, 1]);
+    exif.extend_from_slice(&[0, orientation.to_exif(), 0, 0]);
+    // No further IFDs
+    exif.extend_from_slice(&[0, 0, 0, 0]);
+    exif
+}
+
+/// Replaces the first Exif segment of a JPEG file with `exif` and drops the others. Returns `None`
+/// if the file isn't a well-formed JPEG.
+fn replace_jpeg_exif(data: &[u8], mut exif: Option<&[u8]>) -> Option<Vec<u8>> {
+    let mut rest = data.strip_prefix(&[0xff, 0xd8])?;
+    let mut out = data[..2].to_vec();
+    loop {
+        // Markers may be preceded by any number of fill bytes
+        let start = rest
+            .iter()
+            .position(|&b| b != 0xff)
+            .filter(|&start| start > 0)?;
+        let marker = rest[start];
+        match marker {
+            // Entropy coded data follows the start of scan, and no metadata comes after it
+            0xd9 | 0xda => break,
+            // Markers without a segment
+            0x01 | 0xd0..=0xd7 => {
+                out.extend_from_slice(&rest[..=start]);
+                rest = &rest[start + 1..];
+                continue;
+            }
+            _ => {}
+        }
+        let length = u16::from_be_bytes([*rest.get(start + 1)?, *rest.get(start + 2)?]);
+        let segment = rest.get(..start + 1 + usize::from(length))?;
+        if marker == 0xe1 && segment[start + 3..].starts_with(EXIF_HEADER) {
+            if let Some(exif) = exif.take() {
+                let length = u16::try_from(2 + EXIF_HEADER.len() + exif.len()).ok()?;
+                out.extend_from_slice(&[0xff, 0xe1]);
+                out.extend_from_slice(&length.to_be_bytes());
+                out.extend_from_slice(EXIF_HEADER);
+                out.extend_from_slice(exif);
+            }
+        } else {
+            out.extend_from_slice(segment);
+        }
+        rest = &rest[segment.len()..];
+    }
+    out.extend_from_slice(rest);
+    Some(out)
+}
+
+/// Replaces the first `eXIf` chunk of a PNG file with `exif` and drops the others. Returns `None`
+/// if the file isn't a well-formed PNG.
+fn replace_png_exif(data: &[u8], mut exif: Option<&[u8]>) -> Option<Vec<u8>> {
+    let mut rest = data.strip_prefix(PNG_SIGNATURE)?;
+    let mut out = PNG_SIGNATURE.to_vec();
+    while !rest.is_empty() {
+        let length = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?);
+        let chunk = rest.get(..usize::try_from(length).ok()? + 12)?;
+        if &chunk[4..8] == b"eXIf" {
+            if let Some(exif) = exif.take() {
+                let mut crc = Crc::new();
+                crc.update(b"eXIf");
+                crc.update(exif);
+                out.extend_from_slice(&u32::try_from(exif.len()).ok()?.to_be_bytes());
+                out.extend_from_slice(b"eXIf");
+                out.extend_from_slice(exif);
+                out.extend_from_slice(&crc.sum().to_be_bytes());
+            }
+        } else {
+            out.extend_from_slice(chunk);
+        }
+        rest = &rest[chunk.len()..];
+    }
+    Some(out)
+}
+
+/// Strips the metadata of a JPEG or PNG file without re-encoding it. Only the orientation is
+/// kept, so the image keeps being displayed the right way up.
+fn strip_metadata(data: &[u8], format: ImageFormat, orientation: Orientation) -> Option<Vec<u8>> {
+    let exif = match orientation {
+        Orientation::NoTransforms => None,
+        orientation => Some(orientation_exif(orientation)),
+    };
+    match format {
+        ImageFormat::Jpeg => replace_jpeg_exif(data, exif.as_deref()),
+        ImageFormat::Png => replace_png_exif(data, exif.as_deref()),
+        _ => None,
+    }
+}
+
+/// Strips the metadata of a raster image and generates any of its missing derivatives. Does
+/// nothing for files that aren't raster images or have already been processed.
+pub fn process(settings: &Media, path: &Path) -> Result<(), failure::Error> {
+    let format = match raster_format(path) {
+        Some(format) => format,
+        None => return Ok(()),
+    };
+
+    let data = fs::read(path)?;
+    let mut decoder = ImageReader::new(Cursor::new(&data))
+        .with_guessed_format()?
+        .into_decoder()?;
+    let exif = decoder.exif_metadata()?;
+    let orientation = decoder.orientation()?;
+    // Images we stripped still have an Exif block with their orientation
+    let has_metadata = exif.is_some_and(|exif| exif != orientation_exif(orientation));
+    let (width, _) = decoder.dimensions();
+    let extension = format.extensions_str()[0];
+    let missing = |w: u32| {
+        !derivative_path(path, w, extension).is_file()
+            || settings.webp && !derivative_path(path, w, "webp").is_file()
+    };
+    let mut widths: Vec<u32> = settings
+        .widths
+        .iter()
+        .copied()
+        .filter(|&w| w < width && missing(w))
+        .collect();
+    widths.sort_unstable();
+    widths.dedup();
+    if !has_metadata && widths.is_empty() {
+        return Ok(());
+    }
+
+    if has_metadata {
+        let stripped = strip_metadata(&data, format, orientation)
+            .ok_or_else(|| failure::err_msg("Malformed image metadata"))?;
+        replace(path, &stripped)?;
+    }
+
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    // Rotating the image may have changed its width
+    widths.retain(|&w| w < image.width());
+
+    // Our encoders don't write any metadata, so derivatives never have any.
+    for width in widths {
+        let resized = image.resize(width, u32::MAX, FilterType::Lanczos3);
+        let derivative = derivative_path(path, width, extension);
+        save(&resized, &derivative, format, settings.quality)?;
+        if settings.webp {
+            let webp = derivative_path(path, width, "webp");
+            save(&resized, &webp, ImageFormat::WebP, settings.quality)?;
+        }
+    }
+    Ok(())
+}
+
+/// Processes every image in the media directory and its subdirectories. Failures to process
+/// individual files are reported and skipped.
+pub fn process_all(settings: &Media) -> Result<(), failure::Error> {
+    for path in originals(settings)? {
+        if let Err(e) = process(settings, &path) {
+            log::warn!("Failed to process media file '{}': {}", path.display(), e);
+        }
+    }
+    Ok(())
+}
+
+/// Lists the raster images in the media directory and its subdirectories that aren't derivatives.
+fn originals(settings: &Media) -> io::Result<Vec<PathBuf>> {
+    let dir = Path::new(&settings.dir);
+    let mut originals = Vec::new();
+    if !dir.is_dir() {
+        return Ok(originals);
+    }
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if raster_format(&path).is_some() && !is_derivative(&path) {
+                originals.push(path);
+            }
+        }
+    }
+    Ok(originals)
+}
+
+/// Gets the modification times of the raster images in the media directory.
+fn modification_times(settings: &Media) -> io::Result<HashMap<PathBuf, SystemTime>> {
+    let mut times = HashMap::new();
+    for path in originals(settings)? {
+        let modified = fs::metadata(&path)?.modified()?;
+        times.insert(path, modified);
+    }
+    Ok(times)
+}
+
+/// Processes images added to or replaced in the media directory while the server runs, checking
+/// for them every `interval`. `changed` is called after any were processed, so renderings that
+/// refer to them can be updated. An `interval` of zero disables checking.
+pub fn watch<F>(settings: Media, interval: Duration, changed: F)
+where
+    F: Fn() -> Result<(), failure::Error> + Send + 'static,
+{
+    if interval.is_zero() {
+        return;
+    }
+    let mut known = modification_times(&settings).unwrap_or_default();
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        let mut current = match modification_times(&settings) {
+            Ok(current) => current,
+            Err(e) => {
+                log::warn!("Failed to scan media directory: {}", e);
+                continue;
+            }
+        };
+        let mut processed = false;
+        for (path, modified) in current.iter_mut() {
+            if known.get(path) == Some(modified) {
+                continue;
+            }
+            log::info!("Processing media file '{}'", path.display());
+            if let Err(e) = process(&settings, path) {
+                log::warn!("Failed to process media file '{}': {}", path.display(), e);
+            }
+            processed = true;
+            // Stripping metadata rewrites the file
+            if let Ok(rewritten) = fs::metadata(path).and_then(|m| m.modified()) {
+                *modified = rewritten;
+            }
+        }
+        known = current;
+        if processed {
+            if let Err(e) = changed() {
+                log::warn!("Failed to update renderings for new media files: {}", e);
+            }
+        }
+    });
+}
+
+/// Stores an uploaded avatar in the media directory and processes it like other images. Returns
+/// its path relative to the media directory, which is named after the user and its contents so a
+/// new avatar gets a new url, and users uploading the same image don't share a file.
+pub fn save_avatar(settings: &Media, user: &str, data: &[u8]) -> Result<String, failure::Error> {
+    if data.len() > MAX_AVATAR_SIZE {
+        return Err(failure::format_err!(
+            "Avatars can't be larger than {} MiB",
+            MAX_AVATAR_SIZE / 1024 / 1024
+        ));
+    }
+    let format = image::guess_format(data)
+        .ok()
+        .filter(|format| {
+            matches!(
+                format,
+                ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP
+            )
+        })
+        .ok_or_else(|| failure::err_msg("Avatars must be JPEG, PNG or WebP images"))?;
+    image::load_from_memory_with_format(data, format)?;
+
+    let name = format!(
+        "{}/{}-{}.{}",
+        AVATAR_DIR,
+        user,
+        &format!("{:x}", Sha256::digest(data))[..16],
+        format.extensions_str()[0]
+    );
+    let path = Path::new(&settings.dir).join(&name);
+    fs::create_dir_all(Path::new(&settings.dir).join(AVATAR_DIR))?;
+    fs::write(&path, data)?;
+    process(settings, &path)?;
+    Ok(name)
+}
+
+/// Deletes a file from the media directory along with its derivatives, given its path relative
+/// to the media directory.
+pub fn remove(settings: &Media, name: &str) -> Result<(), failure::Error> {
+    let path = match resolve(settings, &format!("{}{}", URL_PREFIX, name)) {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let stem = path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    if let Some(dir) = path.parent() {
+        for entry in fs::read_dir(dir)? {
+            let derivative = entry?.path();
+            let derived_from = derivative
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.rsplit_once('-'))
+                .map(|(original, _)| original == stem);
+            if derived_from == Some(true) && is_derivative(&derivative) {
+                fs::remove_file(derivative)?;
+            }
+        }
+    }
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+/// Resolves the url of a managed media file to its path on disk. Returns `None` if the url
+/// doesn't point into the media directory.
+pub(crate) fn resolve(settings: &Media, url: &str) -> Option<PathBuf> {
+    let relative = Path::new(url.strip_prefix(URL_PREFIX)?);
+    if !relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+    {
+        return None;
+    }
+    let path = Path::new(&settings.dir).join(relative);
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Gets the value of an attribute in an html tag.
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!(" {}=\"", name))? + name.len() + 3;
+    let len = tag[start..].find('"')?;
+    Some(&tag[start..start + len])
+}
+
+/// Builds the `srcset` attribute value for an image with the derivatives of the given extension.
+fn srcset(settings: &Media, url: &str, path: &Path, width: u32, extension: &str) -> String {
+    let mut widths: Vec<u32> = settings
+        .widths
+        .iter()
+        .copied()
+        .filter(|&w| w < width)
+        .collect();
+    widths.sort_unstable();
+    widths.dedup();
+    let mut entries: Vec<String> = widths
+        .into_iter()
+        .filter(|&w| derivative_path(path, w, extension).is_file())
+        .map(|w| {
+            let name = derivative_path(Path::new(url), w, extension);
+            format!("{} {}w", name.display(), w)
+        })
+        .collect();
+    // The original is only a candidate if it's in the requested format.
+    if raster_format(path).map(|f| f.extensions_str().contains(&extension)) == Some(true) {
+        entries.push(format!("{} {}w", url, width));
+    }
+    entries.join(", ")
+}
+
+/// Rewrites an `<img>` tag pointing at managed media to a responsive image.
+fn responsive_image(settings: &Media, tag: &str) -> Option<String> {
+    let url = attribute(tag, "src")?;
+    let path = resolve(settings, url)?;
+    let format = raster_format(&path)?;
+    let (width, height) = image::image_dimensions(&path).ok()?;
+    let sizes = format!("(max-width: {0}px) 100vw, {0}px", width);
+
+    let mut extra = String::new();
+    if attribute(tag, "srcset").is_none() {
+        let set = srcset(settings, url, &path, width, format.extensions_str()[0]);
+        extra.push_str(&format!(" srcset=\"{}\" sizes=\"{}\"", set, sizes));
+    }
+    if attribute(tag, "width").is_none() && attribute(tag, "height").is_none() {
+        extra.push_str(&format!(" width=\"{}\" height=\"{}\"", width, height));
+    }
+    if attribute(tag, "loading").is_none() {
+        extra.push_str(" loading=\"lazy\"");
+    }
+
+    // Insert the attributes before the end of the tag, which may or may not be self-closing.
+    let body = tag.trim_end_matches('>');
+    let (body, end) = match body.strip_suffix('/') {
+        Some(body) => (body.trim_end(), " />"),
+        None => (body, ">"),
+    };
+    let img = format!("{}{}{}", body, extra, end);
+
+    let webp = srcset(settings, url, &path, width, "webp");
+    if settings.webp && !webp.is_empty() {
+        Some(format!(
+            "<picture><source type=\"image/webp\" srcset=\"{}\" sizes=\"{}\">{}</picture>",
+            webp, sizes, img
+        ))
+    } else {
+        Some(img)
+    }
+}
+
+/// Adds `srcset`, `sizes`, intrinsic dimensions and lazy loading to the `<img>` tags in rendered
+/// html that point at managed media.
+pub fn responsive_images(settings: &Media, html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find("<img ") {
+        let end = match rest[start..].find('>') {
+            Some(end) => start + end + 1,
+            None => break,
+        };
+        out.push_str(&rest[..start]);
+        let tag = &rest[start..end];
+        match responsive_image(settings, tag) {
+            Some(image) => out.push_str(&image),
+            None => out.push_str(tag),
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{metadata::Orientation, DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+
+    use std::{fs, path::Path};
+
+    use super::{
+        is_derivative, orientation_exif, process, remove, replace_jpeg_exif, replace_png_exif,
+        responsive_images, save_avatar,
+    };
+    use crate::config::Media;
+
+    #[test]
+    fn derivative_names() {
+        assert!(is_derivative(Path::new("photo-480w.jpg")));
+        assert!(is_derivative(Path::new("dir/my-photo-1440w.webp")));
+        assert!(!is_derivative(Path::new("photo.jpg")));
+        assert!(!is_derivative(Path::new("photo-w.jpg")));
+        assert!(!is_derivative(Path::new("photo-wide.jpg")));
+    }
+
+    #[test]
+    fn metadata() {
+        let dir = std::env::temp_dir().join(format!("mogger-metadata-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let settings = Media {
+            dir: dir.to_string_lossy().into_owned(),
+            widths: vec![100],
+            webp: false,
+            quality: 80,
+            scan_interval: 0,
+        };
+        // Rotated a quarter turn, with more metadata after the orientation
+        let mut exif = orientation_exif(Orientation::Rotate90);
+        exif.extend_from_slice(b"GPS position");
+        let image = DynamicImage::new_rgb8(200, 150);
+        for format in &[ImageFormat::Jpeg, ImageFormat::Png] {
+            let mut encoded = std::io::Cursor::new(Vec::new());
+            image.write_to(&mut encoded, *format).unwrap();
+            let encoded = encoded.into_inner();
+            let (with_exif, extension) = match format {
+                ImageFormat::Jpeg => {
+                    let mut app1 = vec![0xff, 0xe1, 0, 0];
+                    app1.extend_from_slice(b"Exif\0\0");
+                    app1.extend_from_slice(&exif);
+                    app1[3] = (app1.len() - 2) as u8;
+                    ([&encoded[..2], &app1, &encoded[2..]].concat(), "jpg")
+                }
+                _ => {
+                    // After the header chunk
+                    let mut chunk = (exif.len() as u32).to_be_bytes().to_vec();
+                    chunk.extend_from_slice(b"eXIf");
+                    chunk.extend_from_slice(&exif);
+                    let mut crc = flate2::Crc::new();
+                    crc.update(&chunk[4..]);
+                    chunk.extend_from_slice(&crc.sum().to_be_bytes());
+                    ([&encoded[..33], &chunk, &encoded[33..]].concat(), "png")
+                }
+            };
+            let path = dir.join(format!("photo.{}", extension));
+            fs::write(&path, &with_exif).unwrap();
+            process(&settings, &path).unwrap();
+
+            // The original is stripped of everything but the orientation, without being
+            // re-encoded
+            let stripped = fs::read(&path).unwrap();
+            let mut decoder = ImageReader::new(std::io::Cursor::new(&stripped))
+                .with_guessed_format()
+                .unwrap()
+                .into_decoder()
+                .unwrap();
+            let kept = orientation_exif(Orientation::Rotate90);
+            assert_eq!(decoder.exif_metadata().unwrap(), Some(kept.clone()));
+            let without = match format {
+                ImageFormat::Jpeg => replace_jpeg_exif(&stripped, None),
+                _ => replace_png_exif(&stripped, None),
+            };
+            assert_eq!(without.unwrap(), encoded);
+
+            // Derivatives are rotated, and processing again leaves the original alone
+            let derivative = dir.join(format!("photo-100w.{}", extension));
+            assert_eq!(image::image_dimensions(&derivative).unwrap(), (100, 133));
+            process(&settings, &path).unwrap();
+            assert_eq!(fs::read(&path).unwrap(), stripped);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn responsive() {
+        let dir = std::env::temp_dir().join(format!("mogger-media-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let settings = Media {
+            dir: dir.to_string_lossy().into_owned(),
+            widths: vec![100, 400],
+            webp: true,
+            quality: 80,
+            scan_interval: 0,
+        };
+        DynamicImage::new_rgb8(200, 100)
+            .save_with_format(dir.join("test.png"), ImageFormat::Png)
+            .unwrap();
+        process(&settings, &dir.join("test.png")).unwrap();
+        assert!(dir.join("test-100w.png").is_file());
+        assert!(dir.join("test-100w.webp").is_file());
+        assert!(!dir.join("test-400w.png").is_file());
+
+        let html = responsive_images(
+            &settings,
+            "<p><img src=\"/media/test.png\" alt=\"Test\" /><img src=\"/other.png\"></p>",
+        );
+        assert_eq!(
+            html,
+            "<p><picture><source type=\"image/webp\" srcset=\"/media/test-100w.webp 100w\" \
+             sizes=\"(max-width: 200px) 100vw, 200px\"><img src=\"/media/test.png\" alt=\"Test\" \
+             srcset=\"/media/test-100w.png 100w, /media/test.png 200w\" \
+             sizes=\"(max-width: 200px) 100vw, 200px\" width=\"200\" height=\"100\" \
+             loading=\"lazy\" /></picture><img src=\"/other.png\"></p>"
+        );
+
+        // Urls escaping the media directory are left alone
+        let html = "<img src=\"/media/../test.png\">";
+        assert_eq!(responsive_images(&settings, html), html);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn avatars() {
+        let dir = std::env::temp_dir().join(format!("mogger-avatars-{}", std::process::id()));
+        let settings = Media {
+            dir: dir.to_string_lossy().into_owned(),
+            widths: vec![100],
+            webp: false,
+            quality: 80,
+            scan_interval: 0,
+        };
+        let mut png = std::io::Cursor::new(Vec::new());
+        DynamicImage::new_rgb8(200, 200)
+            .write_to(&mut png, ImageFormat::Png)
+            .unwrap();
+        let name = save_avatar(&settings, "bea", png.get_ref()).unwrap();
+        assert!(name.starts_with("avatars/bea-") && name.ends_with(".png"));
+        let path = dir.join(&name);
+        assert!(path.is_file());
+        let derivative = path.with_file_name(format!(
+            "{}-100w.png",
+            path.file_stem().unwrap().to_string_lossy()
+        ));
+        assert!(derivative.is_file());
+
+        assert!(save_avatar(&settings, "bea", b"<svg></svg>").is_err());
+
+        // Another user's copy of the same image outlives the removal of the first one
+        let other = save_avatar(&settings, "cleo", png.get_ref()).unwrap();
+        assert_ne!(other, name);
+        remove(&settings, &name).unwrap();
+        assert!(!path.exists());
+        assert!(!derivative.exists());
+        assert!(dir.join(&other).is_file());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,126 @@
+//! Prometheus metrics, exposed by the handler in `handler::metrics`.
+
+use futures::prelude::*;
+use gotham::{
+    handler::HandlerFuture,
+    hyper::{Method, Uri},
+    middleware::{Middleware, NewMiddleware},
+    state::{FromState, State},
+};
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge, Histogram, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+};
+
+use std::{pin::Pin, time::Instant};
+
+use crate::routes;
+
+lazy_static! {
+    pub static ref REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "mogger_http_requests_total",
+        "Number of HTTP requests handled",
+        &["route", "method", "status"]
+    )
+    .unwrap();
+    pub static ref REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "mogger_http_request_duration_seconds",
+        "Time spent handling HTTP requests",
+        &["route"]
+    )
+    .unwrap();
+    pub static ref DB_LOCK_WAIT: Histogram = register_histogram!(
+        "mogger_db_lock_wait_seconds",
+        "Time spent waiting for the database connection lock"
+    )
+    .unwrap();
+    pub static ref DB_LOCK_HOLD: Histogram = register_histogram!(
+        "mogger_db_lock_hold_seconds",
+        "Time the database connection was held, covering the queries made while holding it"
+    )
+    .unwrap();
+    pub static ref LOGIN_FAILURES: IntCounter = register_int_counter!(
+        "mogger_login_failures_total",
+        "Number of login attempts with invalid credentials"
+    )
+    .unwrap();
+    pub static ref COMMENTS_SUBMITTED: IntCounterVec = register_int_counter_vec!(
+        "mogger_comments_submitted_total",
+        "Number of comments submitted",
+        &["kind"]
+    )
+    .unwrap();
+    pub static ref RENDER_CACHE: IntCounterVec = register_int_counter_vec!(
+        "mogger_render_cache_requests_total",
+        "Lookups of rendered articles by where they were found",
+        &["result"]
+    )
+    .unwrap();
+    pub static ref ACTIVE_SESSIONS: IntGauge =
+        register_int_gauge!("mogger_active_sessions", "Number of unexpired sessions").unwrap();
+    pub static ref PENDING_COMMENTS: IntGauge = register_int_gauge!(
+        "mogger_pending_comments",
+        "Number of guest comments awaiting moderation"
+    )
+    .unwrap();
+}
+
+/// Maps a request path to the route it's handled by, so paths containing ids don't each get
+/// their own time series. Paths no route handles are all counted as "other".
+pub fn route(path: &str) -> &'static str {
+    routes::find(path).unwrap_or("other")
+}
+
+/// Middleware that counts requests and measures their latency.
+#[derive(Clone, NewMiddleware)]
+pub struct MetricsMiddleware;
+
+impl Middleware for MetricsMiddleware {
+    fn call<C>(self, state: State, chain: C) -> Pin<Box<HandlerFuture>>
+    where
+        C: FnOnce(State) -> Pin<Box<HandlerFuture>>,
+    {
+        let start = Instant::now();
+        let method = Method::borrow_from(&state).clone();
+        let route = route(Uri::borrow_from(&state).path());
+
+        chain(state)
+            .then(move |result| {
+                let status = match result {
+                    Ok((_, ref response)) => response.status(),
+                    Err((_, ref error)) => error.status(),
+                };
+                REQUESTS
+                    .with_label_values(&[route, method.as_str(), status.as_str()])
+                    .inc();
+                REQUEST_DURATION
+                    .with_label_values(&[route])
+                    .observe(start.elapsed().as_secs_f64());
+                future::ready(result)
+            })
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::route;
+
+    #[test]
+    fn routes() {
+        assert_eq!(route("/"), "/");
+        assert_eq!(route("/article/hello-world"), "/article/:id");
+        assert_eq!(route("/user/amanda/edit"), "/user/:user/edit");
+        assert_eq!(route("/user/amanda/other"), "other");
+        assert_eq!(route("/api/comments/view/12"), "/api/comments/view/:id");
+        assert_eq!(route("/api/comments/random-1234"), "other");
+        assert_eq!(route("/api/random-1234/view/12"), "other");
+        assert_eq!(route("/file/fonts/fonts.css"), "/file/*");
+        assert_eq!(route("/feed.rss"), "/feed.rss");
+        assert_eq!(route("/about"), "/:slug");
+        assert_eq!(route("/tag/rust"), "/tag/:tag");
+        assert_eq!(route("/csp-report"), "/csp-report");
+        assert_eq!(route("/wp-admin/install.php"), "other");
+    }
+}
@@ -0,0 +1,91 @@
+//! Parsing of `multipart/form-data` request bodies, which forms uploading files send.
+
+use gotham::mime::{self, Mime};
+
+/// A field of a submitted form
+pub struct Part<'a> {
+    pub name: String,
+    /// The name of the uploaded file, if the field is a file
+    pub filename: Option<String>,
+    pub data: &'a [u8],
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Gets a parameter of a `Content-Disposition` header, e.g. `name` of `form-data; name="field"`.
+fn param(disposition: &str, name: &str) -> Option<String> {
+    disposition.split(';').skip(1).find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        Some(value.trim().trim_matches('"').to_owned()).filter(|_| key.trim() == name)
+    })
+}
+
+/// Splits a request body into the fields of the form, given the request's content type.
+pub fn parse<'a>(content_type: &str, body: &'a [u8]) -> Result<Vec<Part<'a>>, failure::Error> {
+    let content_type: Mime = content_type.parse()?;
+    if content_type.essence_str() != "multipart/form-data" {
+        return Err(failure::err_msg("Expected a form with files"));
+    }
+    let boundary = content_type
+        .get_param(mime::BOUNDARY)
+        .ok_or_else(|| failure::err_msg("Form has no boundary"))?;
+    let delimiter = format!("--{}", boundary);
+    let delimiter = delimiter.as_bytes();
+
+    let mut parts = Vec::new();
+    let mut rest = match find(body, delimiter) {
+        Some(start) => &body[start + delimiter.len()..],
+        None => return Ok(parts),
+    };
+    // The last delimiter is followed by `--`
+    while !rest.starts_with(b"--") {
+        let end = find(rest, delimiter).ok_or_else(|| failure::err_msg("Unterminated form"))?;
+        let part = &rest[..end];
+        rest = &rest[end + delimiter.len()..];
+
+        let part = part.strip_prefix(b"\r\n").unwrap_or(part);
+        let part = part.strip_suffix(b"\r\n").unwrap_or(part);
+        let headers_end =
+            find(part, b"\r\n\r\n").ok_or_else(|| failure::err_msg("Malformed form field"))?;
+        let headers = std::str::from_utf8(&part[..headers_end])?;
+        let disposition = headers
+            .lines()
+            .find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                Some(value).filter(|_| name.trim().eq_ignore_ascii_case("content-disposition"))
+            })
+            .ok_or_else(|| failure::err_msg("Form field has no name"))?;
+        parts.push(Part {
+            name: param(disposition, "name").unwrap_or_default(),
+            filename: param(disposition, "filename"),
+            data: &part[headers_end + 4..],
+        });
+    }
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    #[test]
+    fn fields() {
+        let body = b"--xyz\r\nContent-Disposition: form-data; name=\"text\"\r\n\r\na=b\r\n\
+                     --xyz\r\nContent-Disposition: form-data; name=\"files\"; \
+                     filename=\"blog/post.md\"\r\nContent-Type: text/markdown\r\n\r\n\
+                     # Title\r\n\r\n--xyz--\r\n";
+        let parts = parse("multipart/form-data; boundary=xyz", body).unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name, "text");
+        assert_eq!(parts[0].filename, None);
+        assert_eq!(parts[0].data, b"a=b");
+        assert_eq!(parts[1].name, "files");
+        assert_eq!(parts[1].filename.as_deref(), Some("blog/post.md"));
+        assert_eq!(parts[1].data, b"# Title\r\n");
+        assert!(parse("text/plain", body).is_err());
+    }
+}
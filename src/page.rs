@@ -0,0 +1,195 @@
+//! Static pages like About, edited from the site and served at `/<slug>`.
+
+use chrono::naive::NaiveDateTime;
+use diesel::{
+    pg::PgConnection as Connection, prelude::*, result::Error as DieselError, Queryable,
+    RunQueryDsl,
+};
+use serde::{de::Error as _, Deserialize as _, Deserializer};
+
+use crate::{render::Renderer, routes, schema::pages};
+
+#[derive(Debug, Deserialize, Serialize, Queryable, Identifiable)]
+pub struct Page {
+    pub id: i32,
+    /// The path the page is served at, without the leading slash
+    pub slug: String,
+    pub title: String,
+    /// The page's markdown
+    pub content: String,
+    /// The page's position in the navigation bar, if it's listed there
+    pub nav_order: Option<i32>,
+    /// Whether the page has been published
+    pub visible: bool,
+    /// The time of the last change
+    #[serde(with = "crate::date_format")]
+    pub updated_at: NaiveDateTime,
+}
+
+impl Page {
+    /// Return the marked up version of the page's content.
+    pub fn formatted(&self, renderer: &Renderer) -> String {
+        renderer.page(&self.content)
+    }
+}
+
+/// A new page, or the changes to one
+#[derive(Insertable, AsChangeset, Deserialize)]
+#[table_name = "pages"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct PageChanges {
+    pub slug: String,
+    pub title: String,
+    pub content: String,
+    #[serde(default, deserialize_with = "optional_number")]
+    pub nav_order: Option<i32>,
+    #[serde(default)]
+    pub visible: bool,
+}
+
+/// Deserializes a number that forms leave empty to mean none.
+pub(crate) fn optional_number<'d, D: Deserializer<'d>>(
+    deserializer: D,
+) -> Result<Option<i32>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    match text.trim() {
+        "" => Ok(None),
+        text => text.parse().map(Some).map_err(D::Error::custom),
+    }
+}
+
+/// Checks that a page has a title and a slug that doesn't clash with other routes.
+fn check(page: &PageChanges) -> Result<(), failure::Error> {
+    if page.title.trim().is_empty() {
+        return Err(failure::err_msg("Page has no title"));
+    }
+    if page.slug.is_empty() {
+        return Err(failure::err_msg("Page has no slug"));
+    }
+    if !page
+        .slug
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+    {
+        return Err(failure::err_msg(
+            "Page slugs may only contain lowercase letters, digits, '-' and '_'",
+        ));
+    }
+    if routes::reserved(&page.slug) {
+        return Err(failure::format_err!(
+            "The slug '{}' is used by the site",
+            page.slug
+        ));
+    }
+    Ok(())
+}
+
+/// Gets all pages in navigation order, followed by those not in the navigation bar
+pub fn list(connection: &Connection) -> Result<Vec<Page>, DieselError> {
+    use crate::schema::pages::dsl::*;
+
+    pages
+        .order((nav_order.asc().nulls_last(), title.asc()))
+        .load::<Page>(connection)
+}
+
+/// Gets the slugs and titles of the published pages listed in the navigation bar
+pub fn nav(connection: &Connection) -> Result<Vec<(String, String)>, DieselError> {
+    use crate::schema::pages::dsl::*;
+
+    pages
+        .filter(visible.eq(true))
+        .filter(nav_order.is_not_null())
+        .order((nav_order.asc(), title.asc()))
+        .select((slug, title))
+        .load(connection)
+}
+
+/// Gets the page with a slug
+pub fn view(connection: &Connection, name: &str) -> Result<Page, DieselError> {
+    use crate::schema::pages::dsl::*;
+
+    pages.filter(slug.eq(name)).first(connection)
+}
+
+/// Gets the page with an id
+pub fn get(connection: &Connection, page: i32) -> Result<Page, DieselError> {
+    pages::table.find(page).first(connection)
+}
+
+/// Creates a page
+pub fn create(connection: &Connection, page: &PageChanges) -> Result<usize, failure::Error> {
+    check(page)?;
+    Ok(diesel::insert_into(pages::table)
+        .values(page)
+        .execute(connection)?)
+}
+
+/// Edits a page
+pub fn edit(connection: &Connection, id: i32, page: &PageChanges) -> Result<usize, failure::Error> {
+    check(page)?;
+    Ok(diesel::update(pages::table.find(id))
+        .set(page)
+        .execute(connection)?)
+}
+
+/// Adds a page from a backup, keeping its id and when it was last changed
+pub fn restore(connection: &Connection, page: &Page) -> Result<usize, DieselError> {
+    diesel::insert_into(pages::table)
+        .values((
+            pages::id.eq(page.id),
+            pages::slug.eq(&page.slug),
+            pages::title.eq(&page.title),
+            pages::content.eq(&page.content),
+            pages::nav_order.eq(page.nav_order),
+            pages::visible.eq(page.visible),
+            pages::updated_at.eq(page.updated_at),
+        ))
+        .execute(connection)
+}
+
+/// Deletes every page
+pub fn clear(connection: &Connection) -> Result<usize, DieselError> {
+    diesel::delete(pages::table).execute(connection)
+}
+
+/// Deletes a page
+pub fn delete(connection: &Connection, id: i32) -> Result<usize, DieselError> {
+    diesel::delete(pages::table.find(id)).execute(connection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, PageChanges};
+
+    #[test]
+    fn slugs() {
+        let page = |slug: &str| PageChanges {
+            slug: slug.to_owned(),
+            title: String::from("Title"),
+            content: String::new(),
+            nav_order: None,
+            visible: true,
+        };
+        assert!(check(&page("code-of-conduct")).is_ok());
+        assert!(check(&page("")).is_err());
+        assert!(check(&page("About")).is_err());
+        assert!(check(&page("a/b")).is_err());
+        assert!(check(&page("login")).is_err());
+        assert!(check(&page("csp-report")).is_err());
+    }
+
+    #[test]
+    fn form() {
+        let page: PageChanges =
+            serde_urlencoded::from_str("slug=about&title=About&content=Hi&nav_order=").unwrap();
+        assert_eq!(page.nav_order, None);
+        assert!(!page.visible);
+        let page: PageChanges = serde_urlencoded::from_str(
+            "slug=about&title=About&content=Hi&nav_order=2&visible=true",
+        )
+        .unwrap();
+        assert_eq!(page.nav_order, Some(2));
+        assert!(page.visible);
+    }
+}
@@ -0,0 +1,231 @@
+//! Rendering of article and comment markdown, with a cache of the rendered articles.
+//!
+//! The rendered html is sanitized with the policy of the authors' groups. Rendered articles are
+//! kept in an in-memory LRU cache, backed by the `rendered_articles` table. Both are invalidated
+//! when an article is edited. Articles with diagrams that are still being rendered in the
+//! background aren't cached until they're done.
+
+use comrak::{
+    adapters::SyntaxHighlighterAdapter, markdown_to_html, markdown_to_html_with_plugins,
+    ComrakOptions, ComrakPlugins, ComrakRenderPlugins,
+};
+use gotham::state::StateData;
+use lru::LruCache;
+
+use std::{
+    num::NonZeroUsize,
+    panic::AssertUnwindSafe,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    article::{self, Article},
+    config::{Diagrams, Media, Policy, Sanitize, Settings},
+    db::Connection,
+    diagram::{self, Background, Diagram},
+    embed,
+    highlight::Highlighter,
+    media, metrics, page, sanitize, series, toc,
+};
+
+/// Number of rendered articles to keep in memory.
+const CACHE_SIZE: usize = 64;
+
+/// Renders articles and comments to html. The syntax highlighter is expensive to build, so a
+/// single one is shared through gotham's state data.
+#[derive(Clone, StateData)]
+pub struct Renderer {
+    // The highlighter's interior mutability is limited to lazily compiled regexes, which a panic
+    // can't leave in an inconsistent state.
+    highlighter: Option<Arc<AssertUnwindSafe<Highlighter>>>,
+    articles: Arc<ComrakOptions>,
+    comments: Arc<ComrakOptions>,
+    cache: Arc<Mutex<LruCache<i32, String>>>,
+    media: Media,
+    sanitize: Sanitize,
+    math: bool,
+    diagrams: Diagrams,
+    /// Renders diagrams without making requests wait for them. Diagrams are rendered right away
+    /// without it.
+    background: Option<Background>,
+}
+
+impl Renderer {
+    pub fn new(settings: &Settings) -> Result<Self, failure::Error> {
+        let markdown = &settings.markdown;
+        let highlighter = match markdown.highlight.enabled {
+            true => Some(Arc::new(AssertUnwindSafe(Highlighter::new(
+                &markdown.highlight,
+            )?))),
+            false => None,
+        };
+        Ok(Renderer {
+            highlighter,
+            // Raw HTML in articles is sanitized after rendering
+            articles: Arc::new(markdown.articles.options(true)),
+            comments: Arc::new(markdown.comments.options(false)),
+            cache: Arc::new(Mutex::new(LruCache::new(
+                NonZeroUsize::new(CACHE_SIZE).unwrap(),
+            ))),
+            media: settings.media.clone(),
+            sanitize: settings.sanitize.clone(),
+            math: markdown.math,
+            diagrams: markdown.diagrams.clone(),
+            background: None,
+        })
+    }
+
+    /// Renders diagrams on a background thread, showing their source until they're done.
+    pub fn with_background_diagrams(mut self) -> Self {
+        self.background = Some(Background::new(&self.diagrams));
+        self
+    }
+
+    fn diagram(&self, language: &str, source: &str) -> Option<Diagram> {
+        match &self.background {
+            Some(background) => background.render(language, source),
+            None => diagram::render(&self.diagrams, language, source),
+        }
+    }
+
+    /// Gets the options articles are rendered with. Headers always get ids in articles with a
+    /// table of contents, for it to link to.
+    fn article_options(&self, toc: bool) -> ComrakOptions {
+        let mut options = ComrakOptions::clone(&self.articles);
+        if toc {
+            options.extension.header_ids = Some(String::new());
+        }
+        options
+    }
+
+    /// Renders article markdown without consulting the cache, sanitizing it with `policy`. Math
+    /// and diagrams are added after sanitizing, as they're generated by trusted code. Also returns
+    /// whether all diagrams are rendered.
+    pub fn markdown(&self, content: &str, policy: &Policy, toc: bool) -> (String, bool) {
+        let plugins = ComrakPlugins {
+            render: ComrakRenderPlugins {
+                codefence_syntax_highlighter: self
+                    .highlighter
+                    .as_ref()
+                    .map(|h| &***h as &dyn SyntaxHighlighterAdapter),
+            },
+        };
+        let (content, embeds) = embed::extract(content, self.math, &self.diagrams);
+        let options = self.article_options(toc);
+        let html = markdown_to_html_with_plugins(&content, &options, &plugins);
+        let html = sanitize::clean(&html, policy);
+        let mut done = true;
+        let html = embed::restore(&html, &embeds, |language, source| {
+            let diagram = self.diagram(language, source);
+            done &= !matches!(diagram, Some(Diagram::Pending));
+            diagram
+        });
+        (media::responsive_images(&self.media, &html), done)
+    }
+
+    /// Renders comment markdown. Comments are always sanitized with the basic policy.
+    pub fn comment(&self, content: &str) -> String {
+        let html = markdown_to_html(content, &self.comments);
+        sanitize::clean(&html, &Policy::default())
+    }
+
+    /// Renders the markdown of a static page or series description, which is sanitized with the
+    /// default policy.
+    pub fn page(&self, content: &str) -> String {
+        let (html, _) = self.markdown(content, self.sanitize.for_group(None), false);
+        html
+    }
+
+    /// Starts rendering the diagrams in saved markdown, so they're ready when it's viewed.
+    pub fn prepare(&self, content: &str) {
+        if let Some(background) = &self.background {
+            for (language, source) in embed::diagrams(content, &self.diagrams) {
+                background.render(&language, &source);
+            }
+        }
+    }
+
+    /// Removes cached diagrams that no article, page or series uses anymore.
+    pub fn prune_diagrams(&self, connection: &Connection) -> Result<usize, failure::Error> {
+        let articles = article::list(connection)?.into_iter().map(|a| a.content);
+        let pages = page::list(connection)?.into_iter().map(|p| p.content);
+        let series = series::list(connection)?.into_iter().map(|s| s.description);
+        let diagrams: Vec<_> = articles
+            .chain(pages)
+            .chain(series)
+            .flat_map(|content| embed::diagrams(&content, &self.diagrams))
+            .collect();
+        let used = diagrams.iter().map(|(l, s)| (l.as_str(), s.as_str()));
+        Ok(diagram::prune(&self.diagrams, used)?)
+    }
+
+    /// Gets the rendered body of an article, rendering and caching it if it isn't cached yet.
+    pub fn article(&self, article: &Article, connection: &Connection) -> String {
+        // Renderings may be cleared from the database by other processes, like the CLI after
+        // moving a user to another group, so the in-memory copy is only used while it's there.
+        if let Some(html) = self
+            .cache
+            .lock()
+            .ok()
+            .and_then(|mut c| c.get(&article.id).cloned())
+            .filter(|_| article::is_rendered(connection, article.id).unwrap_or(false))
+        {
+            metrics::RENDER_CACHE.with_label_values(&["memory"]).inc();
+            return html;
+        }
+        let html = match article::rendered(connection, article.id).unwrap_or_default() {
+            Some(html) => {
+                metrics::RENDER_CACHE.with_label_values(&["database"]).inc();
+                html
+            }
+            None => {
+                metrics::RENDER_CACHE.with_label_values(&["miss"]).inc();
+                // Articles get the least trusted policy of their authors, so co-authors can't add
+                // HTML their own policy wouldn't allow. Authors that can't be found get the
+                // default policy.
+                let groups = match article.authors(connection) {
+                    Ok(authors) => authors.into_iter().map(|a| Some(a.group)).collect(),
+                    Err(_) => vec![None],
+                };
+                let policy = self
+                    .sanitize
+                    .for_groups(groups.iter().map(Option::as_deref));
+                let (html, done) = self.markdown(&article.content, &policy, article.toc);
+                if !done {
+                    return html;
+                }
+                // The cache is only an optimization, so failing to store it isn't an error.
+                article::set_rendered(connection, article.id, &html).unwrap_or_default();
+                html
+            }
+        };
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.put(article.id, html.clone());
+        }
+        html
+    }
+
+    /// Renders the table of contents of an article.
+    pub fn toc(&self, article: &Article) -> String {
+        toc::html(&toc::headings(
+            &article.content,
+            &self.article_options(true),
+        ))
+    }
+
+    /// Removes an article from the in-memory cache. Must be called after editing an article.
+    pub fn invalidate(&self, id: i32) {
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.pop(&id);
+        }
+    }
+
+    /// Removes every article from both caches, e.g. after images they may refer to were added.
+    pub fn clear(&self, connection: &Connection) -> Result<(), failure::Error> {
+        article::clear_rendered(connection)?;
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.clear();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+//! Paths of the routes the site's router handles. Metrics label requests by the route handling
+//! them, and static pages can't take a slug a route starts with. Routes added to the router in
+//! `main` must be added here as well.
+
+use crate::security;
+
+/// Every route of the site, in the order they're matched in. `:name` stands for one segment of a
+/// path, and `*` for the rest of it.
+pub const ROUTES: &[&str] = &[
+    "/",
+    "/page/:page",
+    "/initial-setup",
+    "/tag/:tag",
+    "/article/:id/markdown",
+    "/article/:id",
+    "/user/:user",
+    "/user/:user/page/:page",
+    "/user/:user/feed.rss",
+    "/user/:user/edit",
+    "/user/:user/profile",
+    "/user/:user/avatar",
+    "/user/:user/password",
+    "/user/:user/delete",
+    "/login",
+    "/logout",
+    "/signup",
+    "/edit",
+    "/edit/:id",
+    "/import",
+    "/backup",
+    "/api/articles/list",
+    "/api/articles/view/:id",
+    "/api/articles/submit",
+    "/api/articles/edit/:id",
+    "/api/comments/list/:id",
+    "/api/comments/view/:id",
+    "/api/comments/single/:id",
+    "/api/comments/render-content/:id",
+    "/api/comments/render/:id",
+    "/api/comments/submit",
+    "/api/comments/edit/:id",
+    "/api/comments/delete/:id",
+    "/api/comments/restore/:id",
+    "/api/comments/purge/:id",
+    "/api/users/create",
+    "/api/users/login",
+    "/series/:id",
+    "/series/:id/delete",
+    "/pages",
+    "/pages/edit",
+    "/pages/edit/:id",
+    "/pages/delete/:id",
+    "/file/*",
+    "/media/*",
+    "/feed.rss",
+    "/healthz",
+    "/readyz",
+    security::REPORT_PATH,
+    "/metrics",
+    // Static pages come last, as every other route takes precedence
+    "/:slug",
+];
+
+/// Splits a path into its segments, the root being a single empty one.
+fn segments(path: &str) -> Vec<&str> {
+    path.trim_matches('/').split('/').collect()
+}
+
+/// Checks if `route` handles the path with `path` as its segments.
+fn matches(route: &str, path: &[&str]) -> bool {
+    let route = segments(route);
+    if route.last() == Some(&"*") {
+        let prefix = &route[..route.len() - 1];
+        return path.len() > prefix.len() && path.iter().zip(prefix).all(|(p, r)| p == r);
+    }
+    route.len() == path.len()
+        && route
+            .iter()
+            .zip(path)
+            .all(|(r, p)| r == p || (r.starts_with(':') && !p.is_empty()))
+}
+
+/// Finds the route handling a path.
+pub fn find(path: &str) -> Option<&'static str> {
+    let path = segments(path);
+    ROUTES.iter().copied().find(|route| matches(route, &path))
+}
+
+/// Checks if a route starts with `segment`, so no static page can be served at it.
+pub fn reserved(segment: &str) -> bool {
+    ROUTES.iter().any(|route| {
+        let first = segments(route)[0];
+        first == segment && !first.starts_with(':')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find, reserved, ROUTES};
+
+    /// Values for the parameters of routes
+    const PARAMETERS: &[(&str, &str)] = &[
+        (":page", "2"),
+        (":id", "x"),
+        (":user", "bea"),
+        (":tag", "rust"),
+        (":slug", "about"),
+    ];
+
+    #[test]
+    fn routes() {
+        // Every route is found for paths it handles rather than being shadowed by another
+        for route in ROUTES {
+            let mut path = route.replace('*', "a/b");
+            for (name, value) in PARAMETERS {
+                path = path.replace(name, value);
+            }
+            assert_eq!(find(&path), Some(*route));
+        }
+        assert_eq!(find("/user/bea/other"), None);
+        assert_eq!(find("/api/comments/random-1234"), None);
+        assert_eq!(find("/about/more"), None);
+
+        assert!(reserved("csp-report"));
+        assert!(reserved("pages"));
+        assert!(!reserved("about"));
+        assert!(!reserved(":slug"));
+    }
+}
@@ -0,0 +1,240 @@
+//! Sanitization of rendered HTML, removing markup the author's policy doesn't allow.
+
+use ammonia::{Builder, Url};
+
+use std::{borrow::Cow, collections::BTreeMap};
+
+use crate::{config::Policy, highlight::CLASS_PREFIX};
+
+/// CSS properties used by the syntax highlighter, the only ones allowed in `style` attributes
+const STYLE_PROPERTIES: &[&str] = &[
+    "background-color",
+    "color",
+    "font-style",
+    "font-weight",
+    "text-decoration",
+];
+
+/// Attributes kept on embedded frames
+const IFRAME_ATTRIBUTES: &[&str] = &[
+    "allow",
+    "allowfullscreen",
+    "height",
+    "referrerpolicy",
+    "src",
+    "title",
+    "width",
+];
+
+/// Escapes text for use in HTML, including attribute values.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Checks if a frame source is served over HTTPS from one of `hosts`.
+fn frame_allowed(hosts: &[String], src: &str) -> bool {
+    match Url::parse(src) {
+        Ok(url) => url.scheme() == "https" && hosts.iter().any(|h| url.host_str() == Some(h)),
+        Err(_) => false,
+    }
+}
+
+/// Tags the syntax highlighter adds classes to
+const HIGHLIGHT_TAGS: &[&str] = &["pre", "span"];
+
+/// Keeps only the classes added by the syntax highlighter and those in `allowed`.
+fn highlight_classes<'a>(classes: &'a str, allowed: &[String]) -> Cow<'a, str> {
+    let kept: Vec<&str> = classes
+        .split_ascii_whitespace()
+        .filter(|class| class.starts_with(CLASS_PREFIX) || allowed.iter().any(|a| a == class))
+        .collect();
+    Cow::Owned(kept.join(" "))
+}
+
+/// Removes everything `policy` doesn't allow from `html`.
+pub fn clean(html: &str, policy: &Policy) -> String {
+    if policy.unrestricted {
+        return html.to_owned();
+    }
+
+    let mut builder = Builder::default();
+    // Markup generated for syntax highlighting, footnotes, header ids and task lists
+    builder
+        .add_tags(&["input", "section"])
+        .add_tag_attributes("pre", &["class", "lang", "style"])
+        .add_tag_attributes("span", &["class", "style"])
+        .add_tag_attributes("a", &["aria-hidden", "id"])
+        .add_tag_attributes("li", &["id"])
+        .add_tag_attributes("input", &["checked"])
+        .add_tag_attribute_values("input", "type", &["checkbox"])
+        .set_tag_attribute_value("input", "disabled", "")
+        .add_allowed_classes("a", &["anchor", "footnote-backref"])
+        .add_allowed_classes("section", &["footnotes"])
+        .add_allowed_classes("sup", &["footnote-ref"])
+        .filter_style_properties(STYLE_PROPERTIES.iter().copied().collect());
+
+    builder.add_tags(policy.tags.iter().map(String::as_str));
+    for (tag, attributes) in &policy.attributes {
+        builder.add_tag_attributes(tag.as_str(), attributes.iter().map(String::as_str));
+    }
+    // Tags the highlighter adds classes to allow the class attribute, which ammonia doesn't allow
+    // together with a list of classes, so their classes are filtered along with the highlighter's
+    let mut highlight = BTreeMap::new();
+    for (tag, classes) in &policy.classes {
+        if HIGHLIGHT_TAGS.contains(&tag.as_str()) {
+            highlight.insert(tag.clone(), classes.clone());
+        } else {
+            builder.add_allowed_classes(tag.as_str(), classes.iter().map(String::as_str));
+        }
+    }
+    if !policy.iframe_hosts.is_empty() {
+        builder
+            .add_tags(&["iframe"])
+            .add_tag_attributes("iframe", IFRAME_ATTRIBUTES);
+    }
+    let hosts = policy.iframe_hosts.clone();
+    builder.attribute_filter(
+        move |element, attribute, value| match (element, attribute) {
+            ("pre", "class") | ("span", "class") => Some(highlight_classes(
+                value,
+                highlight.get(element).map_or(&[], Vec::as_slice),
+            )),
+            ("iframe", "src") if !frame_allowed(&hosts, value) => None,
+            _ => Some(value.into()),
+        },
+    );
+    builder.clean(html).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::clean;
+    use crate::config::Policy;
+
+    #[test]
+    fn xss_vectors() {
+        let basic = Policy::default();
+        let vectors = [
+            "<script>alert(1)</script>",
+            "<img src=x onerror=alert(1)>",
+            "<a href=\"javascript:alert(1)\">x</a>",
+            "<a href=\" JaVaScRiPt:alert(1)\">x</a>",
+            "<svg onload=alert(1)>",
+            "<iframe src=\"https://evil.example\"></iframe>",
+            "<object data=\"x.swf\"></object>",
+            "<form action=\"https://evil.example\"><input name=password></form>",
+            "<input type=\"submit\" formaction=\"https://evil.example\">",
+            "<math><mtext><table><mglyph><style><img src=x onerror=alert(1)>",
+            "<noscript><p title=\"</noscript><img src=x onerror=alert(1)>\">",
+            "<div style=\"background:url(javascript:alert(1))\">x</div>",
+            "<a href=\"data:text/html;base64,PHNjcmlwdD5hbGVydCgxKTwvc2NyaXB0Pg==\">x</a>",
+            "<meta http-equiv=\"refresh\" content=\"0;url=javascript:alert(1)\">",
+            "<base href=\"https://evil.example/\">",
+        ];
+        for vector in &vectors {
+            let html = clean(vector, &basic).to_lowercase();
+            for bad in &[
+                "<script",
+                "onerror",
+                "onload",
+                "javascript:",
+                "<iframe",
+                "<object",
+                "<form",
+                // Inputs are allowed for task lists, but only as disabled checkboxes
+                "name=",
+                "type=",
+                "<style",
+                "data:",
+                "<meta",
+                "<base",
+            ] {
+                assert!(!html.contains(bad), "{} left {} in {}", vector, bad, html);
+            }
+        }
+    }
+
+    #[test]
+    fn generated_markup() {
+        let basic = Policy::default();
+        let highlighted = "<pre style=\"background-color:#eff1f5;\" lang=\"rust\"><code>\
+                           <span style=\"color:#b48ead;\">fn</span></code></pre>";
+        // Styles are normalized, dropping the trailing semicolons
+        assert_eq!(clean(highlighted, &basic), highlighted.replace(';', ""));
+        let footnote = "<sup class=\"footnote-ref\"><a href=\"#fn1\" id=\"fnref1\" \
+                        rel=\"noopener noreferrer\">1</a></sup>";
+        assert_eq!(clean(footnote, &basic), footnote);
+        // Only the highlighter's classes are kept
+        assert_eq!(
+            clean(
+                "<pre class=\"hl-code big\"><span class=\"x hl-keyword\">fn</span></pre>",
+                &basic
+            ),
+            "<pre class=\"hl-code\"><span class=\"hl-keyword\">fn</span></pre>"
+        );
+        // Policies can allow more classes on them
+        let mut classes = std::collections::BTreeMap::new();
+        classes.insert(String::from("span"), vec![String::from("big")]);
+        classes.insert(String::from("div"), vec![String::from("note")]);
+        let policy = Policy {
+            classes,
+            ..Policy::default()
+        };
+        assert_eq!(
+            clean(
+                "<div class=\"note x\"><span class=\"big x hl-keyword\">fn</span></div>",
+                &policy
+            ),
+            "<div class=\"note\"><span class=\"big hl-keyword\">fn</span></div>"
+        );
+        // Task list items can't be turned into other inputs
+        assert_eq!(
+            clean(
+                "<input type=\"checkbox\" checked=\"\" disabled=\"\" />",
+                &basic
+            ),
+            "<input type=\"checkbox\" checked=\"\" disabled=\"\">"
+        );
+        assert_eq!(
+            clean("<input type=\"text\" name=\"password\">", &basic),
+            "<input disabled=\"\">"
+        );
+        // Only the highlighter's properties are kept
+        assert_eq!(
+            clean(
+                "<span style=\"position: fixed; color: red\">x</span>",
+                &basic
+            ),
+            "<span style=\"color:red\">x</span>"
+        );
+    }
+
+    #[test]
+    fn iframes() {
+        let policy = Policy {
+            iframe_hosts: vec![String::from("www.youtube.com")],
+            ..Policy::default()
+        };
+        let allowed =
+            "<iframe src=\"https://www.youtube.com/embed/x\" allowfullscreen=\"\"></iframe>";
+        assert_eq!(clean(allowed, &policy), allowed);
+        assert_eq!(
+            clean(
+                "<iframe src=\"https://evil.example/\" onload=\"alert(1)\"></iframe>",
+                &policy
+            ),
+            "<iframe></iframe>"
+        );
+        assert_eq!(
+            clean(
+                "<iframe src=\"http://www.youtube.com/embed/x\"></iframe>",
+                &policy
+            ),
+            "<iframe></iframe>"
+        );
+        assert_eq!(clean(allowed, &Policy::default()), "");
+    }
+}
@@ -1,3 +1,11 @@
+table! {
+    article_authors (article, author) {
+        article -> Int4,
+        author -> Varchar,
+        position -> Int4,
+    }
+}
+
 table! {
     articles (id) {
         id -> Int4,
@@ -7,6 +15,12 @@ table! {
         content -> Text,
         date -> Timestamp,
         visible -> Bool,
+        updated_at -> Timestamp,
+        toc -> Bool,
+        tags -> Array<Text>,
+        summary -> Nullable<Text>,
+        series -> Nullable<Int4>,
+        series_part -> Nullable<Int4>,
     }
 }
 
@@ -30,6 +44,34 @@ table! {
     }
 }
 
+table! {
+    pages (id) {
+        id -> Int4,
+        slug -> Varchar,
+        title -> Varchar,
+        content -> Text,
+        nav_order -> Nullable<Int4>,
+        visible -> Bool,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    rendered_articles (article) {
+        article -> Int4,
+        html -> Text,
+    }
+}
+
+table! {
+    series (id) {
+        id -> Int4,
+        title -> Varchar,
+        url -> Varchar,
+        description -> Text,
+    }
+}
+
 table! {
     sessions (id) {
         id -> Varchar,
@@ -47,13 +89,31 @@ table! {
         email -> Varchar,
         group -> Varchar,
         rehash -> Bool,
+        bio -> Text,
+        website -> Varchar,
+        links -> Array<Text>,
+        avatar -> Nullable<Varchar>,
     }
 }
 
+joinable!(article_authors -> articles (article));
+joinable!(article_authors -> users (author));
+joinable!(articles -> series (series));
 joinable!(articles -> users (author));
 joinable!(comments -> articles (article));
 joinable!(comments -> users (author));
+joinable!(rendered_articles -> articles (article));
 joinable!(sessions -> users (user));
 joinable!(users -> groups (group));
 
-allow_tables_to_appear_in_same_query!(articles, comments, groups, sessions, users,);
+allow_tables_to_appear_in_same_query!(
+    article_authors,
+    articles,
+    comments,
+    groups,
+    pages,
+    rendered_articles,
+    series,
+    sessions,
+    users,
+);
@@ -0,0 +1,196 @@
+//! Security headers, including a `Content-Security-Policy` with a nonce for each request's inline
+//! scripts.
+
+use futures::prelude::*;
+use gotham::{
+    handler::HandlerFuture,
+    hyper::{
+        header::{
+            HeaderName, HeaderValue, CONTENT_SECURITY_POLICY, REFERRER_POLICY,
+            X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+        },
+        Uri,
+    },
+    middleware::{Middleware, NewMiddleware},
+    state::{FromState, State, StateData},
+};
+use rand::prelude::*;
+
+use std::{pin::Pin, sync::Arc};
+
+use crate::config::{self, NONCE};
+
+/// Path browsers report policy violations to
+pub const REPORT_PATH: &str = "/csp-report";
+
+/// Length of nonces in bytes
+const NONCE_LEN: usize = 16;
+
+/// Nonce that inline scripts on the page being served must carry to be run
+#[derive(Clone, StateData)]
+pub struct Nonce(pub String);
+
+impl Nonce {
+    fn generate() -> Self {
+        let mut nonce = [0u8; NONCE_LEN];
+        StdRng::from_entropy().fill(&mut nonce[..]);
+        Nonce(base64::encode(nonce))
+    }
+}
+
+type Headers = Vec<(HeaderName, String)>;
+
+/// Sets `name` to `value` in `headers`, replacing any previous value.
+fn set(headers: &mut Headers, name: HeaderName, value: String) {
+    match headers.iter_mut().find(|(n, _)| *n == name) {
+        Some(header) => header.1 = value,
+        None => headers.push((name, value)),
+    }
+}
+
+/// Middleware that adds the configured security headers to responses, unless the handler already
+/// set them.
+#[derive(Clone, NewMiddleware)]
+pub struct SecurityHeadersMiddleware {
+    /// Headers for all paths
+    headers: Arc<Headers>,
+    /// Headers for paths with a prefix, shortest prefix first
+    routes: Arc<Vec<(String, Headers)>>,
+}
+
+impl SecurityHeadersMiddleware {
+    pub fn new(settings: &config::Headers) -> Result<Self, failure::Error> {
+        // Policies get the report endpoint added if reporting is enabled
+        let value = |name: &HeaderName, value: &str| {
+            let mut value = value.to_owned();
+            if name == CONTENT_SECURITY_POLICY
+                && settings.csp_report
+                && !value.is_empty()
+                && !value.contains("report-uri")
+            {
+                value = format!(
+                    "{}; report-uri {}",
+                    value.trim_end_matches(';'),
+                    REPORT_PATH
+                );
+            }
+            value
+        };
+
+        let mut headers = Vec::new();
+        for (name, setting) in [
+            (CONTENT_SECURITY_POLICY, &settings.content_security_policy),
+            (X_CONTENT_TYPE_OPTIONS, &settings.content_type_options),
+            (REFERRER_POLICY, &settings.referrer_policy),
+            (X_FRAME_OPTIONS, &settings.frame_options),
+        ] {
+            let value = value(&name, setting);
+            headers.push((name, value));
+        }
+
+        let mut routes = Vec::new();
+        for (prefix, route) in &settings.routes {
+            let mut headers = Vec::new();
+            for (name, setting) in route {
+                let name = HeaderName::from_bytes(name.as_bytes())?;
+                let value = value(&name, setting);
+                headers.push((name, value));
+            }
+            routes.push((prefix.clone(), headers));
+        }
+        routes.sort_by_key(|(prefix, _)| prefix.len());
+
+        Ok(SecurityHeadersMiddleware {
+            headers: Arc::new(headers),
+            routes: Arc::new(routes),
+        })
+    }
+
+    /// Gets the headers for a path, with the overrides of matching routes applied.
+    fn headers(&self, path: &str) -> Headers {
+        let mut headers = Headers::clone(&self.headers);
+        for (_, route) in self
+            .routes
+            .iter()
+            .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+        {
+            for (name, value) in route {
+                set(&mut headers, name.clone(), value.clone());
+            }
+        }
+        headers
+    }
+}
+
+impl Middleware for SecurityHeadersMiddleware {
+    fn call<C>(self, mut state: State, chain: C) -> Pin<Box<HandlerFuture>>
+    where
+        C: FnOnce(State) -> Pin<Box<HandlerFuture>>,
+    {
+        let headers = self.headers(Uri::borrow_from(&state).path());
+        let nonce = Nonce::generate();
+        let source = format!("'nonce-{}'", nonce.0);
+        state.put(nonce);
+
+        chain(state)
+            .and_then(move |(state, mut response)| {
+                for (name, value) in headers {
+                    if value.is_empty() || response.headers().contains_key(&name) {
+                        continue;
+                    }
+                    if let Ok(value) = HeaderValue::from_str(&value.replace(NONCE, &source)) {
+                        response.headers_mut().insert(name, value);
+                    }
+                }
+                future::ok((state, response))
+            })
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gotham::hyper::header::{CONTENT_SECURITY_POLICY, X_FRAME_OPTIONS};
+
+    use std::collections::BTreeMap;
+
+    use super::SecurityHeadersMiddleware;
+    use crate::config::Headers;
+
+    #[test]
+    fn route_overrides() {
+        let mut settings = Headers {
+            content_security_policy: String::from("default-src 'self';"),
+            ..Headers::default()
+        };
+        let route = |name: &str, value: &str| {
+            let mut route = BTreeMap::new();
+            route.insert(name.to_owned(), value.to_owned());
+            route
+        };
+        settings
+            .routes
+            .insert(String::from("/media/"), route("X-Frame-Options", ""));
+        settings.routes.insert(
+            String::from("/media/embed/"),
+            route("X-Frame-Options", "SAMEORIGIN"),
+        );
+        let middleware = SecurityHeadersMiddleware::new(&settings).unwrap();
+        let get = |path, name| {
+            middleware
+                .headers(path)
+                .into_iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, value)| value)
+                .unwrap()
+        };
+
+        assert_eq!(
+            get("/", CONTENT_SECURITY_POLICY),
+            "default-src 'self'; report-uri /csp-report"
+        );
+        assert_eq!(get("/", X_FRAME_OPTIONS), "DENY");
+        assert_eq!(get("/media/a.png", X_FRAME_OPTIONS), "");
+        assert_eq!(get("/media/embed/a", X_FRAME_OPTIONS), "SAMEORIGIN");
+    }
+}
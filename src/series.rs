@@ -0,0 +1,346 @@
+//! Series of articles, like multi-part tutorials, read in order.
+
+use diesel::{
+    pg::PgConnection as Connection, prelude::*, result::Error as DieselError, Connection as _,
+    Queryable, RunQueryDsl,
+};
+
+use crate::{
+    article::{self, Article},
+    schema::{articles, series},
+};
+
+#[derive(Clone, Debug, Deserialize, Serialize, Queryable, Identifiable)]
+#[table_name = "series"]
+pub struct Series {
+    pub id: i32,
+    pub title: String,
+    /// The pretty url of the series' page
+    pub url: String,
+    /// Markdown introducing the series
+    pub description: String,
+}
+
+#[derive(Insertable)]
+#[table_name = "series"]
+struct NewSeries<'a> {
+    title: &'a str,
+    url: String,
+}
+
+/// Changes to the title, url and description of a series
+#[derive(AsChangeset, Deserialize)]
+#[table_name = "series"]
+pub struct SeriesChanges {
+    pub title: String,
+    pub url: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+/// The series an article is assigned to in the edit form
+#[derive(Deserialize)]
+pub struct Assignment {
+    /// The title of the series, which is created if there's none with it yet. Empty to take the
+    /// article out of its series.
+    #[serde(default)]
+    pub series: String,
+    /// The article's position in the series. Added after the other articles if unset.
+    #[serde(default, deserialize_with = "crate::page::optional_number")]
+    pub series_part: Option<i32>,
+}
+
+/// Where an article is in its series
+#[derive(Serialize)]
+pub struct Position {
+    pub series: Series,
+    /// The article's part number, counting from one
+    pub part: usize,
+    /// The number of parts
+    pub count: usize,
+    pub previous: Option<Article>,
+    pub next: Option<Article>,
+}
+
+fn check(title: &str, url: &str) -> Result<(), failure::Error> {
+    if title.trim().is_empty() {
+        return Err(failure::err_msg("Series has no title"));
+    }
+    if url.is_empty() || url.parse::<i32>().is_ok() {
+        return Err(failure::err_msg("Series urls can't be empty or a number"));
+    }
+    if !article::valid_url(url) {
+        return Err(failure::err_msg("Illegal character in series url"));
+    }
+    Ok(())
+}
+
+/// Checks that no series but the one with id `id` has the title or url, as both are unique.
+/// Titles differing only in case get the same url.
+fn check_unique(
+    connection: &Connection,
+    id: Option<i32>,
+    title: &str,
+    url: &str,
+) -> Result<(), failure::Error> {
+    let mut query = series::table
+        .filter(series::title.eq(title).or(series::url.eq(url)))
+        .into_boxed();
+    if let Some(id) = id {
+        query = query.filter(series::id.ne(id));
+    }
+    match query.first::<Series>(connection).optional()? {
+        Some(other) if other.title == title => Err(failure::format_err!(
+            "There's already a series titled '{}'",
+            title
+        )),
+        Some(other) => Err(failure::format_err!(
+            "The series '{}' already has the url '{}'",
+            other.title,
+            url
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Gets all series in alphabetical order
+pub fn list(connection: &Connection) -> Result<Vec<Series>, DieselError> {
+    series::table
+        .order(series::title.asc())
+        .load::<Series>(connection)
+}
+
+/// Gets a series by its id or url
+pub fn view(connection: &Connection, name: &str) -> Result<Series, DieselError> {
+    match name.parse::<i32>() {
+        Ok(id) => series::table.find(id).first(connection),
+        Err(_) => series::table.filter(series::url.eq(name)).first(connection),
+    }
+}
+
+/// Gets the articles of a series in order, published or not
+pub fn articles(connection: &Connection, id: i32) -> Result<Vec<Article>, DieselError> {
+    use crate::schema::articles::dsl;
+
+    dsl::articles
+        .filter(dsl::series.eq(id))
+        .order((dsl::series_part.asc().nulls_last(), dsl::date.asc()))
+        .load::<Article>(connection)
+}
+
+/// Finds where a published article is among the published articles of its series.
+pub fn position(
+    connection: &Connection,
+    article: &Article,
+) -> Result<Option<Position>, DieselError> {
+    let id = match article.series {
+        Some(id) if article.visible => id,
+        _ => return Ok(None),
+    };
+    let series = series::table.find(id).first(connection)?;
+    Ok(locate(series, articles(connection, id)?, article.id))
+}
+
+/// Finds where the article with id `id` is among the published articles of a series, given all
+/// of its articles in order.
+fn locate(series: Series, mut parts: Vec<Article>, id: i32) -> Option<Position> {
+    parts.retain(|a| a.visible);
+    let index = parts.iter().position(|a| a.id == id)?;
+    let count = parts.len();
+    let next = parts.drain(index + 1..).next();
+    let previous = index.checked_sub(1).map(|i| parts.swap_remove(i));
+    Some(Position {
+        series,
+        part: index + 1,
+        count,
+        previous,
+        next,
+    })
+}
+
+/// Sets the series of an article and its part number in it.
+pub fn set(
+    connection: &Connection,
+    article: i32,
+    series: Option<i32>,
+    part: Option<i32>,
+) -> Result<usize, DieselError> {
+    diesel::update(articles::table.find(article))
+        .set((articles::series.eq(series), articles::series_part.eq(part)))
+        .execute(connection)
+}
+
+/// Assigns an article to the series with the given title, creating it if needed.
+pub fn assign(
+    connection: &Connection,
+    article: i32,
+    assignment: &Assignment,
+) -> Result<(), failure::Error> {
+    let title = assignment.series.trim();
+    if title.is_empty() {
+        set(connection, article, None, None)?;
+        return Ok(());
+    }
+    let id = match series::table
+        .filter(series::title.eq(title))
+        .select(series::id)
+        .first(connection)
+        .optional()?
+    {
+        Some(id) => id,
+        None => {
+            let url = article::slug(title);
+            check(title, &url)?;
+            check_unique(connection, None, title, &url)?;
+            diesel::insert_into(series::table)
+                .values(&NewSeries { title, url })
+                .returning(series::id)
+                .get_result(connection)?
+        }
+    };
+
+    let (current, current_part): (Option<i32>, Option<i32>) = articles::table
+        .find(article)
+        .select((articles::series, articles::series_part))
+        .first(connection)?;
+    let last: Option<i32> = articles::table
+        .filter(articles::series.eq(id))
+        .select(diesel::dsl::max(articles::series_part))
+        .first(connection)?;
+    let same_series = current == Some(id);
+    let part = part_number(assignment.series_part, same_series, current_part, last);
+    set(connection, article, Some(id), part)?;
+    Ok(())
+}
+
+/// Picks the part number of an article assigned to a series: the requested one if any, otherwise
+/// the one it has if it stays in the same series, or the one after the series' last part.
+fn part_number(
+    requested: Option<i32>,
+    same_series: bool,
+    current: Option<i32>,
+    last: Option<i32>,
+) -> Option<i32> {
+    match requested {
+        Some(part) => Some(part),
+        None if same_series => current,
+        None => Some(last.unwrap_or(0) + 1),
+    }
+}
+
+/// Edits the title, url and description of a series
+pub fn edit(
+    connection: &Connection,
+    id: i32,
+    changes: &SeriesChanges,
+) -> Result<usize, failure::Error> {
+    check(&changes.title, &changes.url)?;
+    check_unique(connection, Some(id), &changes.title, &changes.url)?;
+    Ok(diesel::update(series::table.find(id))
+        .set(changes)
+        .execute(connection)?)
+}
+
+/// Deletes a series. Its articles are kept, without their part numbers.
+pub fn delete(connection: &Connection, id: i32) -> Result<usize, DieselError> {
+    connection.transaction(|| {
+        diesel::update(articles::table.filter(articles::series.eq(id)))
+            .set((
+                articles::series.eq(None::<i32>),
+                articles::series_part.eq(None::<i32>),
+            ))
+            .execute(connection)?;
+        diesel::delete(series::table.find(id)).execute(connection)
+    })
+}
+
+/// Adds a series with a given id, used when restoring backups
+pub fn restore(connection: &Connection, series: &Series) -> Result<usize, DieselError> {
+    diesel::insert_into(series::table)
+        .values((
+            series::id.eq(series.id),
+            series::title.eq(&series.title),
+            series::url.eq(&series.url),
+            series::description.eq(&series.description),
+        ))
+        .execute(connection)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::{check, locate, part_number, Series};
+    use crate::article::Article;
+
+    fn article(id: i32, visible: bool) -> Article {
+        let date = NaiveDate::from_ymd_opt(2024, 3, id as u32)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        Article {
+            id,
+            title: format!("Part {}", id),
+            author: String::from("root"),
+            url: format!("part-{}", id),
+            content: String::new(),
+            date,
+            visible,
+            updated_at: date,
+            toc: false,
+            tags: Vec::new(),
+            summary: None,
+            series: Some(1),
+            series_part: Some(id),
+        }
+    }
+
+    fn series() -> Series {
+        Series {
+            id: 1,
+            title: String::from("Rust Tips"),
+            url: String::from("rust-tips"),
+            description: String::new(),
+        }
+    }
+
+    #[test]
+    fn position() {
+        let parts = || vec![article(1, true), article(2, false), article(3, true)];
+        let first = locate(series(), parts(), 1).unwrap();
+        assert_eq!((first.part, first.count), (1, 2));
+        assert!(first.previous.is_none());
+        assert_eq!(first.next.map(|a| a.id), Some(3));
+
+        // Unpublished parts are skipped
+        let last = locate(series(), parts(), 3).unwrap();
+        assert_eq!((last.part, last.count), (2, 2));
+        assert_eq!(last.previous.map(|a| a.id), Some(1));
+        assert!(last.next.is_none());
+
+        assert!(locate(series(), parts(), 2).is_none());
+        assert!(locate(series(), parts(), 4).is_none());
+    }
+
+    #[test]
+    fn part_numbers() {
+        // Requested parts always win
+        assert_eq!(part_number(Some(5), true, Some(2), Some(3)), Some(5));
+        assert_eq!(part_number(Some(5), false, None, Some(3)), Some(5));
+        // Articles staying in their series keep their part
+        assert_eq!(part_number(None, true, Some(2), Some(3)), Some(2));
+        assert_eq!(part_number(None, true, None, Some(3)), None);
+        // Others are added at the end
+        assert_eq!(part_number(None, false, Some(2), Some(3)), Some(4));
+        assert_eq!(part_number(None, false, None, None), Some(1));
+    }
+
+    #[test]
+    fn checks() {
+        assert!(check("Rust Tips", "rust-tips").is_ok());
+        assert!(check(" ", "rust-tips").is_err());
+        assert!(check("Rust Tips", "").is_err());
+        assert!(check("2024", "2024").is_err());
+        assert!(check("Rust Tips", "rust?tips").is_err());
+    }
+}
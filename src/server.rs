@@ -0,0 +1,191 @@
+//! Running the HTTP(S) server, with graceful shutdown and systemd socket activation.
+
+use futures::{
+    future::{self, Either},
+    prelude::*,
+    stream,
+};
+use gotham::{
+    hyper::{
+        server::{
+            accept::{self, Accept},
+            conn::{AddrIncoming, AddrStream},
+        },
+        service::{make_service_fn, service_fn},
+        Server,
+    },
+    router::Router,
+    service::call_handler,
+    state::State,
+};
+use listenfd::ListenFd;
+use sd_notify::NotifyState;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    signal::unix::{signal, SignalKind},
+};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+
+use std::{
+    convert::Infallible,
+    io,
+    net::{SocketAddr, TcpListener},
+    panic::AssertUnwindSafe,
+    time::Duration,
+};
+
+/// How long a client may take to complete a TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Maximum number of TLS handshakes in progress at once
+const MAX_HANDSHAKES: usize = 128;
+
+/// Gets a listener for `address`. If the service manager passed sockets to the process, the one
+/// at `index` is used instead of binding to the address.
+pub fn listener(fds: &mut ListenFd, index: usize, address: &str) -> io::Result<TcpListener> {
+    let listener = match fds.take_tcp_listener(index)? {
+        Some(listener) => {
+            log::info!("Using socket {} passed by the service manager", index);
+            listener
+        }
+        None => TcpListener::bind(address)?,
+    };
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+/// Creates a future that completes when the process receives SIGTERM or SIGINT. Must be called
+/// from within the runtime.
+pub fn shutdown_signal() -> io::Result<impl Future<Output = ()>> {
+    let mut term = signal(SignalKind::terminate())?;
+    let mut int = signal(SignalKind::interrupt())?;
+    Ok(async move {
+        future::select(Box::pin(term.recv()), Box::pin(int.recv())).await;
+        log::info!("Shutting down");
+        let _ = sd_notify::notify(false, &[NotifyState::Stopping]);
+    })
+}
+
+/// Tells the service manager that startup has finished. Does nothing when not running under
+/// systemd.
+pub fn notify_ready() {
+    if let Err(e) = sd_notify::notify(false, &[NotifyState::Ready]) {
+        log::warn!("Failed to notify service manager: {}", e);
+    }
+}
+
+/// A connection the client's address can be determined for
+pub trait Remote {
+    fn remote_addr(&self) -> SocketAddr;
+}
+
+impl Remote for AddrStream {
+    fn remote_addr(&self) -> SocketAddr {
+        AddrStream::remote_addr(self)
+    }
+}
+
+impl Remote for TlsStream<TcpStream> {
+    fn remote_addr(&self) -> SocketAddr {
+        self.get_ref()
+            .0
+            .peer_addr()
+            .unwrap_or_else(|_| SocketAddr::from(([0, 0, 0, 0], 0)))
+    }
+}
+
+/// Serves requests on `incoming` with `router` until `shutdown` completes. Then stops accepting
+/// connections and waits for in-flight requests to finish, for at most `timeout`.
+async fn run<I, S>(
+    incoming: I,
+    router: Router,
+    shutdown: S,
+    timeout: Duration,
+) -> Result<(), failure::Error>
+where
+    I: Accept,
+    I::Conn: Remote + AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    I::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    S: Future<Output = ()> + Clone + Send + 'static,
+{
+    let make_service = make_service_fn(move |connection: &I::Conn| {
+        let address = connection.remote_addr();
+        let router = router.clone();
+        future::ok::<_, Infallible>(service_fn(move |request| {
+            let state = State::from_request(request, address);
+            call_handler(router.clone(), AssertUnwindSafe(state))
+        }))
+    });
+    let server = Server::builder(incoming)
+        .serve(make_service)
+        .with_graceful_shutdown(shutdown.clone());
+    let deadline = shutdown.then(|_| tokio::time::sleep(timeout));
+
+    match future::select(Box::pin(server), Box::pin(deadline)).await {
+        Either::Left((result, _)) => Ok(result?),
+        Either::Right(_) => {
+            log::warn!("Timed out waiting for requests to finish");
+            Ok(())
+        }
+    }
+}
+
+/// Serves plain HTTP requests on `listener`. See `run`.
+pub async fn serve<S>(
+    listener: TcpListener,
+    router: Router,
+    shutdown: S,
+    timeout: Duration,
+) -> Result<(), failure::Error>
+where
+    S: Future<Output = ()> + Clone + Send + 'static,
+{
+    let incoming = AddrIncoming::from_listener(tokio::net::TcpListener::from_std(listener)?)?;
+    run(incoming, router, shutdown, timeout).await
+}
+
+/// Serves HTTPS requests on `listener`. See `run`.
+pub async fn serve_tls<S>(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    router: Router,
+    shutdown: S,
+    timeout: Duration,
+) -> Result<(), failure::Error>
+where
+    S: Future<Output = ()> + Clone + Send + 'static,
+{
+    let listener = tokio::net::TcpListener::from_std(listener)?;
+    let connections = stream::unfold(listener, |listener| async move {
+        let accepted = listener.accept().await;
+        Some((accepted, listener))
+    });
+    // Handshakes are done concurrently so slow clients don't hold up others, and failed ones are
+    // dropped rather than ending the server.
+    let streams = connections
+        .filter_map(|accepted| {
+            future::ready(match accepted {
+                Ok((socket, _)) => Some(socket),
+                Err(e) => {
+                    log::error!("Failed to accept connection: {}", e);
+                    None
+                }
+            })
+        })
+        .map(move |socket| tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(socket)))
+        .buffer_unordered(MAX_HANDSHAKES)
+        .filter_map(|result| {
+            future::ready(match result {
+                Ok(Ok(stream)) => Some(Ok::<_, io::Error>(stream)),
+                Ok(Err(e)) => {
+                    log::debug!("TLS handshake failed: {}", e);
+                    None
+                }
+                Err(_) => {
+                    log::debug!("TLS handshake timed out");
+                    None
+                }
+            })
+        });
+    run(accept::from_stream(streams), router, shutdown, timeout).await
+}
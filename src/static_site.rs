@@ -0,0 +1,470 @@
+//! Generation of a static copy of the site, for hosting without running the server.
+//!
+//! Pages are rendered by sending requests to the site's router, so they look exactly like the
+//! ones the server sends to guests. Links between generated pages, assets and media are made
+//! relative so the copy works from any directory. A manifest in the output directory records
+//! what each article page was made from, so articles that haven't changed since the last build
+//! aren't rendered again.
+
+use gotham::{hyper::StatusCode, router::Router, test::TestServer};
+use percent_encoding::{percent_decode_str, utf8_percent_encode};
+use sha2::{Digest, Sha256};
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    article, comment,
+    config::Settings,
+    db::{Connection, DbConnection},
+    page, series,
+    theme::Theme,
+    user,
+};
+
+/// Name of the file in the output directory recording what was built
+const MANIFEST: &str = ".mogger-build.json";
+/// Path of the RSS feed
+const FEED: &str = "/feed.rss";
+/// Attributes whose values are links
+const LINK_ATTRIBUTES: &[&str] = &["href", "src", "srcset", "action"];
+/// Paths of files that are copied to the output as they are
+const FILE_PREFIXES: &[&str] = &["/file/", "/media/"];
+
+#[derive(Default, Deserialize, Serialize)]
+struct Manifest {
+    /// Fingerprint of what every page depends on
+    site: String,
+    /// Paths of the generated pages, with fingerprints of what they were made from for those
+    /// that are only rebuilt when it changes
+    pages: BTreeMap<String, Option<String>>,
+}
+
+/// A page to generate
+struct Page {
+    /// The decoded path of the page
+    route: String,
+    /// The path to request the page at
+    path: String,
+    fingerprint: Option<String>,
+}
+
+impl Page {
+    fn new(route: String, path: String) -> Self {
+        Page {
+            route,
+            path,
+            fingerprint: None,
+        }
+    }
+}
+
+/// What a build did
+#[derive(Default)]
+pub struct Summary {
+    pub rendered: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+    pub copied: usize,
+}
+
+fn fingerprint(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Checks that a tag or article url can be a directory name.
+fn valid_segment(segment: &str) -> bool {
+    !segment.is_empty() && segment != "." && segment != ".." && !segment.contains(['/', '\\'])
+}
+
+/// Checks if a page is an RSS feed, which is written to a file of its own name and isn't HTML.
+fn is_feed(route: &str) -> bool {
+    route.ends_with(".rss")
+}
+
+/// Gets the file a page is written to, relative to the output directory.
+fn output_file(route: &str) -> PathBuf {
+    if is_feed(route) {
+        return PathBuf::from(route.trim_start_matches('/'));
+    }
+    Path::new(route.trim_start_matches('/')).join("index.html")
+}
+
+/// Lists the pages of the site.
+fn pages(connection: &Connection) -> Result<Vec<Page>, failure::Error> {
+    let mut pages = vec![
+        Page::new(String::from("/"), String::from("/")),
+        Page::new(String::from(FEED), String::from(FEED)),
+    ];
+    let count = article::count(connection)?;
+    let page_count = (count + article::PAGE_LEN as i64 - 1) / article::PAGE_LEN as i64;
+    for page in 1..=page_count {
+        let route = format!("/page/{}", page);
+        pages.push(Page::new(route.clone(), route));
+    }
+    for static_page in page::list(connection)?.into_iter().filter(|p| p.visible) {
+        let route = format!("/{}", static_page.slug);
+        pages.push(Page::new(route.clone(), route));
+    }
+    for tag in article::tags(connection)? {
+        if !valid_segment(&tag) {
+            log::warn!("Skipping tag '{}', which can't be a directory name", tag);
+            continue;
+        }
+        pages.push(Page::new(format!("/tag/{}", tag), article::tag_path(&tag)));
+    }
+    for account in user::accounts(connection)? {
+        let count = article::count_by_author(connection, &account.id)?;
+        if count == 0 {
+            continue;
+        }
+        if !valid_segment(&account.id) {
+            log::warn!(
+                "Skipping the profile of '{}', whose name can't be a directory name",
+                account.id
+            );
+            continue;
+        }
+        let path = format!(
+            "/user/{}",
+            utf8_percent_encode(&account.id, article::PATH_ESCAPED)
+        );
+        let route = format!("/user/{}", account.id);
+        pages.push(Page::new(route.clone(), path.clone()));
+        pages.push(Page::new(
+            format!("{}/feed.rss", route),
+            format!("{}/feed.rss", path),
+        ));
+        let page_count = (count + article::PAGE_LEN as i64 - 1) / article::PAGE_LEN as i64;
+        for page in 1..=page_count {
+            pages.push(Page::new(
+                format!("{}/page/{}", route, page),
+                format!("{}/page/{}", path, page),
+            ));
+        }
+    }
+    for entry in series::list(connection)? {
+        if !valid_segment(&entry.url) {
+            log::warn!(
+                "Skipping series '{}', whose url can't be a directory name",
+                entry.url
+            );
+            continue;
+        }
+        let path = format!(
+            "/series/{}",
+            utf8_percent_encode(&entry.url, article::PATH_ESCAPED)
+        );
+        pages.push(Page::new(format!("/series/{}", entry.url), path));
+    }
+    for article in article::list(connection)?.into_iter().filter(|a| a.visible) {
+        if !valid_segment(&article.url) {
+            log::warn!(
+                "Skipping article '{}', whose url can't be a directory name",
+                article.url
+            );
+            continue;
+        }
+        let comments = comment::list_flat(connection, article.id)?;
+        // The links to the other parts of its series change with them
+        let series = series::position(connection, &article)?;
+        // Their cards under the article show their profiles
+        let authors: Vec<_> = article
+            .authors(connection)?
+            .into_iter()
+            .map(|user| (user.id, user.name, user.bio, user.avatar, user.email))
+            .collect();
+        let path = format!(
+            "/article/{}",
+            utf8_percent_encode(&article.url, article::PATH_ESCAPED)
+        );
+        pages.push(Page {
+            route: format!("/article/{}", article.url),
+            path,
+            fingerprint: Some(fingerprint(&serde_json::to_vec(&(
+                &article, &comments, &series, &authors,
+            ))?)),
+        });
+    }
+    Ok(pages)
+}
+
+/// Fingerprints what every page depends on: the version of mogger, the theme, the pages in the
+/// navigation bar and whether avatars come from Gravatar.
+fn site_fingerprint(settings: &Settings, theme: &Theme) -> Result<String, failure::Error> {
+    let site = (
+        env!("CARGO_PKG_VERSION"),
+        settings.features.gravatar,
+        &theme.title,
+        &theme.head,
+        &theme.header,
+        &theme.footer,
+        theme.nav(),
+    );
+    Ok(fingerprint(&serde_json::to_vec(&site)?))
+}
+
+/// Rewrites a root-relative link to a generated page or copied file to be relative to a page
+/// `depth` directories deep. Other links are left alone, as they need the server.
+fn relative_link(link: &str, depth: usize, routes: &HashSet<String>) -> String {
+    if !link.starts_with('/') || link.starts_with("//") {
+        return link.to_owned();
+    }
+    let end = link.find(['?', '#']).unwrap_or(link.len());
+    let (path, rest) = link.split_at(end);
+    let prefix = "../".repeat(depth);
+    if FILE_PREFIXES.iter().any(|p| path.starts_with(p)) {
+        return format!("{}{}{}", prefix, &path[1..], rest);
+    }
+    let route = percent_decode_str(path).decode_utf8_lossy();
+    let route = match route.len() > 1 {
+        true => route.trim_end_matches('/'),
+        false => &route,
+    };
+    if !routes.contains(route) {
+        return link.to_owned();
+    }
+    if is_feed(route) {
+        return format!("{}{}{}", prefix, &path[1..], rest);
+    }
+    match path.trim_matches('/') {
+        "" => format!("{}index.html{}", prefix, rest),
+        path => format!("{}{}/index.html{}", prefix, path, rest),
+    }
+}
+
+/// Makes the links in a page at `route` relative, given the routes of the generated pages.
+fn relative_links(html: &str, route: &str, routes: &HashSet<String>) -> String {
+    let depth = route.split('/').filter(|s| !s.is_empty()).count();
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find("=\"") {
+        let (before, after) = rest.split_at(start + 2);
+        out.push_str(before);
+        let attribute = before[..start]
+            .rsplit(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let end = after.find('"').unwrap_or(after.len());
+        let value = &after[..end];
+        rest = &after[end..];
+        if !LINK_ATTRIBUTES.contains(&attribute.as_str()) {
+            out.push_str(value);
+        } else if attribute == "srcset" {
+            let candidates: Vec<String> = value
+                .split(',')
+                .map(|candidate| {
+                    let candidate = candidate.trim();
+                    let (link, descriptor) = candidate.split_once(' ').unwrap_or((candidate, ""));
+                    let link = relative_link(link, depth, routes);
+                    match descriptor {
+                        "" => link,
+                        descriptor => format!("{} {}", link, descriptor),
+                    }
+                })
+                .collect();
+            out.push_str(&candidates.join(", "));
+        } else {
+            out.push_str(&relative_link(value, depth, routes));
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Adds the files in a directory to `files`, mapping their paths relative to `root` to where
+/// they are.
+fn collect_files(
+    root: &Path,
+    dir: &Path,
+    files: &mut BTreeMap<PathBuf, PathBuf>,
+) -> Result<(), failure::Error> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(root, &path, files)?;
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            files.insert(relative.to_owned(), path.clone());
+        }
+    }
+    Ok(())
+}
+
+/// Copies files to a directory, skipping those whose copy is up to date. Returns how many were
+/// copied.
+fn copy_files(files: &BTreeMap<PathBuf, PathBuf>, to: &Path) -> Result<usize, failure::Error> {
+    let mut copied = 0;
+    for (relative, source) in files {
+        let target = to.join(relative);
+        let metadata = fs::metadata(source)?;
+        if let Ok(existing) = fs::metadata(&target) {
+            if existing.len() == metadata.len() && existing.modified()? >= metadata.modified()? {
+                continue;
+            }
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(source, &target)?;
+        copied += 1;
+    }
+    Ok(copied)
+}
+
+/// Removes a generated page, and its directory if that is left empty.
+fn remove_page(out: &Path, route: &str) -> Result<(), failure::Error> {
+    let file = out.join(output_file(route));
+    if file.exists() {
+        fs::remove_file(&file)?;
+    }
+    if let Some(dir) = file.parent().filter(|dir| *dir != out) {
+        // Fails if other files were put there, which are left alone
+        let _ = fs::remove_dir(dir);
+    }
+    Ok(())
+}
+
+/// Renders the site with `router` to static files in `out`. Only articles that changed since
+/// the last build into the same directory are rendered again, unless `full` is set.
+pub fn build(
+    router: Router,
+    connection: &DbConnection,
+    settings: &Settings,
+    theme: &Theme,
+    out: &Path,
+    full: bool,
+) -> Result<Summary, failure::Error> {
+    let mut summary = Summary::default();
+    let (pages, site) = {
+        let connection = &*connection.lock()?;
+        (pages(connection)?, site_fingerprint(settings, theme)?)
+    };
+
+    let manifest_path = out.join(MANIFEST);
+    let previous: Manifest = match fs::read(&manifest_path) {
+        Ok(data) => serde_json::from_slice(&data).unwrap_or_default(),
+        Err(_) => Manifest::default(),
+    };
+    let rebuild = full || previous.site != site;
+    let routes: HashSet<String> = pages.iter().map(|page| page.route.clone()).collect();
+
+    let server = TestServer::new(router).map_err(|e| failure::err_msg(e.to_string()))?;
+    let client = server.client();
+    let mut manifest = Manifest {
+        site,
+        pages: BTreeMap::new(),
+    };
+    for page in pages {
+        let file = out.join(output_file(&page.route));
+        let unchanged = page.fingerprint.is_some()
+            && previous.pages.get(&page.route) == Some(&page.fingerprint);
+        if !rebuild && unchanged && file.exists() {
+            summary.unchanged += 1;
+            manifest.pages.insert(page.route, page.fingerprint);
+            continue;
+        }
+
+        let response = client
+            .get(format!("http://localhost{}", page.path))
+            .perform()
+            .map_err(|e| failure::err_msg(e.to_string()))?;
+        if response.status() != StatusCode::OK {
+            return Err(failure::format_err!(
+                "Failed to render {}: {}",
+                page.route,
+                response.status()
+            ));
+        }
+        let body = response.read_body()?;
+        let body = match is_feed(&page.route) {
+            true => body,
+            false => relative_links(&String::from_utf8(body)?, &page.route, &routes).into_bytes(),
+        };
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&file, body)?;
+        summary.rendered += 1;
+        manifest.pages.insert(page.route, page.fingerprint);
+    }
+
+    for route in previous.pages.keys() {
+        if !manifest.pages.contains_key(route) {
+            remove_page(out, route)?;
+            summary.removed += 1;
+        }
+    }
+
+    // The theme's assets replace the built-in ones with the same name
+    let mut assets = BTreeMap::new();
+    let assets_dir = Path::new(&settings.theme.assets);
+    collect_files(assets_dir, assets_dir, &mut assets)?;
+    if let Some(theme_dir) = &settings.theme.dir {
+        let theme_assets = Path::new(theme_dir).join("static");
+        if theme_assets.is_dir() {
+            collect_files(&theme_assets, &theme_assets, &mut assets)?;
+        }
+    }
+    summary.copied += copy_files(&assets, &out.join("file"))?;
+    let media_dir = Path::new(&settings.media.dir);
+    if media_dir.is_dir() {
+        let mut media = BTreeMap::new();
+        collect_files(media_dir, media_dir, &mut media)?;
+        summary.copied += copy_files(&media, &out.join("media"))?;
+    }
+
+    fs::write(&manifest_path, serde_json::to_vec_pretty(&manifest)?)?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{output_file, relative_links};
+
+    #[test]
+    fn links() {
+        let routes: HashSet<String> = ["/", "/feed.rss", "/about", "/article/a b", "/tag/c"]
+            .iter()
+            .map(|route| route.to_string())
+            .collect();
+        let html = "<a href=\"/\">Home</a> <a href=\"/article/a%20b#comments\">A</a> \
+                    <link href=\"/feed.rss\"> <img src=\"/media/x.png\" \
+                    srcset=\"/media/x-1.png 1x, /media/x-2.png 2x\" alt=\"/about\"> \
+                    <a href=\"/login\">Log in</a> <a href=\"https://example.com/\">Out</a> \
+                    <a href=\"/tag/c/\">C</a> <form action=\"/about?x=1\"></form>";
+        assert_eq!(
+            relative_links(html, "/article/a b", &routes),
+            "<a href=\"../../index.html\">Home</a> \
+             <a href=\"../../article/a%20b/index.html#comments\">A</a> \
+             <link href=\"../../feed.rss\"> <img src=\"../../media/x.png\" \
+             srcset=\"../../media/x-1.png 1x, ../../media/x-2.png 2x\" alt=\"/about\"> \
+             <a href=\"/login\">Log in</a> <a href=\"https://example.com/\">Out</a> \
+             <a href=\"../../tag/c/index.html\">C</a> \
+             <form action=\"../../about/index.html?x=1\"></form>"
+        );
+        assert_eq!(
+            relative_links("<a href=\"/about\">", "/", &routes),
+            "<a href=\"about/index.html\">"
+        );
+    }
+
+    #[test]
+    fn files() {
+        assert_eq!(output_file("/"), std::path::Path::new("index.html"));
+        assert_eq!(
+            output_file("/page/2"),
+            std::path::Path::new("page/2/index.html")
+        );
+        assert_eq!(output_file("/feed.rss"), std::path::Path::new("feed.rss"));
+        assert_eq!(
+            output_file("/user/amanda/feed.rss"),
+            std::path::Path::new("user/amanda/feed.rss")
+        );
+    }
+}
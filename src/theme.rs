@@ -0,0 +1,198 @@
+//! Site themes.
+//!
+//! A theme is a directory that can contain:
+//!
+//! * `static/`: Static assets served at `/file/`, taking precedence over the built-in ones
+//! * `theme.toml`: The site title and values of the CSS color variables
+//! * `head.html`: Extra HTML to add to the end of `<head>`
+//! * `header.html`: HTML replacing the default header with the logo and title
+//! * `footer.html`: HTML replacing the default footer
+//!
+//! Anything also set in the `[theme]` section of the configuration is taken from there instead.
+
+use gotham::{
+    handler::{DirHandler, FileOptions, Handler, HandlerFuture, NewHandler},
+    hyper::Uri,
+    state::{FromState, State, StateData},
+};
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Component, Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, PoisonError, RwLock},
+};
+
+use crate::{config, db::Connection, page};
+
+/// The url prefix static assets are served from
+pub const PREFIX: &str = "/file/";
+
+/// The site title used if neither the configuration nor the theme sets one
+const DEFAULT_TITLE: &str = "Amanda's blog";
+
+/// The contents of a theme's `theme.toml`
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ThemeFile {
+    title: Option<String>,
+    colors: BTreeMap<String, String>,
+}
+
+/// The theme in use, with the settings in the configuration applied
+#[derive(Clone, StateData)]
+pub struct Theme {
+    /// Site title
+    pub title: String,
+    /// Extra HTML to add to the end of `<head>`, including the color variables
+    pub head: String,
+    /// HTML replacing the default header
+    pub header: Option<String>,
+    /// HTML replacing the default footer
+    pub footer: Option<String>,
+    /// Slugs and titles of the pages listed in the navigation bar, loaded when pages change
+    nav: Arc<RwLock<Vec<(String, String)>>>,
+}
+
+/// Checks that a CSS color variable name and value can be safely put in a stylesheet.
+pub fn check_color(name: &str, value: &str) -> Result<(), &'static str> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err("names may only contain letters, digits and dashes");
+    }
+    if value.contains(&[';', '{', '}', '<', '>', '\n'][..]) {
+        return Err("invalid color value");
+    }
+    Ok(())
+}
+
+/// Reads a fragment file from the theme directory, if it exists.
+fn fragment(dir: Option<&Path>, name: &str) -> Result<Option<String>, failure::Error> {
+    match dir.map(|dir| dir.join(name)) {
+        Some(path) if path.is_file() => Ok(Some(fs::read_to_string(path)?)),
+        _ => Ok(None),
+    }
+}
+
+impl Theme {
+    /// Loads the configured theme.
+    pub fn load(settings: &config::Theme) -> Result<Self, failure::Error> {
+        let dir = settings.dir.as_deref().map(Path::new);
+        let file: ThemeFile = match dir.map(|dir| dir.join("theme.toml")) {
+            Some(path) if path.is_file() => toml::from_slice(&fs::read(&path)?)
+                .map_err(|e| failure::format_err!("Invalid {}: {}", path.display(), e))?,
+            _ => ThemeFile::default(),
+        };
+
+        let mut colors = file.colors;
+        colors.extend(settings.colors.clone());
+        let mut head = String::new();
+        if !colors.is_empty() {
+            head.push_str("<style>:root {");
+            for (name, value) in &colors {
+                check_color(name, value).map_err(|e| failure::format_err!("{}: {}", name, e))?;
+                head.push_str(&format!(" --{}: {};", name, value));
+            }
+            head.push_str(" }</style>\n");
+        }
+        if let Some(fragment) = fragment(dir, "head.html")? {
+            head.push_str(&fragment);
+        }
+        if let Some(extra) = &settings.head {
+            head.push_str(extra);
+        }
+
+        Ok(Theme {
+            title: settings
+                .title
+                .clone()
+                .or(file.title)
+                .unwrap_or_else(|| String::from(DEFAULT_TITLE)),
+            head,
+            header: settings.header.clone().or(fragment(dir, "header.html")?),
+            footer: settings.footer.clone().or(fragment(dir, "footer.html")?),
+            nav: Arc::default(),
+        })
+    }
+
+    /// Gets the slugs and titles of the pages to list in the navigation bar, which is part of the
+    /// theme's layout.
+    pub fn nav(&self) -> Vec<(String, String)> {
+        self.nav
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Loads the pages listed in the navigation bar. Must be called after changing pages.
+    pub fn load_nav(&self, connection: &Connection) -> Result<(), failure::Error> {
+        let nav = page::nav(connection)?;
+        *self.nav.write().unwrap_or_else(PoisonError::into_inner) = nav;
+        Ok(())
+    }
+}
+
+/// Handler serving static assets from the theme's `static` directory if the requested file exists
+/// there, and from the built-in assets otherwise.
+#[derive(Clone)]
+pub struct AssetHandler {
+    base: DirHandler,
+    theme: Option<(PathBuf, DirHandler)>,
+}
+
+impl AssetHandler {
+    /// Creates a handler for the built-in assets in `assets` and the assets of the theme in
+    /// `theme_dir`, if any. `options` builds the file options to serve a directory with.
+    pub fn new(
+        options: impl Fn(&Path) -> FileOptions,
+        assets: &Path,
+        theme_dir: Option<&Path>,
+    ) -> Self {
+        let theme = theme_dir
+            .map(|dir| dir.join("static"))
+            .filter(|dir| dir.is_dir())
+            .map(|dir| {
+                let handler = DirHandler::new(options(&dir));
+                (dir, handler)
+            });
+        AssetHandler {
+            base: DirHandler::new(options(assets)),
+            theme,
+        }
+    }
+
+    /// Checks if the theme has its own version of the requested file.
+    fn in_theme(&self, state: &State) -> Option<DirHandler> {
+        let (dir, handler) = self.theme.as_ref()?;
+        let path = Uri::borrow_from(state).path();
+        let relative = Path::new(path.strip_prefix(PREFIX)?);
+        if !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            return None;
+        }
+        if dir.join(relative).is_file() {
+            Some(handler.clone())
+        } else {
+            None
+        }
+    }
+}
+
+impl NewHandler for AssetHandler {
+    type Instance = Self;
+
+    fn new_handler(&self) -> gotham::anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+impl Handler for AssetHandler {
+    fn handle(self, state: State) -> Pin<Box<HandlerFuture>> {
+        match self.in_theme(&state) {
+            Some(theme) => theme.handle(state),
+            None => self.base.handle(state),
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! HTTPS support: certificate loading and reloading, HSTS, and redirects from plain HTTP.
+
+use futures::{future, prelude::*};
+use gotham::{
+    handler::HandlerFuture,
+    helpers::http::response::{create_empty_response, create_permanent_redirect},
+    hyper::{
+        header::{HeaderValue, HOST, STRICT_TRANSPORT_SECURITY},
+        Body, HeaderMap, Response, StatusCode, Uri,
+    },
+    middleware::{Middleware, NewMiddleware},
+    state::{FromState, State, StateData},
+};
+use rustls::{
+    crypto::ring::{default_provider, sign::any_supported_type},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    version::{TLS12, TLS13},
+    ServerConfig,
+};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_rustls::TlsAcceptor;
+
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use crate::config::{self, TlsVersion};
+
+/// Reads a certificate chain and private key from PEM files.
+fn load_key(cert: &Path, key: &Path) -> Result<CertifiedKey, failure::Error> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert)?))
+        .collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(failure::format_err!(
+            "No certificates in {}",
+            cert.display()
+        ));
+    }
+    let private = rustls_pemfile::private_key(&mut BufReader::new(File::open(key)?))?
+        .ok_or_else(|| failure::format_err!("No private key in {}", key.display()))?;
+    let signing = any_supported_type(&private).map_err(|e| failure::err_msg(e.to_string()))?;
+    let key = CertifiedKey::new(certs, signing);
+    key.keys_match()
+        .map_err(|e| failure::err_msg(e.to_string()))?;
+    Ok(key)
+}
+
+/// Gets the last time either of the files was modified.
+fn modified(paths: &[&Path]) -> Option<SystemTime> {
+    paths
+        .iter()
+        .filter_map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .max()
+}
+
+/// The server's certificate, which can be replaced while running.
+#[derive(Debug)]
+pub struct Certificate {
+    cert: PathBuf,
+    key: PathBuf,
+    current: RwLock<(Arc<CertifiedKey>, Option<SystemTime>)>,
+}
+
+impl Certificate {
+    /// Loads the configured certificate.
+    pub fn load(settings: &config::Tls) -> Result<Self, failure::Error> {
+        let (cert, key) = (PathBuf::from(&settings.cert), PathBuf::from(&settings.key));
+        let loaded = load_key(&cert, &key)?;
+        let modified = modified(&[&cert, &key]);
+        Ok(Certificate {
+            cert,
+            key,
+            current: RwLock::new((Arc::new(loaded), modified)),
+        })
+    }
+
+    /// Loads the certificate again. If loading fails, the old certificate is kept.
+    pub fn reload(&self) -> Result<(), failure::Error> {
+        let modified = modified(&[&self.cert, &self.key]);
+        let loaded = load_key(&self.cert, &self.key)?;
+        let mut current = self
+            .current
+            .write()
+            .map_err(|_| failure::err_msg("failed to get lock"))?;
+        *current = (Arc::new(loaded), modified);
+        log::info!("Reloaded TLS certificate {}", self.cert.display());
+        Ok(())
+    }
+
+    /// Checks if the certificate or key has been modified since it was loaded.
+    fn changed(&self) -> bool {
+        let loaded = self.current.read().ok().and_then(|current| current.1);
+        modified(&[&self.cert, &self.key]) != loaded
+    }
+
+    /// Reloads the certificate on SIGHUP, and whenever the files change if `interval` isn't zero.
+    /// Runs forever.
+    pub async fn watch(self: Arc<Self>, interval: Duration) -> Result<(), failure::Error> {
+        let mut hangup = signal(SignalKind::hangup())?;
+        loop {
+            let changed = if interval.is_zero() {
+                hangup.recv().await;
+                true
+            } else {
+                let tick = tokio::time::sleep(interval);
+                match future::select(Box::pin(hangup.recv()), Box::pin(tick)).await {
+                    future::Either::Left(_) => true,
+                    future::Either::Right(_) => self.changed(),
+                }
+            };
+            if changed {
+                if let Err(e) = self.reload() {
+                    log::error!("Failed to reload TLS certificate: {}", e);
+                }
+            }
+        }
+    }
+}
+
+impl ResolvesServerCert for Certificate {
+    fn resolve(&self, _: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.current.read().ok().map(|current| current.0.clone())
+    }
+}
+
+/// Creates a TLS acceptor serving `certificate`.
+pub fn acceptor(
+    settings: &config::Tls,
+    certificate: Arc<Certificate>,
+) -> Result<TlsAcceptor, failure::Error> {
+    let versions: &[_] = match settings.min_version {
+        TlsVersion::V1_2 => &[&TLS13, &TLS12],
+        TlsVersion::V1_3 => &[&TLS13],
+    };
+    let mut config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_protocol_versions(versions)?
+        .with_no_client_auth()
+        .with_cert_resolver(certificate);
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Middleware that adds a `Strict-Transport-Security` header to responses when serving HTTPS
+#[derive(Clone, NewMiddleware)]
+pub struct HstsMiddleware {
+    value: Option<HeaderValue>,
+}
+
+impl HstsMiddleware {
+    pub fn new(settings: &config::Tls) -> Result<Self, failure::Error> {
+        let value = if settings.enabled && !settings.hsts.is_empty() {
+            Some(HeaderValue::from_str(&settings.hsts)?)
+        } else {
+            None
+        };
+        Ok(HstsMiddleware { value })
+    }
+}
+
+impl Middleware for HstsMiddleware {
+    fn call<C>(self, state: State, chain: C) -> Pin<Box<HandlerFuture>>
+    where
+        C: FnOnce(State) -> Pin<Box<HandlerFuture>>,
+    {
+        let value = match self.value {
+            Some(value) => value,
+            None => return chain(state),
+        };
+        chain(state)
+            .and_then(move |(state, mut response)| {
+                response
+                    .headers_mut()
+                    .insert(STRICT_TRANSPORT_SECURITY, value);
+                future::ok((state, response))
+            })
+            .boxed()
+    }
+}
+
+/// Port HTTPS is served on, if not the default
+#[derive(Clone, StateData)]
+pub struct HttpsPort(pub Option<u16>);
+
+/// Permanently redirects a plain HTTP request to the same url over HTTPS.
+pub fn redirect(state: State) -> (State, Response<Body>) {
+    let host = HeaderMap::borrow_from(&state)
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        // Strip the port. IPv6 addresses are enclosed in brackets.
+        .map(|host| match host.rfind(':') {
+            Some(i) if !host[i..].contains(']') => &host[..i],
+            _ => host,
+        });
+    let host = match host {
+        Some(host) if !host.is_empty() => host.to_owned(),
+        _ => {
+            let response = create_empty_response(&state, StatusCode::BAD_REQUEST);
+            return (state, response);
+        }
+    };
+    let port = match HttpsPort::borrow_from(&state).0 {
+        Some(port) => format!(":{}", port),
+        None => String::new(),
+    };
+    let path = Uri::borrow_from(&state)
+        .path_and_query()
+        .map_or("/", |p| p.as_str());
+    let location = format!("https://{}{}{}", host, port, path);
+    let response = create_permanent_redirect(&state, location);
+    (state, response)
+}
@@ -0,0 +1,136 @@
+//! Tables of contents for articles, linking to the ids comrak gives headings.
+
+use comrak::{
+    arena_tree::Node,
+    nodes::{Ast, NodeCode, NodeValue},
+    parse_document, Anchorizer, Arena, ComrakOptions,
+};
+
+use std::cell::RefCell;
+
+use crate::sanitize::escape;
+
+/// A heading in a table of contents
+#[derive(Debug, PartialEq)]
+pub struct Heading {
+    /// The heading's level, from 1 to 6
+    pub level: u32,
+    /// The text of the heading
+    pub title: String,
+    /// The id of the heading in the rendered article
+    pub id: String,
+    /// The headings of the sections within this one
+    pub children: Vec<Heading>,
+}
+
+/// Collects the text of a node the same way comrak does when giving headings ids.
+fn collect_text<'a>(node: &'a Node<'a, RefCell<Ast>>, output: &mut Vec<u8>) {
+    match node.data.borrow().value {
+        NodeValue::Text(ref literal) | NodeValue::Code(NodeCode { ref literal, .. }) => {
+            output.extend_from_slice(literal)
+        }
+        NodeValue::LineBreak | NodeValue::SoftBreak => output.push(b' '),
+        _ => {
+            for child in node.children() {
+                collect_text(child, output);
+            }
+        }
+    }
+}
+
+/// Adds a heading to the list, as a child of the last heading if it's of a lower level.
+fn insert(headings: &mut Vec<Heading>, heading: Heading) {
+    match headings.last_mut() {
+        Some(last) if last.level < heading.level => insert(&mut last.children, heading),
+        _ => headings.push(heading),
+    }
+}
+
+/// Gets the table of contents of an article, nested by heading level.
+pub fn headings(content: &str, options: &ComrakOptions) -> Vec<Heading> {
+    let arena = Arena::new();
+    let root = parse_document(&arena, content, options);
+    let mut anchorizer = Anchorizer::new();
+    let mut headings = Vec::new();
+    for node in root.descendants() {
+        let level = match node.data.borrow().value {
+            NodeValue::Heading(ref heading) => heading.level,
+            _ => continue,
+        };
+        let mut text = Vec::new();
+        collect_text(node, &mut text);
+        let title = String::from_utf8_lossy(&text).into_owned();
+        let heading = Heading {
+            level,
+            id: anchorizer.anchorize(title.clone()),
+            title,
+            children: Vec::new(),
+        };
+        insert(&mut headings, heading);
+    }
+    headings
+}
+
+fn list_html(headings: &[Heading], html: &mut String) {
+    html.push_str("<ol>");
+    for heading in headings {
+        html.push_str(&format!(
+            "<li><a href=\"#{}\">{}</a>",
+            escape(&heading.id),
+            escape(&heading.title)
+        ));
+        if !heading.children.is_empty() {
+            list_html(&heading.children, html);
+        }
+        html.push_str("</li>");
+    }
+    html.push_str("</ol>");
+}
+
+/// Renders a table of contents as nested ordered lists. Empty if there are no headings.
+pub fn html(headings: &[Heading]) -> String {
+    if headings.is_empty() {
+        return String::new();
+    }
+    let mut html = String::from("<nav class=\"toc\"><h2>Contents</h2>");
+    list_html(headings, &mut html);
+    html.push_str("</nav>");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use comrak::ComrakOptions;
+
+    use super::{headings, html, Heading};
+
+    #[test]
+    fn nesting() {
+        let content = "# Intro\n\n### Deep\n\n## Usage\n\n## Usage\n\n# `Code` <&>\n";
+        let headings = headings(content, &ComrakOptions::default());
+        let heading = |level, title: &str, id: &str, children| Heading {
+            level,
+            title: title.to_owned(),
+            id: id.to_owned(),
+            children,
+        };
+        assert_eq!(
+            headings,
+            vec![
+                heading(
+                    1,
+                    "Intro",
+                    "intro",
+                    vec![
+                        heading(3, "Deep", "deep", vec![]),
+                        heading(2, "Usage", "usage", vec![]),
+                        heading(2, "Usage", "usage-1", vec![]),
+                    ]
+                ),
+                heading(1, "Code <&>", "code-", vec![]),
+            ]
+        );
+        assert!(html(&headings).contains("<li><a href=\"#code-\">Code &lt;&amp;&gt;</a></li>"));
+        assert_eq!(html(&[]), "");
+    }
+}
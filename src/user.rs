@@ -1,7 +1,7 @@
 use bcrypt::BcryptResult;
 use chrono::{Duration, NaiveDateTime, Utc};
 use cookie::CookieJar;
-use diesel::prelude::*;
+use diesel::{prelude::*, Connection as _};
 use diesel_derive_enum::DbEnum;
 use futures::prelude::*;
 use gotham::{
@@ -18,14 +18,18 @@ use sha2::{Digest, Sha256};
 use std::{borrow::Cow, pin::Pin};
 
 use crate::{
+    article,
     db::{Connection, DbConnection, DieselResult},
+    metrics,
     schema::{groups, sessions, users},
 };
 
 const SESSION_LEN: usize = 24;
 const MIN_PASSWORD_LEN: usize = 8;
+/// Where avatars of users who haven't uploaded one come from, if enabled
+const GRAVATAR_URL: &str = "https://www.gravatar.com/avatar/";
 
-#[derive(Debug, Deserialize, Serialize, Queryable, Identifiable, Insertable)]
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Identifiable, Insertable)]
 pub struct User {
     /// The unique username/login
     pub id: String,
@@ -39,9 +43,17 @@ pub struct User {
     /// The user's email address
     pub email: String,
     /// The group the user belongs to
-    group: String,
+    pub group: String,
     /// Whether the password needs to be rehashed
     rehash: bool,
+    /// Markdown about the user, shown on their profile and with their articles
+    pub bio: String,
+    /// The user's own site
+    pub website: String,
+    /// Links to the user's profiles on other sites
+    pub links: Vec<String>,
+    /// Path of the user's avatar in the media directory, if they uploaded one
+    pub avatar: Option<String>,
 }
 
 impl User {
@@ -62,6 +74,33 @@ impl User {
         Ok(group.permissions.contains(&permission) || group.permissions.contains(&Permission::All))
     }
 
+    /// Gets the url of the user's avatar. Users who didn't upload one get their Gravatar if
+    /// `gravatar` is set.
+    pub fn avatar_url(&self, gravatar: bool) -> Option<String> {
+        match &self.avatar {
+            Some(avatar) => Some(format!("{}{}", crate::media::URL_PREFIX, avatar)),
+            None if gravatar && !self.email.trim().is_empty() => {
+                let email = self.email.trim().to_lowercase();
+                Some(format!(
+                    "{}{:x}?s=160&d=identicon",
+                    GRAVATAR_URL,
+                    Sha256::digest(email.as_bytes())
+                ))
+            }
+            None => None,
+        }
+    }
+
+    /// Gets the user's website and other links along with how to show them: without the scheme
+    /// and trailing slash.
+    pub fn labelled_links(&self) -> Vec<(&str, &str)> {
+        std::iter::once(&self.website)
+            .filter(|website| !website.is_empty())
+            .chain(&self.links)
+            .map(|link| (link.as_str(), link_label(link)))
+            .collect()
+    }
+
     /// Checks if a user can be edited by the given session
     pub fn editable(&self, session: Option<&Session>, conn: &Connection) -> DieselResult<bool> {
         if let Some(session) = session {
@@ -107,6 +146,47 @@ impl NewUser {
             email: self.email,
             group: self.group,
             rehash: false,
+            bio: String::new(),
+            website: String::new(),
+            links: Vec::new(),
+            avatar: None,
+        }
+    }
+}
+
+/// A user's account without their password, as stored in backups
+#[derive(Deserialize, Serialize, Queryable)]
+pub struct Account {
+    pub id: String,
+    pub name: String,
+    pub email: String,
+    pub group: String,
+    // Backups from before profiles were added don't have the rest
+    #[serde(default)]
+    pub bio: String,
+    #[serde(default)]
+    pub website: String,
+    #[serde(default)]
+    pub links: Vec<String>,
+    #[serde(default)]
+    pub avatar: Option<String>,
+}
+
+impl Account {
+    /// Converts the account into a user without a password, who can't log in until one is set.
+    pub fn into_user(self) -> User {
+        User {
+            id: self.id,
+            hash: String::new(),
+            salt: Vec::new(),
+            name: self.name,
+            email: self.email,
+            group: self.group,
+            rehash: false,
+            bio: self.bio,
+            website: self.website,
+            links: self.links,
+            avatar: self.avatar,
         }
     }
 }
@@ -116,6 +196,42 @@ impl NewUser {
 pub struct UserProfile {
     pub name: String,
     pub email: String,
+    #[serde(default)]
+    pub bio: String,
+    #[serde(default)]
+    pub website: String,
+    /// Entered one per line
+    #[serde(default, deserialize_with = "lines")]
+    pub links: Vec<String>,
+}
+
+/// Deserializes the non-empty lines of a text field.
+fn lines<'d, D: serde::Deserializer<'d>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    use serde::Deserialize as _;
+
+    let text = String::deserialize(deserializer)?;
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+/// Checks if a link in a profile is a plain web address.
+fn web_link(link: &str) -> bool {
+    (link.starts_with("https://") || link.starts_with("http://"))
+        && link.len() > "https://".len()
+        && !link.contains(|c: char| c.is_whitespace() || c == '"' || c == '<' || c == '>')
+}
+
+/// Shows a link without its scheme and trailing slash.
+fn link_label(link: &str) -> &str {
+    let label = link
+        .strip_prefix("https://")
+        .or_else(|| link.strip_prefix("http://"))
+        .unwrap_or(link);
+    label.strip_suffix('/').unwrap_or(label)
 }
 
 /// Login credentials
@@ -158,7 +274,10 @@ impl Login {
                     .execute(connection)?;
                 Ok(Some(session))
             }
-            _ => Ok(None),
+            _ => {
+                metrics::LOGIN_FAILURES.inc();
+                Ok(None)
+            }
         }
     }
 }
@@ -255,6 +374,7 @@ impl Middleware for SessionMiddleware {
         match put_session(&mut state) {
             Ok(()) => chain(state).boxed(),
             Err(e) => {
+                log::error!("Failed to check session: {}", e);
                 let response = create_response(
                     &state,
                     StatusCode::INTERNAL_SERVER_ERROR,
@@ -279,6 +399,10 @@ fn verify_old(key: &str, salt: &[u8], hash: &str) -> BcryptResult<bool> {
 }
 
 fn verify(key: &str, hash: &str) -> BcryptResult<bool> {
+    // Users restored from a backup have no password until one is set
+    if hash.is_empty() {
+        return Ok(false);
+    }
     bcrypt::verify(key, hash)
 }
 
@@ -301,6 +425,32 @@ pub fn create(connection: &Connection, user: NewUser) -> Result<usize, failure::
         .execute(connection)?)
 }
 
+/// Gets the accounts of all users
+pub fn accounts(connection: &Connection) -> DieselResult<Vec<Account>> {
+    use crate::schema::users::dsl;
+
+    dsl::users
+        .select((
+            dsl::id,
+            dsl::name,
+            dsl::email,
+            dsl::group,
+            dsl::bio,
+            dsl::website,
+            dsl::links,
+            dsl::avatar,
+        ))
+        .order(dsl::id)
+        .load(connection)
+}
+
+/// Creates a user from an account in a backup
+pub fn restore(connection: &Connection, account: Account) -> DieselResult<usize> {
+    diesel::insert_into(users::table)
+        .values(&account.into_user())
+        .execute(connection)
+}
+
 pub fn get(connection: &Connection, id: &str) -> DieselResult<User> {
     use crate::schema::users::dsl;
 
@@ -317,11 +467,29 @@ pub fn edit_profile(
     connection: &Connection,
     id: &str,
     profile: &UserProfile,
-) -> DieselResult<usize> {
+) -> Result<usize, failure::Error> {
     use crate::schema::users::dsl;
 
-    diesel::update(dsl::users.find(id))
+    if !profile.website.is_empty() && !web_link(&profile.website) {
+        return Err(failure::err_msg("Websites must be http or https links"));
+    }
+    if let Some(link) = profile.links.iter().find(|link| !web_link(link)) {
+        return Err(failure::format_err!(
+            "'{}' isn't an http or https link",
+            link
+        ));
+    }
+    Ok(diesel::update(dsl::users.find(id))
         .set(profile)
+        .execute(connection)?)
+}
+
+/// Sets or removes the avatar of a user, given its path in the media directory
+pub fn set_avatar(connection: &Connection, id: &str, avatar: Option<&str>) -> DieselResult<usize> {
+    use crate::schema::users::dsl;
+
+    diesel::update(dsl::users.find(id))
+        .set(dsl::avatar.eq(avatar))
         .execute(connection)
 }
 
@@ -339,14 +507,57 @@ pub fn change_password(
         return Ok(false);
     }
 
+    set_password(connection, id, &change.new)?;
+    Ok(true)
+}
+
+/// Sets a user's password without verifying the old one
+pub fn set_password(
+    connection: &Connection,
+    id: &str,
+    password: &str,
+) -> Result<(), failure::Error> {
+    use crate::schema::users::dsl;
+
+    if password.len() < MIN_PASSWORD_LEN {
+        return Err(failure::err_msg(format!(
+            "Passwords must be at least {} characters long",
+            MIN_PASSWORD_LEN
+        )));
+    }
+
     // Make new hash
-    let new_hash = hash(&change.new)?;
+    let new_hash = hash(password)?;
 
     // Write new values to database
-    diesel::update(dsl::users.find(id))
-        .set(dsl::hash.eq(&new_hash))
+    let updated = diesel::update(dsl::users.find(id))
+        .set((
+            dsl::hash.eq(&new_hash),
+            dsl::salt.eq(Vec::new()),
+            dsl::rehash.eq(false),
+        ))
         .execute(connection)?;
-    Ok(true)
+    if updated == 0 {
+        return Err(failure::err_msg(format!("No such user '{}'", id)));
+    }
+    Ok(())
+}
+
+/// Moves a user to another group. Their articles are rendered again, as the group decides which
+/// sanitization policy they get.
+pub fn set_group(connection: &Connection, id: &str, group: &str) -> Result<(), failure::Error> {
+    use crate::schema::users::dsl;
+
+    connection.transaction(|| {
+        let updated = diesel::update(dsl::users.find(id))
+            .set(dsl::group.eq(group))
+            .execute(connection)?;
+        if updated == 0 {
+            return Err(failure::err_msg(format!("No such user '{}'", id)));
+        }
+        article::clear_rendered_by(connection, id)?;
+        Ok(())
+    })
 }
 
 #[derive(Serialize, Deserialize)]
@@ -362,8 +573,6 @@ pub fn delete(
     id: &str,
     deletion: &UserDeletion,
 ) -> Result<(), failure::Error> {
-    use crate::schema::comments::dsl as c;
-    use crate::schema::sessions::dsl as s;
     use crate::schema::users::dsl as u;
 
     // Verify password
@@ -373,9 +582,19 @@ pub fn delete(
         return Err(failure::err_msg("Wrong password"));
     }
 
+    remove(connection, id, deletion.purge)
+}
+
+/// Deletes a user without verifying their password. Their comments are disowned and hidden, and
+/// their contents removed as well if `purge` is set.
+pub fn remove(connection: &Connection, id: &str, purge: bool) -> Result<(), failure::Error> {
+    use crate::schema::comments::dsl as c;
+    use crate::schema::sessions::dsl as s;
+    use crate::schema::users::dsl as u;
+
     // We have to make this variable because types can't be inferred for None
     let none_str: Option<String> = None;
-    if deletion.purge {
+    if purge {
         // purge contents of user's comments
         diesel::update(c::comments.filter(c::author.eq(&id)))
             .set((
@@ -398,21 +617,107 @@ pub fn delete(
     // Delete all sessions
     diesel::delete(s::sessions.filter(s::user.eq(&id))).execute(connection)?;
 
-    diesel::delete(u::users.find(id)).execute(connection)?;
+    if diesel::delete(u::users.find(id)).execute(connection)? == 0 {
+        return Err(failure::err_msg(format!("No such user '{}'", id)));
+    }
     Ok(())
 }
 
+/// Deletes expired sessions, or all sessions if `all` is set. Returns the number of deleted
+/// sessions.
+pub fn purge_sessions(connection: &Connection, all: bool) -> DieselResult<usize> {
+    use crate::schema::sessions::dsl;
+
+    if all {
+        diesel::delete(dsl::sessions).execute(connection)
+    } else {
+        diesel::delete(dsl::sessions.filter(dsl::expires.le(Utc::now().naive_utc())))
+            .execute(connection)
+    }
+}
+
+/// Counts unexpired sessions
+pub fn active_sessions(connection: &Connection) -> DieselResult<i64> {
+    use crate::schema::sessions::dsl;
+
+    dsl::sessions
+        .filter(dsl::expires.gt(Utc::now().naive_utc()))
+        .count()
+        .first(connection)
+}
+
 pub fn count(connection: &Connection) -> DieselResult<i64> {
     use crate::schema::users::dsl::*;
 
     users.count().first(connection)
 }
 
-#[derive(Clone, Debug, Queryable, Identifiable, Insertable)]
+#[derive(Clone, Debug, Queryable, Identifiable, Insertable, Deserialize, Serialize)]
 #[table_name = "groups"]
 pub struct Group {
-    id: String,
-    permissions: Vec<Permission>,
+    pub id: String,
+    pub permissions: Vec<Permission>,
+}
+
+/// Gets all groups
+pub fn groups(connection: &Connection) -> DieselResult<Vec<Group>> {
+    use crate::schema::groups::dsl;
+
+    dsl::groups.order(dsl::id).load(connection)
+}
+
+/// Creates a group, or replaces the permissions of an existing one
+pub fn restore_group(connection: &Connection, group: &Group) -> DieselResult<usize> {
+    use crate::schema::groups::dsl;
+
+    diesel::insert_into(groups::table)
+        .values(group)
+        .on_conflict(dsl::id)
+        .do_update()
+        .set(dsl::permissions.eq(&group.permissions))
+        .execute(connection)
+}
+
+/// Creates a group
+pub fn create_group(connection: &Connection, group: &Group) -> DieselResult<usize> {
+    diesel::insert_into(groups::table)
+        .values(group)
+        .execute(connection)
+}
+
+/// Deletes a group. Fails if any users belong to it.
+pub fn delete_group(connection: &Connection, id: &str) -> Result<(), failure::Error> {
+    use crate::schema::groups::dsl;
+    use crate::schema::users::dsl as u;
+
+    let members: i64 = u::users.filter(u::group.eq(id)).count().first(connection)?;
+    if members > 0 {
+        return Err(failure::err_msg(format!(
+            "Group '{}' still has {} members",
+            id, members
+        )));
+    }
+    if diesel::delete(dsl::groups.find(id)).execute(connection)? == 0 {
+        return Err(failure::err_msg(format!("No such group '{}'", id)));
+    }
+    Ok(())
+}
+
+/// Replaces the permissions of a group
+pub fn set_permissions(
+    connection: &Connection,
+    id: &str,
+    permissions: &[Permission],
+) -> Result<(), failure::Error> {
+    use crate::schema::groups::dsl;
+
+    let updated = diesel::update(dsl::groups.find(id))
+        .set(dsl::permissions.eq(permissions))
+        .execute(connection)?;
+    if updated == 0 {
+        return Err(failure::err_msg(format!("No such group '{}'", id)));
+    }
+    Ok(())
 }
 
 /*impl Queryable<groups::SqlType, diesel::pg::Pg> for Group {
@@ -434,7 +739,9 @@ where
 }*/
 
 /// Represents a type of action that a user or group can be allowed or denied permission for
-#[derive(Clone, Copy, Debug, PartialEq, Eq, DbEnum)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, DbEnum, clap::ValueEnum, Deserialize, Serialize)]
+#[value(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
 pub enum Permission {
     All,
 
@@ -506,3 +813,29 @@ impl std::fmt::Display for Permission {
         write!(f, "{}", string)
     }
 }*/
+
+#[cfg(test)]
+mod tests {
+    use super::{link_label, web_link, UserProfile};
+
+    #[test]
+    fn links() {
+        assert!(web_link("https://github.com/amanda"));
+        assert!(web_link("http://example.com"));
+        assert!(!web_link("javascript:alert(1)"));
+        assert!(!web_link("https://"));
+        assert!(!web_link("https://example.com/\"onclick"));
+        assert_eq!(link_label("https://example.com/"), "example.com");
+        assert_eq!(link_label("http://example.com/@me"), "example.com/@me");
+    }
+
+    #[test]
+    fn profile_form() {
+        let profile: UserProfile = serde_urlencoded::from_str(
+            "name=A&email=a%40b.c&links=https%3A%2F%2Fa.b%0D%0A%0D%0A+https%3A%2F%2Fc.d+",
+        )
+        .unwrap();
+        assert_eq!(profile.links, vec!["https://a.b", "https://c.d"]);
+        assert_eq!(profile.bio, "");
+    }
+}